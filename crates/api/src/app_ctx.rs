@@ -1,59 +1,176 @@
 use utils::config::Config;
 use database::Database;
+use database::item::DbItem;
+use database::job::DbJob;
+use database::object::Object;
+use database::repository::DbRepository;
+use database::upload::DbUpload;
+use crate::jobs::JobRunner;
 use crate::upload::{Upload, UploadState};
 use anyhow::Error;
-use rand::random;
+use rand::distributions::{Alphanumeric, DistString};
 use std::collections::HashMap;
 use std::fs;
 use std::sync::Arc;
+use thumbnailer::raw_image::RawImage;
+use thumbnailer::Thumbnail;
+use thumbnailer::video_preview::VideoPreview;
+use tracing::{info, warn};
+use types::item::Item;
+use types::job::{Job, JobKind};
+
+/// Upload sessions that were not updated since this delay are discarded (7 days)
+const UPLOAD_EXPIRATION_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Cached upload. The slot is inserted before the upload is restored from the database, so that concurrent requests
+/// wait for the same restoration without locking the other uploads.
+type UploadSlot = Arc<tokio::sync::OnceCell<Arc<tokio::sync::RwLock<Upload>>>>;
 
 pub struct AppCtx {
     pub config: Config,
     pub database: Database,
-    uploads: tokio::sync::RwLock<HashMap<String, Arc<tokio::sync::RwLock<Upload>>>>,
+    uploads: tokio::sync::RwLock<HashMap<String, UploadSlot>>,
+    jobs: JobRunner,
 }
 
 impl AppCtx {
     pub async fn new(config: Config) -> Result<Self, Error> {
         let database = Database::new(&config.backend_config).await?;
 
+        let expired = DbUpload::delete_expired(&database, UPLOAD_EXPIRATION_MS).await?;
+        if expired > 0 {
+            info!("Discarded {expired} expired upload sessions");
+        }
+
+        let indexed = DbItem::index_missing(&database).await?;
+        if indexed > 0 {
+            info!("Added {indexed} items to the search index");
+        }
+
+        let interrupted = DbJob::restart_interrupted(&database).await?;
+        if interrupted > 0 {
+            info!("Restarted {interrupted} interrupted jobs");
+        }
+
         Ok(Self {
+            jobs: JobRunner::new(config.backend_config.max_parallel_task),
             config,
             database,
             uploads: Default::default(),
         })
     }
 
+    /// Start processing the job queue in background
+    pub fn start_jobs(self: &Arc<Self>) {
+        let ctx = self.clone();
+        tokio::spawn(async move { ctx.jobs.run(ctx.clone()).await });
+    }
+
+    /// Add a job to the queue, or get the existing one
+    pub async fn queue_job(&self, mut job: Job) -> Result<Job, Error> {
+        DbJob::push(&self.database, &mut job).await?;
+        self.jobs.wake_up();
+        Ok(job)
+    }
+
+    /// Queue a failed job again if its cooldown is over
+    pub async fn retry_failed_job(&self, job: &Job) -> Result<Option<Job>, Error> {
+        let job = DbJob::retry_failed(&self.database, job.id()).await?;
+        if job.is_some() {
+            self.jobs.wake_up();
+        }
+        Ok(job)
+    }
+
     pub async fn add_upload(&self, mut upload: Upload) -> Result<String, Error> {
         let mut uploads = self.uploads.write().await;
 
         let mut id;
         loop {
-            id = random::<usize>().to_string();
-            if !uploads.contains_key(&id) {
+            id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+            if !uploads.contains_key(&id) && DbUpload::from_id(&self.database, &id).await.is_err() {
                 break;
             }
         }
 
-        upload.id = id.clone();
-        if upload.get_file_path().exists() {
-            fs::remove_file(upload.get_file_path())?;
+        upload.session_mut().set_id(id.clone());
+        if upload.get_file_path(&self.database).exists() {
+            fs::remove_file(upload.get_file_path(&self.database))?;
         }
-        uploads.insert(id.clone(), Arc::new(tokio::sync::RwLock::new(upload)));
+        DbUpload::push(upload.session_mut(), &self.database).await?;
+        uploads.insert(id.clone(), Arc::new(tokio::sync::OnceCell::new_with(Some(Arc::new(tokio::sync::RwLock::new(upload))))));
         Ok(id)
     }
 
+    /// Get a pending upload, restoring it from the database if it is not in cache (after a restart).
+    /// The partial data is hashed again on a blocking thread, without holding the lock of the upload map.
     pub async fn get_upload(&self, id: &String) -> Result<Arc<tokio::sync::RwLock<Upload>>, Error> {
-        match self.uploads.read().await.get(id) {
-            None => { Err(Error::msg("Upload not found")) }
-            Some(upload) => { Ok(upload.clone()) }
+        let slot = self.uploads.read().await.get(id).cloned();
+        let slot = match slot {
+            Some(slot) => { slot }
+            None => { self.uploads.write().await.entry(id.clone()).or_default().clone() }
+        };
+        let result = slot.get_or_try_init(|| async {
+            let session = DbUpload::from_id(&self.database, id).await?;
+            let path = DbUpload::data_path(session.id(), &self.database);
+            let upload = tokio::task::spawn_blocking(move || Upload::resume(&path, session)).await??;
+            Ok::<_, Error>(Arc::new(tokio::sync::RwLock::new(upload)))
+        }).await.cloned();
+        if result.is_err() {
+            // Unknown ids must not leave an empty slot behind
+            let mut uploads = self.uploads.write().await;
+            if uploads.get(id).is_some_and(|current| Arc::ptr_eq(current, &slot) && current.get().is_none()) {
+                uploads.remove(id);
+            }
         }
+        result
     }
 
     pub async fn finalize_upload(&self, id: &String, db: &Database) -> Result<UploadState, Error> {
-        let item = self.uploads.write().await.remove(id).ok_or(Error::msg("Upload not found"))?;
+        let item = self.uploads.write().await.remove(id).and_then(|slot| slot.get().cloned()).ok_or(Error::msg("Upload not found"))?;
         let mut upload = item.write().await;
-        upload.store(db).await?;
-        Ok(upload.get_state())
+        let item = upload.store(db).await?;
+        if let Err(err) = self.queue_previews(&item).await {
+            warn!("Failed to queue previews of {} : {err}", item.id());
+        }
+        let mut state = upload.get_state();
+        state.item = Some(item);
+        Ok(state)
+    }
+
+    /// Queue the generation of the thumbnail and of the video previews and transcodes of a new file
+    pub(crate) async fn queue_previews(&self, item: &Item) -> Result<(), Error> {
+        let file = match &item.file {
+            None => { return Ok(()) }
+            Some(file) => { file }
+        };
+        let mimetype = file.mimetype.plain()?;
+        let size = self.config.backend_config.thumbnail_size;
+        if Thumbnail::is_supported(&mimetype) && !Object::thumbnail_path(&file.object, size, &self.database).exists() {
+            self.queue_job(Job::thumbnail(file.object.clone(), file.mimetype.clone(), size)).await?;
+        }
+        if RawImage::is_supported(&mimetype) && !Object::rendition_path(&file.object, &self.database).exists() {
+            self.queue_job(Job::new(JobKind::Rendition, file.object.clone(), file.mimetype.clone())).await?;
+        }
+        if VideoPreview::is_supported(&mimetype) {
+            if !Object::video_sprites_vtt_path(&file.object, &self.database).exists() {
+                self.queue_job(Job::new(JobKind::VideoSprites, file.object.clone(), file.mimetype.clone())).await?;
+            }
+            if !Object::video_preview_path(&file.object, &self.database).exists() {
+                self.queue_job(Job::new(JobKind::VideoPreview, file.object.clone(), file.mimetype.clone())).await?;
+            }
+            if !Object::hls_path(&file.object, &self.database).exists() && DbRepository::from_id(&self.database, &item.repository).await?.hls_streaming {
+                self.queue_job(Job::new(JobKind::Hls, file.object.clone(), file.mimetype.clone())).await?;
+            }
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Abort a pending upload and discard the received data
+    pub async fn cancel_upload(&self, id: &String) -> Result<(), Error> {
+        let upload = self.get_upload(id).await?;
+        self.uploads.write().await.remove(id);
+        let upload = upload.write().await;
+        DbUpload::delete(upload.session(), &self.database).await
+    }
+}
@@ -0,0 +1,80 @@
+use crate::app_ctx::AppCtx;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use database::async_tar::AsyncDirectoryTar;
+use database::async_zip::AsyncDirectoryZip;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+use types::item::Item;
+use utils::server_error::ServerError;
+
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarZst,
+}
+
+/// Query parameters of archive downloads
+#[derive(Deserialize, Default)]
+pub struct ArchiveOptions {
+    compress: Option<String>,
+    format: Option<String>,
+}
+
+impl ArchiveOptions {
+    pub fn compression(&self) -> bool {
+        matches!(self.compress.as_deref(), Some("1") | Some("true"))
+    }
+
+    pub fn format(&self) -> Result<ArchiveFormat, ServerError> {
+        match self.format.as_deref() {
+            None | Some("zip") => { Ok(ArchiveFormat::Zip) }
+            Some("tar") => { Ok(ArchiveFormat::Tar) }
+            Some("tar.zst") => { Ok(ArchiveFormat::TarZst) }
+            Some(format) => { Err(ServerError::msg(StatusCode::BAD_REQUEST, format!("Unsupported archive format '{format}'"))) }
+        }
+    }
+}
+
+/// Stream an archive of the given items. Content-Length is only known for uncompressed archives.
+pub async fn archive_response(ctx: Arc<AppCtx>, options: &ArchiveOptions, items: Vec<Item>, name: &str) -> Result<Response, ServerError> {
+    let (w, r) = tokio::io::duplex(4096);
+    let (size, content_type, extension) = match options.format()? {
+        ArchiveFormat::Zip => {
+            let mut zip = AsyncDirectoryZip::new();
+            zip.set_compression(options.compression());
+            for item in items {
+                zip.push_item(&ctx.database, item).await?;
+            }
+            let size = zip.size()?;
+            tokio::spawn(async move {
+                zip.finalize(&ctx.database, w).await
+            });
+            (size, "application/zip", "zip")
+        }
+        ArchiveFormat::Tar | ArchiveFormat::TarZst => {
+            let zstd = matches!(options.format()?, ArchiveFormat::TarZst);
+            let mut tar = AsyncDirectoryTar::new();
+            tar.set_zstd(zstd);
+            for item in items {
+                tar.push_item(&ctx.database, item).await?;
+            }
+            let size = tar.size()?;
+            tokio::spawn(async move {
+                tar.finalize(&ctx.database, w).await
+            });
+            if zstd { (size, "application/zstd", "tar.zst") } else { (size, "application/x-tar", "tar") }
+        }
+    };
+
+    let body = Body::from_stream(ReaderStream::new(r));
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Some(size) = size {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+    }
+    headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_str(&format!("attachment; filename=\"{name}.{extension}\""))?);
+    Ok((headers, body).into_response())
+}
@@ -0,0 +1,201 @@
+use crate::app_ctx::AppCtx;
+use crate::route_item::VIDEO_SPRITES_IMAGE_URL;
+use anyhow::Error;
+use database::conversion::DbConversion;
+use database::job::DbJob;
+use database::object::Object;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use thumbnailer::converter::Converter;
+use thumbnailer::hls::HlsTranscoder;
+use thumbnailer::raw_image::RawImage;
+use thumbnailer::Thumbnail;
+use thumbnailer::video_preview::VideoPreview;
+use tokio::sync::{watch, Notify, Semaphore};
+use tracing::{error, warn};
+use types::conversion::{ConversionQuality, ConversionStatus, ConversionTarget};
+use types::database_ids::ConversionId;
+use types::job::{Job, JobKind, JobStatus};
+
+/// A failed job is retried this many times before being abandoned
+const MAX_ATTEMPTS: i32 = 5;
+/// Delay before the first retry, doubled after each failure
+const RETRY_DELAY_MS: i64 = 10 * 1000;
+/// A job abandoned after `MAX_ATTEMPTS` failures is queued again when its result is requested after this delay (1 hour)
+const FAILED_JOB_COOLDOWN_MS: i64 = 60 * 60 * 1000;
+/// The queue is checked at least this often, in case another server instance added jobs
+const POLL_INTERVAL_MS: i64 = 60 * 1000;
+
+/// Runs the queued jobs in background, with at most `max_parallel_task` jobs at the same time
+pub struct JobRunner {
+    wake_up: Notify,
+    max_parallel_task: usize,
+}
+
+impl JobRunner {
+    pub fn new(max_parallel_task: usize) -> Self {
+        let max_parallel_task = if max_parallel_task == 0 {
+            std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1)
+        } else {
+            max_parallel_task
+        };
+        Self { wake_up: Notify::new(), max_parallel_task }
+    }
+
+    /// Check the queue for new jobs
+    pub fn wake_up(&self) {
+        self.wake_up.notify_one();
+    }
+
+    pub async fn run(&self, ctx: Arc<AppCtx>) {
+        let slots = Arc::new(Semaphore::new(self.max_parallel_task));
+        loop {
+            let slot = slots.clone().acquire_owned().await.expect("Job slots are never closed");
+            let wait = match DbJob::start_next(&ctx.database).await {
+                Ok(Some(job)) => {
+                    let ctx = ctx.clone();
+                    tokio::spawn(async move {
+                        Self::execute(&ctx, job).await;
+                        drop(slot);
+                    });
+                    continue;
+                }
+                Ok(None) => {
+                    match DbJob::next_attempt(&ctx.database).await {
+                        Ok(Some(next_attempt)) => { (next_attempt - DbJob::now().unwrap_or_default()).clamp(0, POLL_INTERVAL_MS) }
+                        Ok(None) => { POLL_INTERVAL_MS }
+                        Err(err) => {
+                            error!("Failed to read job queue : {err}");
+                            POLL_INTERVAL_MS
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to read job queue : {err}");
+                    POLL_INTERVAL_MS
+                }
+            };
+            drop(slot);
+            let _ = tokio::time::timeout(Duration::from_millis(wait as u64), self.wake_up.notified()).await;
+        }
+    }
+
+    async fn execute(ctx: &AppCtx, job: Job) {
+        let result = match Self::process(ctx, &job).await {
+            Ok(_) => { DbJob::complete(&ctx.database, job.id()).await }
+            Err(err) => {
+                let now = DbJob::now().unwrap_or_default();
+                let (status, next_attempt) = if job.attempts < MAX_ATTEMPTS {
+                    warn!("Job {} failed (attempt {}/{MAX_ATTEMPTS}) : {err}", job.id(), job.attempts);
+                    (JobStatus::Pending, now + RETRY_DELAY_MS * 2i64.pow(job.attempts.max(1) as u32 - 1))
+                } else {
+                    error!("Job {} failed after {MAX_ATTEMPTS} attempts : {err}", job.id());
+                    (JobStatus::Failed, now + FAILED_JOB_COOLDOWN_MS)
+                };
+                if let Some(conversion) = &job.conversion {
+                    let status = if status == JobStatus::Pending { ConversionStatus::Pending } else { ConversionStatus::Failed };
+                    if let Err(err) = DbConversion::set_status(&ctx.database, conversion, status, Some(&err.to_string())).await {
+                        error!("Failed to update conversion {conversion} : {err}");
+                    }
+                }
+                DbJob::fail(&ctx.database, job.id(), &err.to_string(), status, next_attempt).await
+            }
+        };
+        if let Err(err) = result {
+            error!("Failed to update job {} : {err}", job.id());
+        }
+    }
+
+    async fn process(ctx: &AppCtx, job: &Job) -> Result<(), Error> {
+        match job.kind {
+            JobKind::Thumbnail => {
+                let input = Object::data_path(&job.object, &ctx.database);
+                let output = Object::thumbnail_path(&job.object, job.size as usize, &ctx.database);
+                let mimetype = job.mimetype.plain()?;
+                let size = job.size as u32;
+                tokio::task::spawn_blocking(move || Thumbnail::create(&input, &output, &mimetype, size)).await??;
+            }
+            JobKind::VideoSprites => {
+                let input = Object::data_path(&job.object, &ctx.database);
+                let sprites = Object::video_sprites_path(&job.object, &ctx.database);
+                let vtt = Object::video_sprites_vtt_path(&job.object, &ctx.database);
+                tokio::task::spawn_blocking(move || VideoPreview::sprite_sheet(&input, &sprites, &vtt, VIDEO_SPRITES_IMAGE_URL)).await??;
+            }
+            JobKind::VideoPreview => {
+                let input = Object::data_path(&job.object, &ctx.database);
+                let output = Object::video_preview_path(&job.object, &ctx.database);
+                tokio::task::spawn_blocking(move || VideoPreview::animated_preview(&input, &output)).await??;
+            }
+            JobKind::Hls => {
+                let input = Object::data_path(&job.object, &ctx.database);
+                let output = Object::hls_path(&job.object, &ctx.database);
+                tokio::task::spawn_blocking(move || HlsTranscoder::transcode(&input, &output)).await??;
+            }
+            JobKind::Rendition => {
+                let input = Object::data_path(&job.object, &ctx.database);
+                let output = Object::rendition_path(&job.object, &ctx.database);
+                tokio::task::spawn_blocking(move || RawImage::jpeg_rendition(&input, &output)).await??;
+            }
+            JobKind::Conversion => {
+                let conversion = job.conversion.as_ref().ok_or(Error::msg("Missing conversion of conversion job"))?;
+                let output = ctx.database.upload_storage_path.join(format!("conversion_{conversion}"));
+                let result = Self::convert(ctx, conversion, &output).await;
+                if output.exists() {
+                    let _ = fs::remove_file(&output);
+                }
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert the file to `output`, then store it as a new object that waits for the confirmation of the user
+    async fn convert(ctx: &AppCtx, id: &ConversionId, output: &Path) -> Result<(), Error> {
+        let conversion = DbConversion::from_id(&ctx.database, id).await?;
+        DbConversion::start(&ctx.database, id).await?;
+        fs::create_dir_all(&ctx.database.upload_storage_path)?;
+
+        let input = Object::data_path(&conversion.source, &ctx.database);
+        let output_path = output.to_path_buf();
+        let (crf, image_quality) = match conversion.quality {
+            ConversionQuality::Low => { (42, 60) }
+            ConversionQuality::Medium => { (34, 75) }
+            ConversionQuality::High => { (26, 88) }
+        };
+        let (sender, mut receiver) = watch::channel(0f32);
+        let mut task = tokio::task::spawn_blocking(move || match conversion.target {
+            ConversionTarget::Av1 => { Converter::video_to_av1(&input, &output_path, crf, |progress| { sender.send_replace(progress); }) }
+            ConversionTarget::Webp => { Converter::recompress_image(&input, &output_path, "webp", image_quality) }
+            ConversionTarget::Jpeg => { Converter::recompress_image(&input, &output_path, "jpeg", image_quality) }
+            ConversionTarget::Avif => { Converter::recompress_image(&input, &output_path, "avif", image_quality) }
+        });
+        loop {
+            tokio::select! {
+                result = &mut task => {
+                    result??;
+                    break;
+                }
+                Ok(()) = receiver.changed() => {
+                    let progress = *receiver.borrow_and_update();
+                    if let Err(err) = DbConversion::set_progress(&ctx.database, id, progress).await {
+                        warn!("Failed to update progress of conversion {id} : {err}");
+                    }
+                }
+            }
+        }
+
+        let hashed_path = output.to_path_buf();
+        let (hash, size) = tokio::task::spawn_blocking(move || -> Result<(String, i64), Error> {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(fs::File::open(&hashed_path)?)?;
+            Ok((hasher.finalize().to_string(), fs::metadata(&hashed_path)?.len() as i64))
+        }).await??;
+
+        let transaction = ctx.database.begin().await?;
+        let object = Object::store(&transaction, output, &hash).await?;
+        DbConversion::set_result(&transaction, id, object.id(), size).await?;
+        transaction.commit().await
+    }
+}
@@ -9,6 +9,7 @@ use types::item::Item;
 use types::repository::Repository;
 use types::user::User;
 use crate::app_ctx::AppCtx;
+use crate::route_album::AlbumRoutes;
 use crate::route_item::ItemRoutes;
 use crate::route_repository::RepositoryRoutes;
 use crate::route_user::UserRoutes;
@@ -16,14 +17,17 @@ use crate::route_user::UserRoutes;
 mod route_repository;
 mod route_item;
 mod route_user;
+mod route_album;
 mod permissions;
 mod upload;
+mod archive;
+mod jobs;
 pub mod app_ctx;
 
 #[macro_export]
 macro_rules! get_connected_user {
     ($request:expr, $prop:ident, $body:expr, $or_else:expr) => {{
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.connected_user().await.as_ref() {
             {$body}
         } else {
@@ -32,7 +36,7 @@ macro_rules! get_connected_user {
     }};
 
     ($request:expr, $prop:ident, $body:expr) => (
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.connected_user().await.as_ref() {
             $body
         }
@@ -42,7 +46,7 @@ macro_rules! get_connected_user {
 #[macro_export]
 macro_rules! require_connected_user {
     ($request:expr) => {{
-        crate::get_connected_user!($request, connected_user, {
+        $crate::get_connected_user!($request, connected_user, {
             connected_user.clone()
         }, {
             return Err(ServerError::msg(axum::http::StatusCode::UNAUTHORIZED, "Not connected"))
@@ -54,7 +58,7 @@ macro_rules! require_connected_user {
 #[macro_export]
 macro_rules! get_display_repository {
     ($request:expr, $prop:ident, $body:expr, $or_else:expr) => {{
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_repository().await.as_ref() {
             {$body}
         } else {
@@ -63,7 +67,7 @@ macro_rules! get_display_repository {
     }};
 
     ($request:expr, $prop:ident, $body:expr) => (
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_repository().await.as_ref() {
             $body
         }
@@ -73,7 +77,7 @@ macro_rules! get_display_repository {
 #[macro_export]
 macro_rules! get_display_user {
     ($request:expr, $prop:ident, $body:expr, $or_else:expr) => {{
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_user().await.as_ref() {
             {$body}
         } else {
@@ -82,7 +86,7 @@ macro_rules! get_display_user {
     }};
 
     ($request:expr, $prop:ident, $body:expr) => (
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_user().await.as_ref() {
             $body
         }
@@ -92,7 +96,7 @@ macro_rules! get_display_user {
 #[macro_export]
 macro_rules! get_display_item {
     ($request:expr, $prop:ident, $body:expr, $or_else:expr) => {{
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_item().await.as_ref() {
             {$body}
         } else {
@@ -101,7 +105,7 @@ macro_rules! get_display_item {
     }};
 
     ($request:expr, $prop:ident, $body:expr) => (
-        let req_ctx = $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap();
+        let req_ctx = $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap();
         if let Some($prop) = req_ctx.display_item().await.as_ref() {
             $body
         }
@@ -111,7 +115,7 @@ macro_rules! get_display_item {
 #[macro_export]
 macro_rules! require_display_repository {
     ($request:expr) => {{
-        crate::get_display_repository!($request, display_directory, {
+        $crate::get_display_repository!($request, display_directory, {
             display_directory.clone()
         }, {
             return Err(ServerError::msg(StatusCode::UNAUTHORIZED, "Invalid repository"))
@@ -122,7 +126,7 @@ macro_rules! require_display_repository {
 #[macro_export]
 macro_rules! get_action {
     ($request:expr) => (
-        $request.extensions().get::<std::sync::Arc<crate::RequestContext>>().unwrap().action().await
+        $request.extensions().get::<std::sync::Arc<$crate::RequestContext>>().unwrap().action().await
     );
 }
 
@@ -136,31 +140,31 @@ pub struct RequestContext {
 }
 
 impl RequestContext {
-    pub async fn connected_user(&self) -> tokio::sync::RwLockReadGuard<Option<User>> {
+    pub async fn connected_user(&self) -> tokio::sync::RwLockReadGuard<'_, Option<User>> {
         self.connected_user.read().await
     }
-    pub async fn connected_user_mut(&self) -> tokio::sync::RwLockWriteGuard<Option<User>> {
+    pub async fn connected_user_mut(&self) -> tokio::sync::RwLockWriteGuard<'_, Option<User>> {
         self.connected_user.write().await
     }
 
-    pub async fn display_user(&self) -> tokio::sync::RwLockReadGuard<Option<User>> {
+    pub async fn display_user(&self) -> tokio::sync::RwLockReadGuard<'_, Option<User>> {
         self.display_user.read().await
     }
-    pub async fn display_user_mut(&self) -> tokio::sync::RwLockWriteGuard<Option<User>> {
+    pub async fn display_user_mut(&self) -> tokio::sync::RwLockWriteGuard<'_, Option<User>> {
         self.display_user.write().await
     }
 
-    pub async fn display_repository(&self) -> tokio::sync::RwLockReadGuard<Option<Repository>> {
+    pub async fn display_repository(&self) -> tokio::sync::RwLockReadGuard<'_, Option<Repository>> {
         self.display_repository.read().await
     }
-    pub async fn display_repository_mut(&self) -> tokio::sync::RwLockWriteGuard<Option<Repository>> {
+    pub async fn display_repository_mut(&self) -> tokio::sync::RwLockWriteGuard<'_, Option<Repository>> {
         self.display_repository.write().await
     }
 
-    pub async fn display_item(&self) -> tokio::sync::RwLockReadGuard<Option<Item>> {
+    pub async fn display_item(&self) -> tokio::sync::RwLockReadGuard<'_, Option<Item>> {
         self.display_item.read().await
     }
-    pub async fn display_item_mut(&self) -> tokio::sync::RwLockWriteGuard<Option<Item>> {
+    pub async fn display_item_mut(&self) -> tokio::sync::RwLockWriteGuard<'_, Option<Item>> {
         self.display_item.write().await
     }
 
@@ -177,6 +181,7 @@ impl RootRoutes {
             .nest("/repository/", RepositoryRoutes::create(ctx)?)
             .nest("/user/", UserRoutes::router(ctx)?)
             .nest("/item/", ItemRoutes::create(ctx)?)
+            .nest("/album/", AlbumRoutes::create(ctx)?)
             .fallback(handler_404);
         Ok(router)
     }
@@ -1,3 +1,4 @@
+use database::album::DbAlbum;
 use database::item::{DbItem, Trash};
 use database::subscription::{Subscription, SubscriptionAccessType};
 use database::Database;
@@ -7,7 +8,8 @@ use axum::extract::Request;
 use axum::http::StatusCode;
 use std::sync::Arc;
 use database::repository::DbRepository;
-use types::database_ids::{ItemId, RepositoryId};
+use types::album::Album;
+use types::database_ids::{AlbumId, ItemId, RepositoryId};
 use types::repository::RepositoryStatus;
 
 pub struct Permissions {
@@ -31,10 +33,7 @@ impl PermissionResult {
     }
 
     pub fn granted(&self) -> bool {
-        match self {
-            PermissionResult::Granted => { true }
-            _ => { false }
-        }
+        matches!(self, PermissionResult::Granted)
     }
 }
 
@@ -55,9 +54,7 @@ impl Permissions {
         }
 
         Ok(if let Some(user) = &*self.request_context.connected_user().await {
-            if repository.owner == *user.id() {
-                PermissionResult::Granted
-            } else if Subscription::find(db, user.id(), repository_id).await.is_ok() {
+            if repository.owner == *user.id() || Subscription::find(db, user.id(), repository_id).await.is_ok() {
                 PermissionResult::Granted
             } else {
                 PermissionResult::Denied
@@ -142,4 +139,34 @@ impl Permissions {
             PermissionResult::Denied
         })
     }
-}
\ No newline at end of file
+
+    pub async fn view_album(&self, db: &Database, album_id: &AlbumId) -> Result<PermissionResult, ServerError> {
+        let album = Self::album(db, album_id).await?;
+        self.view_repository(db, &album.repository).await
+    }
+
+    /// Albums can be edited by the moderators of the repository, or by their owner if they can still upload to it
+    pub async fn edit_album(&self, db: &Database, album_id: &AlbumId) -> Result<PermissionResult, ServerError> {
+        let album = Self::album(db, album_id).await?;
+        if self.edit_repository(db, &album.repository).await?.granted() {
+            return Ok(PermissionResult::Granted);
+        }
+        if !self.upload_to_repository(db, &album.repository).await?.granted() {
+            return Ok(PermissionResult::Denied);
+        }
+        Ok(if let Some(user) = &*self.request_context.connected_user().await {
+            if album.owner == *user.id() {
+                PermissionResult::Granted
+            } else {
+                PermissionResult::Denied
+            }
+        } else {
+            PermissionResult::Denied
+        })
+    }
+
+    /// Unknown albums are reported as not found rather than as a server error
+    async fn album(db: &Database, album_id: &AlbumId) -> Result<Album, ServerError> {
+        DbAlbum::find(db, album_id).await?.ok_or(ServerError::msg(StatusCode::NOT_FOUND, "Album not found"))
+    }
+}
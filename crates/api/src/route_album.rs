@@ -0,0 +1,185 @@
+use crate::app_ctx::AppCtx;
+use crate::permissions::Permissions;
+use crate::require_connected_user;
+use anyhow::Error;
+use axum::extract::{FromRequest, Path, Request, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use database::album::DbAlbum;
+use database::item::{DbItem, Trash};
+use serde::Deserialize;
+use std::sync::Arc;
+use types::album::Album;
+use types::database_ids::{AlbumId, DatabaseId, ItemId, RepositoryId};
+use types::enc_string::EncString;
+use utils::server_error::ServerError;
+
+pub struct AlbumRoutes {}
+
+impl AlbumRoutes {
+    pub fn create(ctx: &Arc<AppCtx>) -> Result<Router, Error> {
+        let router = Router::new()
+            .route("/find/", post(find_albums).with_state(ctx.clone()))
+            .route("/repository/:id/", get(repository_albums).with_state(ctx.clone()))
+            .route("/content/:id/", get(content).with_state(ctx.clone()))
+            .route("/create/", post(create_albums).with_state(ctx.clone()))
+            .route("/update/", post(update).with_state(ctx.clone()))
+            .route("/delete/", post(delete).with_state(ctx.clone()))
+            .route("/add/", post(add_items).with_state(ctx.clone()))
+            .route("/remove/", post(remove_items).with_state(ctx.clone()))
+            .route("/reorder/", post(reorder).with_state(ctx.clone()));
+        Ok(router)
+    }
+}
+
+#[derive(Deserialize)]
+struct AlbumItems {
+    album: AlbumId,
+    items: Vec<ItemId>,
+}
+
+/// Get albums by id
+async fn find_albums(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<AlbumId>>::from_request(request, &ctx).await.map_err(|err| { Error::msg(format!("Invalid body, {err} : expected Vec<AlbumId>")) })?;
+    let mut albums = vec![];
+    for album in &json.0 {
+        // Unknown albums are skipped, like the albums the user cannot see
+        let album = match DbAlbum::find(&ctx.database, album).await? {
+            None => { continue }
+            Some(album) => { album }
+        };
+        if permissions.view_repository(&ctx.database, &album.repository).await?.granted() {
+            albums.push(album);
+        }
+    }
+    Ok(Json(albums))
+}
+
+/// Get all albums of a repository
+async fn repository_albums(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let repository = RepositoryId::from(id);
+    let permissions = Permissions::new(&request)?;
+    permissions.view_repository(&ctx.database, &repository).await?.require()?;
+    Ok(Json(DbAlbum::from_repository(&ctx.database, &repository).await?))
+}
+
+/// Get the items of an album in their order
+async fn content(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let album = AlbumId::from(id);
+    let permissions = Permissions::new(&request)?;
+    permissions.view_album(&ctx.database, &album).await?.require()?;
+    Ok(Json(DbAlbum::items(&ctx.database, &album).await?))
+}
+
+/// Create new albums
+async fn create_albums(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+
+    #[derive(Deserialize)]
+    struct Data {
+        repository: RepositoryId,
+        name: EncString,
+        description: Option<EncString>,
+    }
+
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<Data>>::from_request(request, &ctx).await?;
+    let mut albums = vec![];
+    for data in json.0 {
+        permissions.upload_to_repository(&ctx.database, &data.repository).await?.require()?;
+        if data.name.is_empty() {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Album name cannot be empty"));
+        }
+        let mut album = Album::new(data.repository, user.id().clone(), data.name, data.description);
+        DbAlbum::push(&ctx.database, &mut album).await?;
+        albums.push(album);
+    }
+    Ok(Json(albums))
+}
+
+/// Rename albums or change their cover
+async fn update(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    require_connected_user!(request);
+
+    #[derive(Deserialize)]
+    struct Data {
+        id: AlbumId,
+        name: EncString,
+        description: Option<EncString>,
+        cover: Option<ItemId>,
+    }
+
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<Data>>::from_request(request, &ctx).await?;
+    let mut albums = vec![];
+    for data in json.0 {
+        permissions.edit_album(&ctx.database, &data.id).await?.require()?;
+        if data.name.is_empty() {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Album name cannot be empty"));
+        }
+        albums.push(DbAlbum::update(&ctx.database, &data.id, &data.name, &data.description, &data.cover).await?);
+    }
+    Ok(Json(albums))
+}
+
+/// Delete albums. Their files are kept.
+async fn delete(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<AlbumId>>::from_request(request, &ctx).await?;
+    let mut deleted = vec![];
+    for album in json.0 {
+        // Unknown albums are skipped, like the albums the user cannot edit
+        if DbAlbum::find(&ctx.database, &album).await?.is_none() {
+            continue;
+        }
+        if permissions.edit_album(&ctx.database, &album).await?.granted() {
+            DbAlbum::delete(&ctx.database, &album).await?;
+            deleted.push(album);
+        }
+    }
+    Ok(Json(deleted))
+}
+
+/// Append files of the repository of the album at its end
+async fn add_items(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<AlbumItems>::from_request(request, &ctx).await?.0;
+    permissions.edit_album(&ctx.database, &json.album).await?.require()?;
+    let album = DbAlbum::from_id(&ctx.database, &json.album).await?;
+    for item in &json.items {
+        let item = DbItem::from_id(&ctx.database, item, Trash::No).await?;
+        if item.repository != album.repository {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Albums can only contain files of their repository"));
+        }
+        if item.file.is_none() {
+            return Err(ServerError::msg(StatusCode::BAD_REQUEST, "Albums can only contain files"));
+        }
+    }
+    DbAlbum::add_items(&ctx.database, album.id(), json.items).await?;
+    Ok(Json(DbAlbum::from_id(&ctx.database, album.id()).await?))
+}
+
+/// Remove files from an album. The files themselves are kept.
+async fn remove_items(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<AlbumItems>::from_request(request, &ctx).await?.0;
+    permissions.edit_album(&ctx.database, &json.album).await?.require()?;
+    DbAlbum::remove_items(&ctx.database, &json.album, json.items).await?;
+    Ok(Json(DbAlbum::from_id(&ctx.database, &json.album).await?))
+}
+
+/// Change the order of the items of an album : the given items are placed first, in this order
+async fn reorder(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<AlbumItems>::from_request(request, &ctx).await?.0;
+    permissions.edit_album(&ctx.database, &json.album).await?.require()?;
+    DbAlbum::reorder(&ctx.database, &json.album, json.items).await?;
+    Ok(Json(DbAlbum::items(&ctx.database, &json.album).await?))
+}
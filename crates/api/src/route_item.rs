@@ -1,27 +1,39 @@
+use std::collections::HashSet;
 use std::str::FromStr;
 use crate::app_ctx::AppCtx;
-use database::item::{DbItem, ItemSearchData, Trash};
+use database::conversion::DbConversion;
+use database::item::{ConflictPolicy, DbItem, ItemSearchData, ListingOptions, Trash};
+use database::file_version::DbFileVersion;
+use database::job::DbJob;
 use database::object::Object;
+use database::repository::DbRepository;
 use crate::{require_connected_user};
-use database::async_zip::AsyncDirectoryZip;
 use types::enc_string::EncString;
 use crate::permissions::Permissions;
+use utils::file_response::FileResponse;
 use utils::server_error::ServerError;
+use thumbnailer::hls::HLS_MASTER_PLAYLIST;
+use thumbnailer::raw_image::RawImage;
 use thumbnailer::Thumbnail;
+use thumbnailer::video_preview::VideoPreview;
+use crate::archive::{archive_response, ArchiveOptions};
 use crate::upload::Upload;
 use anyhow::Error;
 use axum::body::Body;
-use axum::extract::{FromRequest, Path, Request, State};
-use axum::http::{header, StatusCode};
-use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::extract::{FromRequest, OriginalUri, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, head, post};
 use axum::{Json, Router};
 use regex::Regex;
 use serde::Deserialize;
 use std::sync::Arc;
-use tokio_util::io::ReaderStream;
-use types::database_ids::{DatabaseId, ItemId};
-use types::item::{CreateDirectoryParams, DirectoryData, Item};
+use types::conversion::{Conversion, ConversionOutput, ConversionQuality, ConversionTarget};
+use types::database_ids::{ConversionId, DatabaseId, FileVersionId, ItemId, RepositoryId};
+use types::item::{CreateDirectoryParams, DirectoryData, FileData, Item};
+use types::job::{Job, JobKind, JobStatus};
+use types::user::User;
+use tracing::warn;
 
 pub struct ItemRoutes {}
 
@@ -35,11 +47,27 @@ impl ItemRoutes {
             .route("/new-directory/", post(new_directory).with_state(ctx.clone()))
             .route("/directory-content/", post(directory_content).with_state(ctx.clone()))
             .route("/thumbnail/:id/", get(thumbnail).with_state(ctx.clone()))
-            .route("/send/", post(send).with_state(ctx.clone()))
+            .route("/video-sprites/:id/", get(video_sprites).with_state(ctx.clone()))
+            .route("/video-sprites/:id/image/", get(video_sprites_image).with_state(ctx.clone()))
+            .route("/video-preview/:id/", get(video_preview).with_state(ctx.clone()))
+            .route("/hls/:id/:file", get(hls).with_state(ctx.clone()))
+            .route("/rendition/:id/", get(rendition).with_state(ctx.clone()))
+            .route("/send/", post(send).options(tus_options).with_state(ctx.clone()))
+            .route("/send/:id/", head(tus_head).patch(tus_patch).delete(tus_delete).with_state(ctx.clone()))
             .route("/get/:path/", get(download).with_state(ctx.clone()))
             .route("/download/:ids/", get(download_multi).with_state(ctx.clone()))
             .route("/preview/:path/", get(download).with_state(ctx.clone()))
             .route("/update/", post(edit).with_state(ctx.clone()))
+            .route("/move/", post(move_items).with_state(ctx.clone()))
+            .route("/copy/", post(copy_items).with_state(ctx.clone()))
+            .route("/versions/:id/", get(versions).with_state(ctx.clone()))
+            .route("/versions/get/:id/", get(download_version).with_state(ctx.clone()))
+            .route("/versions/restore/", post(restore_versions).with_state(ctx.clone()))
+            .route("/convert/", post(convert).with_state(ctx.clone()))
+            .route("/conversions/", get(conversions).with_state(ctx.clone()))
+            .route("/conversions/get/:id/", get(download_conversion).with_state(ctx.clone()))
+            .route("/conversions/confirm/", post(confirm_conversions).with_state(ctx.clone()))
+            .route("/conversions/cancel/", post(cancel_conversions).with_state(ctx.clone()))
             .route("/search/", post(search).with_state(ctx.clone()))
         )
     }
@@ -58,17 +86,17 @@ async fn find_items(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<
     Ok(Json(items))
 }
 
-/// Get items inside a given directory
-async fn directory_content(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+/// Get items inside the given directories, one page at a time
+async fn directory_content(State(ctx): State<Arc<AppCtx>>, Query(options): Query<ListingOptions>, request: Request) -> Result<impl IntoResponse, ServerError> {
     let permissions = Permissions::new(&request)?;
     let json = Json::<Vec<ItemId>>::from_request(request, &ctx).await?;
-    let mut items = vec![];
+    let mut directories = vec![];
     for directory in json.0 {
         if permissions.view_item(&ctx.database, &directory).await?.granted() {
-            items.append(&mut DbItem::from_parent(&ctx.database, &directory, Trash::Both).await?);
+            directories.push(directory);
         }
     }
-    Ok(Json(items))
+    Ok(Json(DbItem::from_parents_paged(&ctx.database, directories, Trash::Both, &options).await?))
 }
 
 /// Create a directory
@@ -77,6 +105,7 @@ async fn new_directory(State(ctx): State<Arc<AppCtx>>, request: Request) -> Resu
     let user = require_connected_user!(request);
 
     let json = Json::<Vec<CreateDirectoryParams>>::from_request(request, &ctx).await?;
+    let re = Regex::new(r#"[<>:"/\\|?*\x00-\x1F]|^(?:aux|con|clock\$|nul|prn|com[1-9]|lpt[1-9])$"#)?;
     let mut items = vec![];
     for params in json.0 {
         let mut item = Item::default();
@@ -85,7 +114,6 @@ async fn new_directory(State(ctx): State<Arc<AppCtx>>, request: Request) -> Resu
             if !permissions.upload_to_directory(&ctx.database, parent_item).await?.granted() { continue; }
         } else if !permissions.upload_to_repository(&ctx.database, &params.repository).await?.granted() { continue; }
 
-        let re = Regex::new(r#"[<>:"/\\|?*\x00-\x1F]|^(?:aux|con|clock\$|nul|prn|com[1-9]|lpt[1-9])$"#)?;
         if re.is_match(item.name.plain()?.as_str()) {
             return Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, format!("Invalid directory name '${}'", item.name.plain()?)));
         }
@@ -158,8 +186,14 @@ async fn delete(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
 }
 
 
+#[derive(Deserialize)]
+struct ThumbnailOptions {
+    /// Requested size (px), rounded up to the nearest configured size
+    size: Option<usize>,
+}
+
 /// Get item thumbnail if available
-async fn thumbnail(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+async fn thumbnail(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(options): Query<ThumbnailOptions>, request: Request) -> Result<Response, ServerError> {
     let item = DbItem::from_id(&ctx.database, &ItemId::from(id), Trash::Both).await?;
     let permissions = Permissions::new(&request)?;
     permissions.view_item(&ctx.database, item.id()).await?.require()?;
@@ -169,51 +203,286 @@ async fn thumbnail(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, r
         Some(f) => { f }
     };
 
-    let thumbnail_path = Thumbnail::find_or_create(&Object::data_path(&file.object, &ctx.database), &Object::thumbnail_path(&file.object, &ctx.database), &file.mimetype.plain()?, 100)?;
+    // Thumbnails are generated in background
+    let size = ctx.config.backend_config.thumbnail_preset(options.size);
+    let thumbnail_path = Object::thumbnail_path(&file.object, size, &ctx.database);
+    if !thumbnail_path.exists() {
+        if !Thumbnail::is_supported(&file.mimetype.plain()?) {
+            return Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, "Cannot generate thumbnail for this file type"));
+        }
+        return pending_job_response(&ctx, Job::thumbnail(file.object.clone(), file.mimetype.clone(), size)).await;
+    }
+
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(thumbnail_path, "image/webp")
+        .etag(format!("{}-thumbnail-{size}", object.hash).as_str())
+        .disposition(format!("attachment; filename=\"{}\"", item.name.encoded()))
+        .respond(request.method(), request.headers()).await
+}
+
+
+/// Status of the background job generating a file that is not available yet, which is queued if needed
+async fn pending_job_response(ctx: &AppCtx, job: Job) -> Result<Response, ServerError> {
+    let job = match DbJob::from_object(&ctx.database, &job.kind, &job.object, job.size).await? {
+        Some(job) if job.status == JobStatus::Failed => {
+            // Failed jobs are given another chance once their cooldown is over
+            match ctx.retry_failed_job(&job).await? {
+                Some(job) => { job }
+                None => { return Err(ServerError::msg(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate preview : {}", job.last_error.unwrap_or_default()))) }
+            }
+        }
+        Some(job) => { job }
+        None => { ctx.queue_job(job).await? }
+    };
+    Ok((StatusCode::ACCEPTED, Json(job)).into_response())
+}
+
+/// Url of the sprite sheet, relative to the WebVTT track
+pub(crate) const VIDEO_SPRITES_IMAGE_URL: &str = "image/";
 
-    let stream = ReaderStream::new(tokio::fs::File::open(thumbnail_path).await?);
-    let body = Body::from_stream(stream);
+/// Get the file of a video the connected user can view
+async fn video_file(ctx: &AppCtx, id: DatabaseId, permissions: &Permissions) -> Result<(Item, FileData), ServerError> {
+    let item = DbItem::from_id(&ctx.database, &ItemId::from(id), Trash::Both).await?;
+    permissions.view_item(&ctx.database, item.id()).await?.require()?;
+    match item.file.clone() {
+        Some(file) if VideoPreview::is_supported(&file.mimetype.plain()?) => { Ok((item, file)) }
+        _ => { Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, "Video previews are only available for videos")) }
+    }
+}
 
-    let headers = [
-        (header::CONTENT_TYPE, "image/webp".to_string()),
-        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", item.name.encoded()))
-    ];
-    Ok((headers, body))
+/// WebVTT thumbnails track for the seek bar of a video
+async fn video_sprites(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let (_, file) = video_file(&ctx, id, &Permissions::new(&request)?).await?;
+    let vtt_path = Object::video_sprites_vtt_path(&file.object, &ctx.database);
+    if !vtt_path.exists() {
+        return pending_job_response(&ctx, Job::new(JobKind::VideoSprites, file.object.clone(), file.mimetype.clone())).await;
+    }
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(vtt_path, "text/vtt")
+        .etag(format!("{}-video-sprites", object.hash).as_str())
+        .respond(request.method(), request.headers()).await
 }
 
+/// Sprite sheet referenced by the WebVTT track of a video
+async fn video_sprites_image(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let (_, file) = video_file(&ctx, id, &Permissions::new(&request)?).await?;
+    let sprites_path = Object::video_sprites_path(&file.object, &ctx.database);
+    if !sprites_path.exists() {
+        return pending_job_response(&ctx, Job::new(JobKind::VideoSprites, file.object.clone(), file.mimetype.clone())).await;
+    }
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(sprites_path, "image/jpeg")
+        .etag(format!("{}-video-sprites-image", object.hash).as_str())
+        .respond(request.method(), request.headers()).await
+}
 
-/// Upload item
-async fn send(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+/// Short animated preview of a video
+async fn video_preview(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let (_, file) = video_file(&ctx, id, &Permissions::new(&request)?).await?;
+    let preview_path = Object::video_preview_path(&file.object, &ctx.database);
+    if !preview_path.exists() {
+        return pending_job_response(&ctx, Job::new(JobKind::VideoPreview, file.object.clone(), file.mimetype.clone())).await;
+    }
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(preview_path, "image/webp")
+        .etag(format!("{}-video-preview", object.hash).as_str())
+        .respond(request.method(), request.headers()).await
+}
+
+/// Jpeg rendition of a raw photo, that browsers can display
+async fn rendition(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let item = DbItem::from_id(&ctx.database, &ItemId::from(id), Trash::Both).await?;
+    Permissions::new(&request)?.view_item(&ctx.database, item.id()).await?.require()?;
+    let file = match &item.file {
+        Some(file) if RawImage::is_supported(&file.mimetype.plain()?) => { file }
+        _ => { return Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, "Renditions are only available for raw photos")) }
+    };
+    let rendition_path = Object::rendition_path(&file.object, &ctx.database);
+    if !rendition_path.exists() {
+        return pending_job_response(&ctx, Job::new(JobKind::Rendition, file.object.clone(), file.mimetype.clone())).await;
+    }
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(rendition_path, "image/jpeg")
+        .etag(format!("{}-rendition", object.hash).as_str())
+        .respond(request.method(), request.headers()).await
+}
+
+/// HLS playlists and segments of a video, starting from the master playlist `master.m3u8`
+async fn hls(State(ctx): State<Arc<AppCtx>>, Path((id, file_name)): Path<(DatabaseId, String)>, request: Request) -> Result<Response, ServerError> {
+    let (item, file) = video_file(&ctx, id, &Permissions::new(&request)?).await?;
+    let mimetype = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+            match extension {
+                "m3u8" => { "application/vnd.apple.mpegurl" }
+                "ts" => { "video/mp2t" }
+                _ => { return Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid stream file")) }
+            }
+        }
+        _ => { return Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid stream file")) }
+    };
+
+    let hls_path = Object::hls_path(&file.object, &ctx.database);
+    if !hls_path.join(HLS_MASTER_PLAYLIST).exists() {
+        if !DbRepository::from_id(&ctx.database, &item.repository).await?.hls_streaming {
+            return Err(ServerError::msg(StatusCode::NOT_ACCEPTABLE, "Video streaming is not enabled for this repository"));
+        }
+        return pending_job_response(&ctx, Job::new(JobKind::Hls, file.object.clone(), file.mimetype.clone())).await;
+    }
+    let path = hls_path.join(&file_name);
+    if !path.exists() {
+        return Err(ServerError::msg(StatusCode::NOT_FOUND, "Invalid stream file"));
+    }
+    let object = Object::from_id(&ctx.database, &file.object).await?;
+    FileResponse::new(path, mimetype)
+        .etag(format!("{}-hls-{file_name}", object.hash).as_str())
+        .respond(request.method(), request.headers()).await
+}
+
+
+/// Upload item. Accepts both the legacy `Content-*` headers and tus creation requests.
+async fn send(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<Response, ServerError> {
     let permissions = Permissions::new(&request)?;
     let connected_user = require_connected_user!(request);
     let headers = request.headers().clone();
+
+    if headers.contains_key("Tus-Resumable") {
+        check_tus_version(&headers)?;
+        let upload = Upload::from_tus(headers.clone(), connected_user.id().clone()).map_err(|err| ServerError::error(StatusCode::BAD_REQUEST, err))?;
+        check_upload_permission(&ctx, &permissions, &upload).await?;
+        let id = ctx.add_upload(upload).await?;
+
+        let location = format!("{}{}/", request.extensions().get::<OriginalUri>().map(|uri| uri.path().to_string()).unwrap_or(request.uri().path().to_string()), id);
+        // creation-with-upload : the first chunk may be sent with the creation request
+        let offset = if headers.get(header::CONTENT_TYPE).map(|value| value == TUS_CONTENT_TYPE).unwrap_or(false) {
+            push_tus_data(&ctx, &id, None, request.into_body()).await?
+        } else {
+            0
+        };
+        return Ok((StatusCode::CREATED, [
+            (header::LOCATION, location),
+            (HeaderName::from_static("upload-offset"), offset.to_string()),
+            (HeaderName::from_static("tus-resumable"), TUS_VERSION.to_string())
+        ]).into_response());
+    }
+
+    let expected_offset = match headers.get("Upload-Offset") {
+        Some(offset) => { Some(i64::from_str(offset.to_str()?).map_err(|err| ServerError::error(StatusCode::BAD_REQUEST, err))?) }
+        None => { None }
+    };
     let id = if let Some(content_id) = headers.get("Content-Id") {
-        content_id.to_str()?.to_string()
+        let id = content_id.to_str()?.to_string();
+        let found_upload = ctx.get_upload(&id).await.map_err(|err| ServerError::error(StatusCode::NOT_FOUND, err))?;
+        if found_upload.read().await.session().owner != *connected_user.id() {
+            return Err(ServerError::msg(StatusCode::FORBIDDEN, "This upload belongs to another user"));
+        }
+        id
     } else {
         // Register new upload
         let upload = Upload::new(headers, connected_user.id().clone())?;
-        if let Some(parent) = &upload.item().parent_item {
-            permissions.upload_to_directory(&ctx.database, parent).await?.require()?;
-        } else {
-            permissions.upload_to_repository(&ctx.database, &upload.item().repository).await?.require()?;
-        }
+        check_upload_permission(&ctx, &permissions, &upload).await?;
         ctx.add_upload(upload).await?
     };
 
     let mut state = {
         let found_upload = ctx.get_upload(&id).await?;
         let mut upload = found_upload.write().await;
-        upload.push_data(request.into_body()).await?;
+        upload.push_data(&ctx.database, expected_offset, request.into_body()).await?;
         upload.get_state()
     };
     if state.finished {
         state = ctx.finalize_upload(&id, &ctx.database).await?;
     }
-    Ok(Json(state))
+    Ok(Json(state).into_response())
+}
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+fn check_tus_version(headers: &HeaderMap) -> Result<(), ServerError> {
+    match headers.get("Tus-Resumable") {
+        Some(version) if version == TUS_VERSION => { Ok(()) }
+        _ => { Err(ServerError::msg(StatusCode::PRECONDITION_FAILED, format!("Unsupported tus version (expected {TUS_VERSION})"))) }
+    }
+}
+
+async fn check_upload_permission(ctx: &AppCtx, permissions: &Permissions, upload: &Upload) -> Result<(), ServerError> {
+    if let Some(parent) = &upload.session().parent_item {
+        permissions.upload_to_directory(&ctx.database, parent).await?.require()
+    } else {
+        permissions.upload_to_repository(&ctx.database, &upload.session().repository).await?.require()
+    }
+}
+
+/// Get a pending upload owned by the connected user
+async fn find_tus_upload(ctx: &AppCtx, connected_user: &User, headers: &HeaderMap, id: &String) -> Result<Arc<tokio::sync::RwLock<Upload>>, ServerError> {
+    check_tus_version(headers)?;
+    let upload = ctx.get_upload(id).await.map_err(|err| ServerError::error(StatusCode::NOT_FOUND, err))?;
+    if upload.read().await.session().owner != *connected_user.id() {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "This upload belongs to another user"));
+    }
+    Ok(upload)
+}
+
+/// Append data to a tus upload and finalize it once complete. Returns the new offset.
+async fn push_tus_data(ctx: &AppCtx, id: &String, expected_offset: Option<i64>, body: Body) -> Result<i64, ServerError> {
+    let (offset, finished) = {
+        let found_upload = ctx.get_upload(id).await?;
+        let mut upload = found_upload.write().await;
+        upload.push_data(&ctx.database, expected_offset, body).await?;
+        (upload.session().upload_offset, upload.is_finished())
+    };
+    if finished {
+        ctx.finalize_upload(id, &ctx.database).await?;
+    }
+    Ok(offset)
+}
+
+/// Describe the tus capabilities of the server
+async fn tus_options() -> impl IntoResponse {
+    (StatusCode::NO_CONTENT, [
+        (HeaderName::from_static("tus-resumable"), TUS_VERSION),
+        (HeaderName::from_static("tus-version"), TUS_VERSION),
+        (HeaderName::from_static("tus-extension"), "creation,creation-with-upload,termination"),
+    ])
+}
+
+/// Get the current offset of a tus upload
+async fn tus_head(State(ctx): State<Arc<AppCtx>>, Path(id): Path<String>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let found_upload = find_tus_upload(&ctx, &require_connected_user!(request), request.headers(), &id).await?;
+    let upload = found_upload.read().await;
+    Ok((StatusCode::OK, [
+        (HeaderName::from_static("upload-offset"), upload.session().upload_offset.to_string()),
+        (HeaderName::from_static("upload-length"), upload.session().size.to_string()),
+        (header::CACHE_CONTROL, "no-store".to_string()),
+        (HeaderName::from_static("tus-resumable"), TUS_VERSION.to_string()),
+    ]))
+}
+
+/// Append a chunk to a tus upload
+async fn tus_patch(State(ctx): State<Arc<AppCtx>>, Path(id): Path<String>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    find_tus_upload(&ctx, &require_connected_user!(request), request.headers(), &id).await?;
+    if request.headers().get(header::CONTENT_TYPE).map(|value| value != TUS_CONTENT_TYPE).unwrap_or(true) {
+        return Err(ServerError::msg(StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Expected Content-Type: {TUS_CONTENT_TYPE}")));
+    }
+    let offset = i64::from_str(request.headers().get("Upload-Offset").ok_or(ServerError::msg(StatusCode::BAD_REQUEST, "missing Upload-Offset header"))?.to_str()?)
+        .map_err(|err| ServerError::error(StatusCode::BAD_REQUEST, err))?;
+    let offset = push_tus_data(&ctx, &id, Some(offset), request.into_body()).await?;
+    Ok((StatusCode::NO_CONTENT, [
+        (HeaderName::from_static("upload-offset"), offset.to_string()),
+        (HeaderName::from_static("tus-resumable"), TUS_VERSION.to_string()),
+    ]))
+}
+
+/// Abort a tus upload
+async fn tus_delete(State(ctx): State<Arc<AppCtx>>, Path(id): Path<String>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    find_tus_upload(&ctx, &require_connected_user!(request), request.headers(), &id).await?;
+    ctx.cancel_upload(&id).await?;
+    Ok((StatusCode::NO_CONTENT, [(HeaderName::from_static("tus-resumable"), TUS_VERSION)]))
 }
 
-/// Download item or directory
-async fn download(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+/// Download item or directory. Files support range and conditional requests, directories are
+/// sent as archive (`?format=zip|tar|tar.zst`, `?compress=1` to deflate zip entries).
+async fn download(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(options): Query<ArchiveOptions>, request: Request) -> Result<Response, ServerError> {
     let item = DbItem::from_id(&ctx.database, &ItemId::from(id), Trash::Both).await?;
     let permissions = Permissions::new(&request)?;
     permissions.view_item(&ctx.database, item.id()).await?.require()?;
@@ -221,38 +490,157 @@ async fn download(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, re
     if let Some(file) = item.file {
         let object = Object::from_id(&ctx.database, &file.object).await?;
 
-        let stream = ReaderStream::new(tokio::fs::File::open(Object::data_path(object.id(), &ctx.database)).await?);
-        let body = Body::from_stream(stream);
-
-        let headers = [
-            (header::CONTENT_TYPE, file.mimetype.plain()?),
-            (header::CONTENT_LENGTH, file.size.to_string()),
-            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", item.name.encoded()))
-        ];
-        Ok((headers, body))
+        FileResponse::new(Object::data_path(object.id(), &ctx.database), file.mimetype.plain()?.as_str())
+            .etag(object.hash.as_str())
+            .last_modified_ms(file.timestamp)
+            .disposition(format!("attachment; filename=\"{}\"", item.name.encoded()))
+            .respond(request.method(), request.headers()).await
     } else {
-        let mut zip = AsyncDirectoryZip::new();
-        zip.push_item(&ctx.database, item.clone()).await?;
+        let name = item.name.encoded().clone();
+        archive_response(ctx, &options, vec![item], &name).await
+    }
+}
 
-        let size = zip.size()?;
+/// List the versions of a file, most recent (current) first
+async fn versions(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let item = ItemId::from(id);
+    Permissions::new(&request)?.view_item(&ctx.database, &item).await?.require()?;
+    Ok(Json(DbFileVersion::from_item(&ctx.database, &item).await?))
+}
 
-        let (w, r) = tokio::io::duplex(4096);
-        tokio::spawn(async move {
-            zip.finalize(&ctx.database, w).await
-        });
+/// Download a specific version of a file
+async fn download_version(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let version = DbFileVersion::from_id(&ctx.database, &FileVersionId::from(id)).await?;
+    let permissions = Permissions::new(&request)?;
+    permissions.view_item(&ctx.database, &version.item).await?.require()?;
+    let item = DbItem::from_id(&ctx.database, &version.item, Trash::Both).await?;
+    let object = Object::from_id(&ctx.database, &version.object).await?;
+
+    FileResponse::new(Object::data_path(object.id(), &ctx.database), version.mimetype.plain()?.as_str())
+        .etag(object.hash.as_str())
+        .last_modified_ms(version.timestamp)
+        .disposition(format!("attachment; filename=\"{}\"", item.name.encoded()))
+        .respond(request.method(), request.headers()).await
+}
 
-        let body = Body::from_stream(ReaderStream::new(r));
-        let headers = [
-            (header::CONTENT_TYPE, "application/zip".to_string()),
-            (header::CONTENT_LENGTH, size.to_string()),
-            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", item.name.encoded()))
-        ];
-        Ok((headers, body))
+/// Make previous versions the current content of their files
+async fn restore_versions(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<FileVersionId>>::from_request(request, &ctx).await?;
+    let mut items = vec![];
+    for version in json.0 {
+        let version = DbFileVersion::from_id(&ctx.database, &version).await?;
+        if permissions.edit_item(&ctx.database, &version.item).await?.granted() {
+            let mut item = DbItem::from_id(&ctx.database, &version.item, Trash::Both).await?;
+            DbFileVersion::set_current(&ctx.database, &mut item, version.file_data(), user.id()).await?;
+            Upload::index_content(&ctx.database, &item).await;
+            Upload::read_metadata(&ctx.database, &mut item).await;
+            items.push(item.id().clone());
+        }
+    }
+    Ok(Json(items))
+}
+
+#[derive(Deserialize)]
+struct ConvertRequest {
+    items: Vec<ItemId>,
+    target: ConversionTarget,
+    #[serde(default)]
+    quality: ConversionQuality,
+    #[serde(default)]
+    output: ConversionOutput,
+}
+
+/// Queue the conversion of files. Files that cannot be converted to the target are ignored.
+async fn convert(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<ConvertRequest>::from_request(request, &ctx).await?;
+    let mut conversions = vec![];
+    for item in &json.items {
+        if !permissions.edit_item(&ctx.database, item).await?.granted() {
+            continue;
+        }
+        let file = match DbItem::from_id(&ctx.database, item, Trash::Both).await?.file {
+            Some(file) if json.target.accepts(&file.mimetype.plain()?) => { file }
+            _ => { continue }
+        };
+        let mut conversion = Conversion::new(item.clone(), user.id().clone(), file.object.clone(), json.target.clone(), json.quality.clone(), json.output.clone());
+        DbConversion::push(&ctx.database, &mut conversion).await?;
+        ctx.queue_job(Job::conversion(file.object, file.mimetype, conversion.id().clone())).await?;
+        conversions.push(conversion);
+    }
+    Ok(Json(conversions))
+}
+
+/// Conversions of the connected user, with their progress
+async fn conversions(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    Ok(Json(DbConversion::from_owner(&ctx.database, user.id()).await?))
+}
+
+/// Get a conversion of the connected user
+async fn owned_conversion(ctx: &AppCtx, id: &ConversionId, user: &User) -> Result<Conversion, ServerError> {
+    let conversion = DbConversion::from_id(&ctx.database, id).await?;
+    if conversion.owner != *user.id() {
+        return Err(ServerError::msg(StatusCode::FORBIDDEN, "This conversion belongs to another user"));
+    }
+    Ok(conversion)
+}
+
+/// Download the converted file to review it before confirming the conversion
+async fn download_conversion(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<Response, ServerError> {
+    let user = require_connected_user!(request);
+    let conversion = owned_conversion(&ctx, &ConversionId::from(id), &user).await?;
+    let result = match &conversion.result {
+        None => { return Ok((StatusCode::ACCEPTED, Json(conversion)).into_response()) }
+        Some(result) => { Object::from_id(&ctx.database, result).await? }
+    };
+    let item = DbItem::from_id(&ctx.database, &conversion.item, Trash::Both).await?;
+
+    FileResponse::new(Object::data_path(result.id(), &ctx.database), conversion.target.mimetype())
+        .etag(result.hash.as_str())
+        .disposition(format!("attachment; filename=\"{}\"", item.name.encoded()))
+        .respond(request.method(), request.headers()).await
+}
+
+/// Apply finished conversions : the converted files become new items or new versions, and the original content is released
+async fn confirm_conversions(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let permissions = Permissions::new(&request)?;
+    let json = Json::<Vec<ConversionId>>::from_request(request, &ctx).await?;
+    let mut items = vec![];
+    for conversion in json.0 {
+        let conversion = owned_conversion(&ctx, &conversion, &user).await?;
+        if permissions.edit_item(&ctx.database, &conversion.item).await?.granted() {
+            let mut item = DbConversion::confirm(&ctx.database, &conversion).await?;
+            Upload::index_content(&ctx.database, &item).await;
+            Upload::read_metadata(&ctx.database, &mut item).await;
+            if let Err(err) = ctx.queue_previews(&item).await {
+                warn!("Failed to queue previews of {} : {err}", item.id());
+            }
+            items.push(item);
+        }
     }
+    Ok(Json(items))
 }
 
-/// Download item or directory
-async fn download_multi(State(ctx): State<Arc<AppCtx>>, Path(ids): Path<String>, request: Request) -> Result<impl IntoResponse, ServerError> {
+/// Discard conversions and their converted files, keeping the original files
+async fn cancel_conversions(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let user = require_connected_user!(request);
+    let json = Json::<Vec<ConversionId>>::from_request(request, &ctx).await?;
+    let mut conversions = vec![];
+    for conversion in json.0 {
+        let conversion = owned_conversion(&ctx, &conversion, &user).await?;
+        DbConversion::cancel(&ctx.database, &conversion).await?;
+        conversions.push(conversion.id().clone());
+    }
+    Ok(Json(conversions))
+}
+
+/// Download multiple items as an archive (`?format=zip|tar|tar.zst`, `?compress=1` to deflate zip entries)
+async fn download_multi(State(ctx): State<Arc<AppCtx>>, Path(ids): Path<String>, Query(options): Query<ArchiveOptions>, request: Request) -> Result<Response, ServerError> {
     let mut items = vec![];
     for str in ids.split('-') {
         if !str.is_empty() {
@@ -261,26 +649,12 @@ async fn download_multi(State(ctx): State<Arc<AppCtx>>, Path(ids): Path<String>,
     }
     let permissions = Permissions::new(&request)?;
 
-    let mut zip = AsyncDirectoryZip::new();
+    let mut archive_items = vec![];
     for item in items {
         permissions.view_item(&ctx.database, &item).await?.require()?;
-        let item = DbItem::from_id(&ctx.database, &item, Trash::Both).await?;
-        zip.push_item(&ctx.database, item.clone()).await?;
+        archive_items.push(DbItem::from_id(&ctx.database, &item, Trash::Both).await?);
     }
-    let size = zip.size()?;
-
-    let (w, r) = tokio::io::duplex(4096);
-    tokio::spawn(async move {
-        zip.finalize(&ctx.database, w).await
-    });
-
-    let body = Body::from_stream(ReaderStream::new(r));
-    let headers = [
-        (header::CONTENT_TYPE, "application/zip".to_string()),
-        (header::CONTENT_LENGTH, size.to_string()),
-        (header::CONTENT_DISPOSITION, "attachment; filename=\"Archive.zip\"".to_string())
-    ];
-    Ok((headers, body))
+    archive_response(ctx, &options, archive_items, "Archive").await
 }
 
 /// Update item data
@@ -318,10 +692,151 @@ async fn edit(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl I
     Ok(Json(items))
 }
 
-/// Search item by filter
+#[derive(Clone, Copy, PartialEq)]
+enum TransferMode {
+    Move,
+    Copy,
+}
+
+/// Move items to another directory, or to the root of a repository
+async fn move_items(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    transfer_items(ctx, request, TransferMode::Move).await
+}
+
+/// Copy items to another directory, or to the root of a repository, without duplicating file data
+async fn copy_items(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    transfer_items(ctx, request, TransferMode::Copy).await
+}
+
+async fn transfer_items(ctx: Arc<AppCtx>, request: Request, mode: TransferMode) -> Result<Json<Vec<Item>>, ServerError> {
+    let user = require_connected_user!(request);
+
+    #[derive(Deserialize, Debug)]
+    struct Data {
+        items: Vec<ItemId>,
+        /// Destination directory. Items are sent to the root of `repository` if None.
+        parent_item: Option<ItemId>,
+        repository: Option<RepositoryId>,
+        #[serde(default)]
+        on_conflict: ConflictPolicy,
+    }
+
+    let permissions = Permissions::new(&request)?;
+    let data = Json::<Data>::from_request(request, &ctx).await?.0;
+
+    let destination = match &data.parent_item {
+        Some(parent) => {
+            let parent = DbItem::from_id(&ctx.database, parent, Trash::No).await?;
+            if parent.directory.is_none() {
+                return Err(ServerError::msg(StatusCode::BAD_REQUEST, "The destination is not a directory"));
+            }
+            permissions.upload_to_directory(&ctx.database, parent.id()).await?.require()?;
+            Some(parent)
+        }
+        None => { None }
+    };
+    let repository = match &destination {
+        Some(parent) => { parent.repository.clone() }
+        None => {
+            let repository = data.repository.ok_or(ServerError::msg(StatusCode::BAD_REQUEST, "No destination specified"))?;
+            permissions.upload_to_repository(&ctx.database, &repository).await?.require()?;
+            repository
+        }
+    };
+
+    let mut items = vec![];
+    for id in data.items {
+        let permission = match mode {
+            TransferMode::Move => { permissions.edit_item(&ctx.database, &id).await? }
+            TransferMode::Copy => { permissions.view_item(&ctx.database, &id).await? }
+        };
+        if !permission.granted() {
+            continue;
+        }
+        let item = match DbItem::from_id(&ctx.database, &id, Trash::No).await {
+            Ok(item) => { item }
+            Err(_) => { continue }
+        };
+        if let Some(destination) = &destination {
+            if destination.repository == item.repository && (destination.id() == item.id() || destination.absolute_path.to_string().starts_with(&format!("{}/", item.absolute_path))) {
+                return Err(ServerError::msg(StatusCode::BAD_REQUEST, format!("Cannot move or copy '{}' inside itself", item.name.plain()?)));
+            }
+        }
+        items.push(item);
+    }
+    let is_in_place = |item: &Item| mode == TransferMode::Move && item.parent_item == data.parent_item && item.repository == repository;
+
+    // Check every item before changing anything so that the request is rejected as a whole
+    if let ConflictPolicy::Fail = data.on_conflict {
+        let mut names = HashSet::new();
+        for item in items.iter().filter(|item| !is_in_place(item)) {
+            if !names.insert(item.name.encoded().clone()) || DbItem::find_child(&ctx.database, &repository, &data.parent_item, &item.name).await?.is_some() {
+                return Err(ServerError::msg(StatusCode::CONFLICT, format!("An item named '{}' already exists in the destination", item.name.plain()?)));
+            }
+        }
+    }
+
+    // The whole batch is applied in a single transaction, so that an overwritten item is only deleted if every
+    // transfer succeeds
+    let transaction = ctx.database.begin().await?;
+    let mut result = vec![];
+    for item in items {
+        if is_in_place(&item) {
+            result.push(item);
+            continue;
+        }
+        let mut name = item.name.clone();
+        if let Some(existing) = DbItem::find_child(&transaction, &repository, &data.parent_item, &name).await? {
+            match data.on_conflict {
+                ConflictPolicy::Fail => {
+                    return Err(ServerError::msg(StatusCode::CONFLICT, format!("An item named '{}' already exists in the destination", name.plain()?)));
+                }
+                ConflictPolicy::Skip => { continue }
+                ConflictPolicy::Rename => {
+                    name = DbItem::available_name(&transaction, &repository, &data.parent_item, &name).await?;
+                }
+                ConflictPolicy::Overwrite => {
+                    // Copying an item over itself
+                    if existing.id() == item.id() {
+                        result.push(item);
+                        continue;
+                    }
+                    if existing.repository == item.repository && item.absolute_path.to_string().starts_with(&format!("{}/", existing.absolute_path)) {
+                        return Err(ServerError::msg(StatusCode::BAD_REQUEST, format!("Cannot overwrite '{}' which contains the source item", existing.name.plain()?)));
+                    }
+                    permissions.edit_item(&transaction, existing.id()).await?.require()?;
+                    DbItem::delete(&existing, &transaction).await?;
+                }
+            }
+        }
+        let id = match mode {
+            TransferMode::Move => {
+                DbItem::move_to(&transaction, item.id(), &data.parent_item, &repository, &name).await?;
+                item.id().clone()
+            }
+            TransferMode::Copy => {
+                DbItem::copy_to(&transaction, item.id(), &data.parent_item, &repository, &name, user.id()).await?
+            }
+        };
+        result.push(DbItem::from_id(&transaction, &id, Trash::Both).await?);
+    }
+    transaction.commit().await?;
+    Ok(Json(result))
+}
+
+/// Search item by filter. When a full-text query is given, matches are returned ordered by relevance with a snippet.
 async fn search(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
     let permissions = Permissions::new(&request)?;
     let data = Json::<ItemSearchData>::from_request(request, &ctx).await?.0;
+    if data.query.is_some() {
+        let mut matches = vec![];
+        for found in DbItem::search_ranked(&ctx.database, data).await? {
+            if permissions.view_item(&ctx.database, &found.id).await?.granted() {
+                matches.push(found);
+            }
+        }
+        return Ok(Json(matches).into_response());
+    }
     let result = DbItem::search(&ctx.database, data).await?;
     let mut items = vec![];
     for data in result {
@@ -329,5 +844,5 @@ async fn search(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
             items.push(data.id().clone());
         }
     }
-    Ok(Json(items))
+    Ok(Json(items).into_response())
 }
\ No newline at end of file
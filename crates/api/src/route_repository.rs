@@ -1,26 +1,26 @@
-use database::item::{DbItem, Trash};
+use database::file_version::DbFileVersion;
+use database::item::{DbItem, ListingOptions, TimelinePeriod, Trash};
 use crate::require_connected_user;
 use crate::route_user::UserCredentials;
 use crate::permissions::Permissions;
 use utils::server_error::ServerError;
 use anyhow::Error;
 use axum::body::Body;
-use axum::extract::{FromRequest, Path, Request, State};
-use axum::http::{header, StatusCode};
-use axum::response::IntoResponse;
+use axum::extract::{FromRequest, Path, Query, Request, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use serde::{Deserialize};
 use std::sync::Arc;
-use tokio_util::io::ReaderStream;
 use database::subscription::{Subscription, SubscriptionAccessType};
-use database::async_zip::AsyncDirectoryZip;
 use database::repository::DbRepository;
 use database::user::DbUser;
 use types::database_ids::{DatabaseId, RepositoryId, UserId};
 use types::enc_string::EncString;
 use types::repository::{Repository, RepositoryStatus};
 use crate::app_ctx::AppCtx;
+use crate::archive::{archive_response, ArchiveOptions};
 
 pub struct RepositoryRoutes {}
 
@@ -40,8 +40,11 @@ impl RepositoryRoutes {
             .route("/subscribe/", post(subscribe).with_state(ctx.clone()))
             .route("/unsubscribe/", post(unsubscribe).with_state(ctx.clone()))
             .route("/stats/", post(stats).with_state(ctx.clone()))
+            .route("/update-sizes/", post(update_sizes).with_state(ctx.clone()))
             .route("/subscriptions/", post(subscriptions).with_state(ctx.clone()))
-            .route("/trash-content/", post(trash_content).with_state(ctx.clone()));
+            .route("/trash-content/", post(trash_content).with_state(ctx.clone()))
+            .route("/timeline/:id/", get(timeline).with_state(ctx.clone()))
+            .route("/timeline/:id/items/", get(timeline_items).with_state(ctx.clone()));
         Ok(router)
     }
 }
@@ -90,14 +93,30 @@ async fn create_repository(State(ctx): State<Arc<AppCtx>>, request: Request) ->
 }
 
 /// Get repositories owned by connected user
-async fn content(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+async fn content(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(options): Query<ListingOptions>, request: Request) -> Result<impl IntoResponse, ServerError> {
     let repository = RepositoryId::from(id);
     let permissions = Permissions::new(&request)?;
     permissions.view_repository(&ctx.database, &repository).await?.require()?;
-    let items = DbItem::from_repository(&ctx.database, &repository, Trash::No).await?;
-    Ok(Json(items))
+    Ok(Json(DbItem::from_repository_paged(&ctx.database, &repository, Trash::No, &options).await?))
 }
 
+/// Number of photos and videos of a repository per year, month and day
+async fn timeline(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(period): Query<TimelinePeriod>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let repository = RepositoryId::from(id);
+    let permissions = Permissions::new(&request)?;
+    permissions.view_repository(&ctx.database, &repository).await?.require()?;
+    period.validate().map_err(|err| ServerError::error(StatusCode::BAD_REQUEST, err))?;
+    Ok(Json(DbItem::timeline(&ctx.database, &repository, &period).await?))
+}
+
+/// Photos and videos of a repository taken during a year, month or day, ordered by date
+async fn timeline_items(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(period): Query<TimelinePeriod>, Query(options): Query<ListingOptions>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let repository = RepositoryId::from(id);
+    let permissions = Permissions::new(&request)?;
+    permissions.view_repository(&ctx.database, &repository).await?.require()?;
+    period.validate().map_err(|err| ServerError::error(StatusCode::BAD_REQUEST, err))?;
+    Ok(Json(DbItem::timeline_paged(&ctx.database, &repository, &period, &options).await?))
+}
 
 /// Get repositories owned by connected user
 async fn get_owned_repositories(State(ctx): State<Arc<AppCtx>>, request: Request) -> impl IntoResponse {
@@ -147,17 +166,15 @@ async fn delete_repository(State(ctx): State<Arc<AppCtx>>, request: axum::http::
 }
 
 /// Get all root items of a repository
-pub async fn root_content(State(ctx): State<Arc<AppCtx>>, request: axum::http::Request<Body>) -> Result<impl IntoResponse, ServerError> {
+pub async fn root_content(State(ctx): State<Arc<AppCtx>>, Query(options): Query<ListingOptions>, request: axum::http::Request<Body>) -> Result<impl IntoResponse, ServerError> {
     let permission = Permissions::new(&request)?;
 
     let data = Json::<Vec<RepositoryId>>::from_request(request, &ctx).await?;
 
-    let mut result = vec![];
-    for repository in data.0 {
-        permission.view_repository(&ctx.database, &repository).await?.require()?;
-        result.append(&mut DbItem::repository_root(&ctx.database, &repository, Trash::Both).await?);
+    for repository in &data.0 {
+        permission.view_repository(&ctx.database, repository).await?.require()?;
     }
-    Ok(Json(result))
+    Ok(Json(DbItem::repository_root_paged(&ctx.database, data.0, Trash::Both, &options).await?))
 }
 
 /// Get trash root items of a repository
@@ -188,6 +205,10 @@ async fn update(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
         allow_visitor_upload: bool,
         status: String,
         description: Option<EncString>,
+        /// Unchanged when missing, 0 to keep every version
+        max_file_versions: Option<i64>,
+        /// Unchanged when missing
+        hls_streaming: Option<bool>,
     }
 
     let permissions = Permissions::new(&request)?;
@@ -203,7 +224,14 @@ async fn update(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
                 repository.visitor_file_lifetime = data.visitor_file_lifetime;
                 repository.allow_visitor_upload = data.allow_visitor_upload;
                 repository.status = RepositoryStatus::from(data.status);
+                if let Some(max_file_versions) = data.max_file_versions {
+                    repository.max_file_versions = Some(max_file_versions).filter(|max| *max > 0);
+                }
+                if let Some(hls_streaming) = data.hls_streaming {
+                    repository.hls_streaming = hls_streaming;
+                }
                 DbRepository::push(&mut repository, &ctx.database).await?;
+                DbFileVersion::apply_repository_retention(&ctx.database, repository.id()).await?;
                 repositories.push(repository.id().clone());
             }
         }
@@ -212,30 +240,14 @@ async fn update(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
 }
 
 /// Download items or directory from a repository
-async fn download(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, request: Request) -> Result<impl IntoResponse, ServerError> {
+async fn download(State(ctx): State<Arc<AppCtx>>, Path(id): Path<DatabaseId>, Query(options): Query<ArchiveOptions>, request: Request) -> Result<Response, ServerError> {
     let repository = DbRepository::from_id(&ctx.database, &RepositoryId::from(id)).await?;
     let permissions = Permissions::new(&request)?;
     permissions.view_repository(&ctx.database, repository.id()).await?.require()?;
 
-    let mut zip = AsyncDirectoryZip::new();
-    for item in DbItem::from_repository(&ctx.database, &RepositoryId::from(id), Trash::No).await? {
-        zip.push_item(&ctx.database, item).await?;
-    }
-
-    let size = zip.size()?;
-
-    let (w, r) = tokio::io::duplex(4096);
-    tokio::spawn(async move {
-        zip.finalize(&ctx.database, w).await
-    });
+    let items = DbItem::from_repository(&ctx.database, &RepositoryId::from(id), Trash::No).await?;
 
-    let body = Body::from_stream(ReaderStream::new(r));
-    let headers = [
-        (header::CONTENT_TYPE, "application/zip".to_string()),
-        (header::CONTENT_LENGTH, size.to_string()),
-        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", repository.display_name.encoded()))
-    ];
-    Ok((headers, body))
+    archive_response(ctx, &options, items, repository.display_name.encoded()).await
 }
 
 /// Subscribe user to a repository
@@ -259,10 +271,11 @@ async fn subscribe(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<i
     permissions.edit_repository(&ctx.database, &data.repository).await?.require()?;
     let mut subscriptions = vec![];
     for user in &data.users {
-        let mut subscription = Subscription::default();
-        subscription.owner = user.user.clone();
-        subscription.repository = data.repository.clone();
-        subscription.access_type = SubscriptionAccessType::from(user.access_type.clone());
+        let mut subscription = Subscription {
+            owner: user.user.clone(),
+            repository: data.repository.clone(),
+            access_type: SubscriptionAccessType::from(user.access_type.clone()),
+        };
         subscription.push(&ctx.database).await?;
         subscriptions.push(subscription);
     }
@@ -302,4 +315,12 @@ async fn stats(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl
     let data = Json::<RepositoryId>::from_request(request, &ctx).await?.0;
     permissions.edit_repository(&ctx.database, &data).await?.require()?;
     Ok(Json(DbRepository::stats(&DbRepository::from_id(&ctx.database, &data).await?, &ctx.database).await?))
-}
\ No newline at end of file
+}
+/// Recompute the size of the directories of a repository
+async fn update_sizes(State(ctx): State<Arc<AppCtx>>, request: Request) -> Result<impl IntoResponse, ServerError> {
+    let permissions = Permissions::new(&request)?;
+    let data = Json::<RepositoryId>::from_request(request, &ctx).await?.0;
+    permissions.edit_repository(&ctx.database, &data).await?.require()?;
+    DbRepository::update_directory_sizes(&data, &ctx.database).await?;
+    Ok(StatusCode::OK)
+}
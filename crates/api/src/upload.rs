@@ -1,137 +1,303 @@
-use std::{env, fs};
-use std::io::Write;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
 use database::object::Object;
 use types::enc_string::EncString;
 use anyhow::Error;
 use axum::body::Body;
-use axum::http::HeaderMap;
+use axum::http::{HeaderMap, StatusCode};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use futures::{io, TryStreamExt};
 use serde::Serialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio_util::io::StreamReader;
 use database::{Database};
+use database::file_version::DbFileVersion;
 use database::item::DbItem;
+use database::upload::{DbUpload, UploadSession};
+use thumbnailer::metadata::MetadataExtractor;
+use thumbnailer::text_extractor::TextExtractor;
+use tracing::warn;
 use types::database_ids::{DatabaseId, ItemId, RepositoryId, UserId};
 use types::item::{FileData, Item};
+use utils::server_error::ServerError;
 
 pub struct Upload {
-    pub id: String,
-    item: Item,
-    file: FileData,
-    bytes_read: usize,
+    session: UploadSession,
     hasher: blake3::Hasher,
 }
 
 impl Upload {
+    /// Create an upload from the legacy `Content-*` headers
     pub fn new(headers: HeaderMap, owner: UserId) -> Result<Self, Error> {
-        let mut item = Item::default();
-
         let name = EncString::try_from(headers.get("Content-Name").ok_or(Error::msg("missing Content-Name header"))?)?;
-
-        item.name = name.clone();
-        item.description = match headers.get("Content-Description") {
+        let mut session = UploadSession::new(
+            owner,
+            RepositoryId::from(DatabaseId::from_str(headers.get("Content-Repository").ok_or(Error::msg("missing Content-Repository header"))?.to_str()?)?),
+            name.clone(),
+            i64::from_str(headers.get("Content-Size").ok_or(Error::msg("missing Content-Size header"))?.to_str()?)?);
+        session.parent_item = match headers.get("Content-Parent") {
+            None => { None }
+            Some(header) => {
+                Some(ItemId::from(DatabaseId::from_str(header.to_str()?)?))
+            }
+        };
+        session.description = match headers.get("Content-Description") {
             None => { None }
             Some(header) => { Some(EncString::try_from(header)?) }
         };
-        item.in_trash = false;
-        item.repository = RepositoryId::from(DatabaseId::from_str(headers.get("Content-Repository").ok_or(Error::msg("missing Content-Repository header"))?.to_str()?)?);
+        session.mimetype = Self::guess_mimetype(&name)?;
+        session.timestamp = i64::from_str(headers.get("Content-Timestamp").ok_or(Error::msg("missing Content-Timestamp header"))?.to_str()?)?;
+        Self::from_session(session)
+    }
 
-        item.parent_item = match headers.get("Content-Parent") {
-            None => { None }
-            Some(header) => {
-                Some(ItemId::from(DatabaseId::from_str(header.to_str()?)?))
+    /// Create an upload from a tus creation request (`Upload-Length` and `Upload-Metadata` headers)
+    pub fn from_tus(headers: HeaderMap, owner: UserId) -> Result<Self, Error> {
+        let size = i64::from_str(headers.get("Upload-Length").ok_or(Error::msg("missing Upload-Length header"))?.to_str()?)?;
+        let mut metadata = HashMap::new();
+        if let Some(header) = headers.get("Upload-Metadata") {
+            for pair in header.to_str()?.split(',') {
+                let mut split = pair.trim().splitn(2, ' ');
+                let key = split.next().unwrap_or_default();
+                if key.is_empty() {
+                    continue;
+                }
+                let value = match split.next() {
+                    None => { String::new() }
+                    Some(value) => { String::from_utf8(BASE64_STANDARD.decode(value.trim())?)? }
+                };
+                metadata.insert(key.to_string(), value);
             }
+        }
+
+        let name = EncString::encode(metadata.get("name").or(metadata.get("filename")).ok_or(Error::msg("missing name metadata"))?);
+        let mut session = UploadSession::new(
+            owner,
+            RepositoryId::from(DatabaseId::from_str(metadata.get("repository").ok_or(Error::msg("missing repository metadata"))?)?),
+            name.clone(),
+            size);
+        session.parent_item = match metadata.get("parent") {
+            None => { None }
+            Some(parent) => { Some(ItemId::from(DatabaseId::from_str(parent)?)) }
+        };
+        session.description = metadata.get("description").map(|description| EncString::encode(description));
+        session.mimetype = match metadata.get("mimetype").or(metadata.get("filetype")) {
+            None => { Self::guess_mimetype(&name)? }
+            Some(mimetype) => { EncString::encode(mimetype) }
         };
-        item.owner = owner.clone();
-
-        Ok(Self {
-            id: String::default(),
-            item,
-            file: FileData {
-                size: i64::from_str(headers.get("Content-Size").ok_or(Error::msg("missing Content-Size header"))?.to_str()?)?,
-                mimetype: EncString::from(match mime_guess::from_path(PathBuf::from(name.plain()?.as_str())).first_raw() {
-                    None => { "application/octet-stream" }
-                    Some(mime_type) => {
-                        mime_type
-                    }
-                }),
-                timestamp: i64::from_str(headers.get("Content-Timestamp").ok_or(Error::msg("missing Content-Timestamp header"))?.to_str()?)?,
-                object: Default::default(),
-            },
-            bytes_read: 0,
-            hasher: blake3::Hasher::new(),
-        })
-    }
-
-    pub async fn push_data(&mut self, body: Body) -> Result<(), Error> {
+        session.timestamp = match metadata.get("timestamp") {
+            None => { 0 }
+            Some(timestamp) => { i64::from_str(timestamp)? }
+        };
+        Self::from_session(session)
+    }
+
+    /// Restore an upload from its database entry and its partial data at `path`. The data is truncated to the last
+    /// known offset and hashed again to rebuild the hasher state : this blocks, call it from a blocking task.
+    pub fn resume(path: &Path, mut session: UploadSession) -> Result<Self, Error> {
+        let mut hasher = blake3::Hasher::new();
+        if path.exists() {
+            let file = fs::OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.len() < session.upload_offset as u64 {
+                session.upload_offset = file.metadata()?.len() as i64;
+            }
+            file.set_len(session.upload_offset as u64)?;
+            let mut reader = fs::File::open(path)?;
+            let mut buf = [0u8; 65536];
+            loop {
+                let read_data = reader.read(&mut buf)?;
+                if read_data == 0 {
+                    break;
+                }
+                hasher.write_all(&buf[..read_data])?;
+            }
+        } else {
+            session.upload_offset = 0;
+        }
+        Ok(Self { session, hasher })
+    }
+
+    fn from_session(session: UploadSession) -> Result<Self, Error> {
+        if session.size < 0 {
+            return Err(Error::msg("Invalid upload size"));
+        }
+        Ok(Self { session, hasher: blake3::Hasher::new() })
+    }
+
+    fn guess_mimetype(name: &EncString) -> Result<EncString, Error> {
+        Ok(EncString::from(match mime_guess::from_path(PathBuf::from(name.plain()?.as_str())).first_raw() {
+            None => { "application/octet-stream" }
+            Some(mime_type) => {
+                mime_type
+            }
+        }))
+    }
+
+    /// Append the body to the stored data. The offset is persisted even if the stream is interrupted.
+    /// When the client states the offset it resumes from, it is checked under the same write lock as the append, so
+    /// two requests sending the same chunk cannot both be written.
+    pub async fn push_data(&mut self, db: &Database, expected_offset: Option<i64>, body: Body) -> Result<(), ServerError> {
+        if let Some(expected_offset) = expected_offset {
+            if expected_offset != self.session.upload_offset {
+                return Err(ServerError::msg(StatusCode::CONFLICT, format!("Upload offset mismatch (expected {})", self.session.upload_offset)));
+            }
+        }
+        let result = self.write_body(db, body).await;
+        let offset = self.session.upload_offset;
+        DbUpload::set_offset(&mut self.session, db, offset).await?;
+        Ok(result?)
+    }
+
+    async fn write_body(&mut self, db: &Database, body: Body) -> Result<(), Error> {
         let stream = body.into_data_stream();
-        let stream = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+        let stream = stream.map_err(io::Error::other);
         let mut read = StreamReader::new(stream);
 
-        if !self.get_file_path().parent().unwrap().exists() {
-            fs::create_dir_all(self.get_file_path().parent().unwrap())?;
+        let path = self.get_file_path(db);
+        if !path.parent().unwrap().exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
         };
 
         let mut file = BufWriter::new(tokio::fs::OpenOptions::new()
             .create(true)
             .append(true)
-            .open(self.get_file_path()).await.map_err(|err| { Error::msg(format!("Cannot open file sink : {err}")) })?);
+            .open(&path).await.map_err(|err| { Error::msg(format!("Cannot open file sink : {err}")) })?);
 
         let mut buf = [0u8; 4096];
-        loop {
-            let read_data = read.read(&mut buf).await?;
+        let result = loop {
+            let read_data = match read.read(&mut buf).await {
+                Ok(read_data) => { read_data }
+                Err(err) => { break Err(Error::from(err)) }
+            };
             if read_data == 0 {
-                break;
+                break Ok(());
+            }
+            if self.session.upload_offset + read_data as i64 > self.session.size {
+                break Err(Error::msg("Received more data than the declared upload size"));
             }
             let data_to_write = &buf[..read_data];
-            let hash_data = self.hasher.write(data_to_write)?;
-            let write_data = file.write(data_to_write).await?;
-            if read_data != write_data && read_data != hash_data {
-                return Err(Error::msg(format!("Failed to write the right amount of data (expected {}, got {})", read_data, write_data)));
+            if let Err(err) = file.write_all(data_to_write).await {
+                break Err(Error::from(err));
             }
-            self.bytes_read += read_data;
-        }
+            self.hasher.write_all(data_to_write)?;
+            self.session.upload_offset += read_data as i64;
+        };
         file.flush().await?;
-        Ok(())
+        result
     }
 
-    pub fn get_file_path(&self) -> PathBuf {
-        env::temp_dir().join("fileshare_upload").join(self.id.to_string())
+    pub fn get_file_path(&self, db: &Database) -> PathBuf {
+        DbUpload::data_path(self.session.id(), db)
     }
 
     pub fn get_state(&self) -> UploadState {
-        let finished = self.bytes_read == self.file.size as usize;
         UploadState {
-            id: self.id.clone(),
-            finished,
-            item: if finished { Some(self.item.clone()) } else { None },
+            id: self.session.id().clone(),
+            finished: self.is_finished(),
+            item: None,
         }
     }
 
+    pub fn is_finished(&self) -> bool {
+        self.session.upload_offset == self.session.size
+    }
+
     pub async fn store(&mut self, db: &Database) -> Result<Item, Error> {
-        assert_eq!(self.bytes_read, self.file.size as usize);
+        assert!(self.is_finished());
+        // Uploading a file over one of our own files creates a new version of it
+        let mut item = match DbItem::find_child(db, &self.session.repository, &self.session.parent_item, &self.session.name).await? {
+            None => { self.item() }
+            Some(existing) => {
+                if existing.file.is_none() || existing.in_trash || existing.owner != self.session.owner {
+                    return Err(Error::msg(format!("An item named '{}' already exists", self.session.name.plain()?)));
+                }
+                existing
+            }
+        };
+        if self.session.description.is_some() {
+            item.description = self.session.description.clone();
+        }
+        let mut file = FileData {
+            size: self.session.size,
+            mimetype: self.session.mimetype.clone(),
+            timestamp: self.session.timestamp,
+            object: Default::default(),
+        };
         let hash = self.hasher.clone().finalize().to_string();
-        for existing in Object::from_hash(db, &hash).await? {
-            if existing.equals_to_file(db, self.get_file_path()).await? {
-                fs::remove_file(self.get_file_path())?;
-                self.file.object = existing.id().clone();
-                self.item.file = Some(self.file.clone());
-                DbItem::push(&mut self.item, db).await?;
-                return Ok(self.item.clone());
+        let transaction = db.begin().await?;
+        let object = Object::store(&transaction, self.get_file_path(db).as_path(), &hash).await?;
+        file.object = object.id().clone();
+        DbFileVersion::set_current(&transaction, &mut item, file, &self.session.owner).await?;
+        DbUpload::delete(&self.session, &transaction).await?;
+        transaction.commit().await?;
+        Self::index_content(db, &item).await;
+        Self::read_metadata(db, &mut item).await;
+        Ok(item)
+    }
+
+    /// Extract the text of the file for full-text search. Failures never reject the upload.
+    pub async fn index_content(db: &Database, item: &Item) {
+        let (path, mimetype) = match &item.file {
+            None => { return }
+            Some(file) => { (Object::data_path(&file.object, db), file.mimetype.plain().unwrap_or_default()) }
+        };
+        let content = tokio::task::spawn_blocking(move || TextExtractor::extract(&path, &mimetype)).await;
+        let result = match content {
+            Ok(Ok(Some(content))) => { DbItem::set_search_content(db, item.id(), &content).await }
+            Ok(Ok(None)) => { Ok(()) }
+            Ok(Err(err)) => { Err(err) }
+            Err(err) => { Err(err.into()) }
+        };
+        if let Err(err) = result {
+            warn!("Failed to index the content of {} : {err}", item.id());
+        }
+    }
+
+    /// Read the metadata of photos, videos and audio files. Failures never reject the upload.
+    pub async fn read_metadata(db: &Database, item: &mut Item) {
+        let (path, mimetype) = match &item.file {
+            None => { return }
+            Some(file) => { (Object::data_path(&file.object, db), file.mimetype.plain().unwrap_or_default()) }
+        };
+        let metadata = match tokio::task::spawn_blocking(move || MetadataExtractor::extract(&path, &mimetype)).await {
+            Ok(Ok(metadata)) => { metadata }
+            Ok(Err(err)) => {
+                warn!("Failed to read the metadata of {} : {err}", item.id());
+                None
             }
+            Err(err) => {
+                warn!("Failed to read the metadata of {} : {err}", item.id());
+                None
+            }
+        };
+        // The metadata of the previous content is removed even if the new one could not be read
+        if let Err(err) = DbItem::set_metadata(db, item.id(), metadata.as_ref()).await {
+            warn!("Failed to store the metadata of {} : {err}", item.id());
         }
+        item.metadata = metadata;
+    }
 
-        let object = Object::insert(db, self.get_file_path().as_path(), &hash).await?;
-        self.file.object = object.id().clone();
-        self.item.file = Some(self.file.clone());
-        DbItem::push(&mut self.item, db).await?;
-        Ok(self.item.clone())
+    /// Item that will be created once the upload is complete
+    pub fn item(&self) -> Item {
+        let mut item = Item::default();
+        item.repository = self.session.repository.clone();
+        item.owner = self.session.owner.clone();
+        item.name = self.session.name.clone();
+        item.description = self.session.description.clone();
+        item.parent_item = self.session.parent_item.clone();
+        item.in_trash = false;
+        item
     }
-    
-    pub fn item(&self) -> &Item {
-        &self.item
+
+    pub fn session(&self) -> &UploadSession {
+        &self.session
+    }
+
+    pub fn session_mut(&mut self) -> &mut UploadSession {
+        &mut self.session
     }
 }
 
@@ -140,4 +306,4 @@ pub struct UploadState {
     pub id: String,
     pub finished: bool,
     pub item: Option<Item>,
-}
\ No newline at end of file
+}
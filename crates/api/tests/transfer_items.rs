@@ -0,0 +1,292 @@
+//! Moving and copying items with the `/item/move/` and `/item/copy/` routes. The tests run against the PostgreSQL server
+//! described by the server configuration file given in the `FILESHARE_TEST_CONFIG` environment variable, each in its
+//! own schema. They are skipped when the variable is not set.
+
+use anyhow::Error;
+use api::app_ctx::AppCtx;
+use api::{RequestContext, RootRoutes};
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use database::item::{DbItem, Trash};
+use database::user::DbUser;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tower::ServiceExt;
+use types::database_ids::{ItemId, ObjectId, RepositoryId, UserId};
+use types::enc_string::EncString;
+use types::item::{DirectoryData, FileData, Item};
+use types::user::User;
+use utils::config::Config;
+
+static SCHEMA_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Two users, each owning a private repository
+struct TestServer {
+    ctx: Arc<AppCtx>,
+    router: Router,
+    schema: String,
+    owner: User,
+    repository: RepositoryId,
+    other: User,
+    other_repository: RepositoryId,
+    object: ObjectId,
+}
+
+impl TestServer {
+    /// None if no test server is configured
+    async fn create() -> Result<Option<Self>, Error> {
+        let Ok(path) = std::env::var("FILESHARE_TEST_CONFIG") else {
+            eprintln!("FILESHARE_TEST_CONFIG is not set : skipped");
+            return Ok(None);
+        };
+        let mut config = Config::from_file(PathBuf::from(path))?;
+        let schema = format!("fileshare_api_test_{}_{}", std::process::id(), SCHEMA_COUNTER.fetch_add(1, Ordering::SeqCst));
+        config.backend_config.postgres.scheme_name = schema.clone();
+        let ctx = Arc::new(AppCtx::new(config).await?);
+        let fixtures = match Self::insert_fixtures(&ctx, &schema).await {
+            Ok(fixtures) => { fixtures }
+            Err(err) => {
+                ctx.database.db().await?.batch_execute(&format!("DROP SCHEMA {schema} CASCADE")).await?;
+                return Err(err);
+            }
+        };
+        let (owner, repository, other, other_repository, object) = fixtures;
+        Ok(Some(Self { router: RootRoutes::create(&ctx)?, ctx, schema, owner, repository, other, other_repository, object }))
+    }
+
+    async fn insert_fixtures(ctx: &AppCtx, schema: &str) -> Result<(User, RepositoryId, User, RepositoryId, ObjectId), Error> {
+        let rows = ctx.database.db().await?.query(&"WITH users AS (
+                INSERT INTO SCHEMA_NAME.users (id, email, name, login, password_hash, user_role) VALUES
+                    (1, 'owner@test', 'owner', 'owner', '', 'admin'), (2, 'other@test', 'other', 'other', '', 'admin') RETURNING id
+            ), repositories AS (
+                INSERT INTO SCHEMA_NAME.repository (url_name, owner, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload)
+                SELECT 'repository' || id, id, 'private', 'repository', 0, 0, false FROM users RETURNING id, owner
+            ), object AS (
+                INSERT INTO SCHEMA_NAME.objects (hash) VALUES ('test') RETURNING id
+            )
+            SELECT repositories.owner, repositories.id AS repository, object.id AS object FROM repositories, object ORDER BY repositories.owner".replace("SCHEMA_NAME", schema), &[]).await?;
+        if rows.len() != 2 {
+            return Err(Error::msg("Failed to create the test repositories"));
+        }
+        let repository = |index: usize| RepositoryId::from(rows[index].get::<&str, i64>("repository"));
+        Ok((
+            DbUser::from_id(&ctx.database, &UserId::from(1)).await?,
+            repository(0),
+            DbUser::from_id(&ctx.database, &UserId::from(2)).await?,
+            repository(1),
+            ObjectId::from(rows[0].get::<&str, i64>("object")),
+        ))
+    }
+
+    async fn push(&self, mut item: Item, repository: &RepositoryId, owner: &User, name: &str, parent: Option<&ItemId>) -> Result<ItemId, Error> {
+        item.repository = repository.clone();
+        item.owner = owner.id().clone();
+        item.name = EncString::encode(name);
+        item.parent_item = parent.cloned();
+        DbItem::push(&mut item, &self.ctx.database).await?;
+        Ok(item.id().clone())
+    }
+
+    async fn directory(&self, name: &str, parent: Option<&ItemId>) -> Result<ItemId, Error> {
+        let mut item = Item::default();
+        item.directory = Some(DirectoryData::default());
+        self.push(item, &self.repository, &self.owner, name, parent).await
+    }
+
+    async fn file_in(&self, repository: &RepositoryId, owner: &User, name: &str, parent: Option<&ItemId>) -> Result<ItemId, Error> {
+        let mut item = Item::default();
+        item.file = Some(FileData { size: 1, mimetype: EncString::encode("text/plain"), timestamp: 0, object: self.object.clone() });
+        self.push(item, repository, owner, name, parent).await
+    }
+
+    async fn file(&self, name: &str, parent: Option<&ItemId>) -> Result<ItemId, Error> {
+        self.file_in(&self.repository, &self.owner, name, parent).await
+    }
+
+    /// Id of the item named `name` in the directory, or at the root of the repository
+    async fn find(&self, repository: &RepositoryId, parent: Option<&ItemId>, name: &str) -> Result<Option<ItemId>, Error> {
+        Ok(DbItem::find_child(&self.ctx.database, repository, &parent.cloned(), &EncString::encode(name)).await?.map(|item| item.id().clone()))
+    }
+
+    async fn exists(&self, item: &ItemId) -> bool {
+        DbItem::from_id(&self.ctx.database, item, Trash::Both).await.is_ok()
+    }
+
+    /// Send a transfer request as `user` (`route` is "move" or "copy"). Returns the status and the transferred items.
+    async fn transfer(&self, user: &User, route: &str, data: Value) -> Result<(StatusCode, Vec<Value>), Error> {
+        let context = RequestContext::default();
+        *context.connected_user_mut().await = Some(user.clone());
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/item/{route}/"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(data.to_string()))?;
+        request.extensions_mut().insert(Arc::new(context));
+        let response = self.router.clone().oneshot(request).await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, if status.is_success() { serde_json::from_slice(&body)? } else { vec![] }))
+    }
+}
+
+/// Run a test in a new schema. The schema is removed even if the test fails.
+async fn run<F, Fut>(test: F)
+where
+    F: FnOnce(Arc<TestServer>) -> Fut,
+    Fut: Future<Output=Result<(), Error>> + Send + 'static,
+{
+    let Some(server) = TestServer::create().await.expect("Failed to create the test server") else {
+        return;
+    };
+    let server = Arc::new(server);
+    let result = tokio::spawn(test(server.clone())).await;
+    server.ctx.database.db().await.expect("Failed to connect").batch_execute(&format!("DROP SCHEMA {} CASCADE", server.schema)).await.expect("Failed to remove the test schema");
+    match result {
+        Ok(result) => { result.expect("Test failed") }
+        Err(err) => { std::panic::resume_unwind(err.into_panic()) }
+    }
+}
+
+#[tokio::test]
+async fn fail_rejects_the_whole_batch() {
+    run(|server| async move {
+        let destination = server.directory("destination", None).await?;
+        let existing = server.file("a.txt", Some(&destination)).await?;
+        let a = server.file("a.txt", None).await?;
+        let b = server.file("b.txt", None).await?;
+
+        let (status, _) = server.transfer(&server.owner, "move", json!({"items": [&b, &a], "parent_item": &destination})).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a));
+        assert_eq!(server.find(&server.repository, None, "b.txt").await?, Some(b));
+        assert_eq!(server.find(&server.repository, Some(&destination), "a.txt").await?, Some(existing));
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn skip_leaves_conflicting_items() {
+    run(|server| async move {
+        let destination = server.directory("destination", None).await?;
+        let existing = server.file("a.txt", Some(&destination)).await?;
+        let a = server.file("a.txt", None).await?;
+        let b = server.file("b.txt", None).await?;
+
+        let (status, moved) = server.transfer(&server.owner, "move", json!({"items": [&a, &b], "parent_item": &destination, "on_conflict": "skip"})).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a));
+        assert_eq!(server.find(&server.repository, Some(&destination), "a.txt").await?, Some(existing));
+        assert_eq!(server.find(&server.repository, Some(&destination), "b.txt").await?, Some(b));
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn rename_picks_a_free_name() {
+    run(|server| async move {
+        let destination = server.directory("destination", None).await?;
+        let existing = server.file("a.txt", Some(&destination)).await?;
+        server.file("a (1).txt", Some(&destination)).await?;
+        let a = server.file("a.txt", None).await?;
+
+        let (status, copied) = server.transfer(&server.owner, "copy", json!({"items": [&a], "parent_item": &destination, "on_conflict": "rename"})).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(copied.len(), 1);
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a));
+        assert_eq!(server.find(&server.repository, Some(&destination), "a.txt").await?, Some(existing));
+        assert!(server.find(&server.repository, Some(&destination), "a (2).txt").await?.is_some());
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn overwrite_replaces_the_existing_item() {
+    run(|server| async move {
+        let destination = server.directory("destination", None).await?;
+        let existing = server.file("a.txt", Some(&destination)).await?;
+        let a = server.file("a.txt", None).await?;
+
+        let (status, moved) = server.transfer(&server.owner, "move", json!({"items": [&a], "parent_item": &destination, "on_conflict": "overwrite"})).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved.len(), 1);
+        assert!(!server.exists(&existing).await);
+        assert_eq!(server.find(&server.repository, Some(&destination), "a.txt").await?, Some(a));
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, None);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn overwrite_is_cancelled_when_the_batch_fails() {
+    run(|server| async move {
+        let destination = server.directory("destination", None).await?;
+        let existing = server.file("a.txt", Some(&destination)).await?;
+        let a = server.file("a.txt", None).await?;
+        // Moving 'x' to the destination would overwrite its own parent
+        let parent = server.directory("x", Some(&destination)).await?;
+        let x = server.file("x", Some(&parent)).await?;
+
+        let (status, _) = server.transfer(&server.owner, "move", json!({"items": [&a, &x], "parent_item": &destination, "on_conflict": "overwrite"})).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(server.exists(&existing).await);
+        assert_eq!(server.find(&server.repository, Some(&destination), "a.txt").await?, Some(existing));
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a));
+        assert_eq!(server.find(&server.repository, Some(&parent), "x").await?, Some(x));
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn cannot_transfer_a_directory_inside_itself() {
+    run(|server| async move {
+        let directory = server.directory("directory", None).await?;
+        let child = server.directory("child", Some(&directory)).await?;
+
+        for route in ["move", "copy"] {
+            for destination in [&directory, &child] {
+                let (status, _) = server.transfer(&server.owner, route, json!({"items": [&directory], "parent_item": destination})).await?;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{route} to {destination}");
+            }
+        }
+        assert_eq!(server.find(&server.repository, None, "directory").await?, Some(directory.clone()));
+        assert_eq!(server.find(&server.repository, Some(&directory), "child").await?, Some(child.clone()));
+        assert_eq!(server.find(&server.repository, Some(&child), "directory").await?, None);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn cross_repository_permissions() {
+    run(|server| async move {
+        let a = server.file("a.txt", None).await?;
+        let theirs = server.file_in(&server.other_repository, &server.other, "theirs.txt", None).await?;
+
+        // The destination repository does not accept uploads from the owner
+        for route in ["move", "copy"] {
+            let (status, _) = server.transfer(&server.owner, route, json!({"items": [&a], "repository": server.other_repository})).await?;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{route}");
+        }
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a.clone()));
+        assert_eq!(server.find(&server.other_repository, None, "a.txt").await?, None);
+
+        // The items of a private repository cannot be taken by another user
+        let (status, _) = server.transfer(&server.other, "move", json!({"items": [&a], "repository": server.other_repository})).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, copied) = server.transfer(&server.other, "copy", json!({"items": [&a], "repository": server.other_repository})).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(copied.is_empty());
+        assert_eq!(server.find(&server.repository, None, "a.txt").await?, Some(a.clone()));
+        assert_eq!(server.find(&server.other_repository, None, "a.txt").await?, None);
+
+        // Each user can move their own items in their own repository
+        let (status, moved) = server.transfer(&server.other, "move", json!({"items": [&theirs], "repository": server.other_repository, "on_conflict": "rename"})).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved.len(), 1);
+        Ok(())
+    }).await
+}
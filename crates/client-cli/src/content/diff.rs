@@ -56,9 +56,11 @@ pub enum Action {
 
 impl Diff {
     pub async fn from_repository(repository: &mut Repository) -> Result<Self, Error> {
-        Diff::new(&*repository.scan_local_content()?.read().unwrap(),
-                  &*repository.fetch_local_content()?.read().unwrap(),
-                  &*repository.fetch_remote_content().await?.read().unwrap())
+        let remote_content = repository.fetch_remote_content().await?;
+        let diff = Diff::new(&*repository.scan_local_content()?.read().unwrap(),
+                             &*repository.fetch_local_content()?.read().unwrap(),
+                             &*remote_content.read().unwrap());
+        diff
     }
 
     pub fn new(scanned: &dyn Filesystem, local: &dyn Filesystem, remote: &dyn Filesystem) -> Result<Self, Error> {
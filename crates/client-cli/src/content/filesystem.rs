@@ -10,7 +10,7 @@ use types::database_ids::ItemId;
 
 pub trait Filesystem {
     fn get_roots(&self) -> Result<Vec<Arc<RwLock<dyn Item>>>, Error>;
-    fn find_from_path(&self, path: &PathBuf) -> Result<Option<Arc<RwLock<dyn Item>>>, Error>;
+    fn find_from_path(&self, path: &Path) -> Result<Option<Arc<RwLock<dyn Item>>>, Error>;
 }
 
 #[derive(Default, Debug)]
@@ -53,7 +53,7 @@ impl RemoteFilesystem {
         Ok(children)
     }
 
-    fn find_from_path_internal(&self, path: &PathBuf, items: &HashSet<ItemId>) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
+    fn find_from_path_internal(&self, path: &Path, items: &HashSet<ItemId>) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
         let mut path_iter = path.iter();
         let mut p = path_iter.next().ok_or(Error::msg("Empty path"))?;
 
@@ -99,7 +99,7 @@ impl Filesystem for RemoteFilesystem {
         Ok(roots)
     }
 
-    fn find_from_path(&self, path: &PathBuf) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
+    fn find_from_path(&self, path: &Path) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
         self.find_from_path_internal(path, &self.roots)
     }
 }
@@ -156,7 +156,7 @@ impl LocalFilesystem {
         Ok(())
     }
 
-    fn find_from_path_internal(path: &PathBuf, items: &Vec<Arc<RwLock<LocalItem>>>) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
+    fn find_from_path_internal(path: &Path, items: &Vec<Arc<RwLock<LocalItem>>>) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
         let mut path_iter = path.iter();
         let mut p = path_iter.next().ok_or(Error::msg("Empty path"))?;
 
@@ -180,7 +180,8 @@ impl LocalFilesystem {
         Ok(None)
     }
 
-    pub async fn remove_item(&mut self, root_path: &Path, item: &LocalItem) -> Result<(), Error> {
+    /// Delete the file or directory of the item. The item itself is removed from the tree by [`Self::remove_item`].
+    pub async fn remove_from_disk(root_path: &Path, item: &LocalItem) -> Result<(), Error> {
         let item_path = root_path.join(item.path_from_root()?);
         if item_path.exists() {
             if item_path.is_file() {
@@ -189,7 +190,10 @@ impl LocalFilesystem {
                 fs::remove_dir_all(item_path).await?;
             }
         }
+        Ok(())
+    }
 
+    pub fn remove_item(&mut self, item: &LocalItem) -> Result<(), Error> {
         match item.get_parent()? {
             None => {
                 for (i, root) in self.roots.iter().enumerate() {
@@ -241,7 +245,7 @@ impl Filesystem for LocalFilesystem {
         Ok(roots)
     }
 
-    fn find_from_path(&self, path: &PathBuf) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
+    fn find_from_path(&self, path: &Path) -> Result<Option<Arc<RwLock<dyn Item>>>, Error> {
         Self::find_from_path_internal(path, &self.roots)
     }
 }
\ No newline at end of file
@@ -148,7 +148,7 @@ impl Item for RemoteItem {
                     .read() {
                     Ok(filesystem) => {
                         let mut children = vec![];
-                        for child in &filesystem.get_children(&self.id())? {
+                        for child in &filesystem.get_children(self.id())? {
                             let item: Arc<RwLock<dyn Item>> = child.clone();
 
                             children.push(item);
@@ -20,12 +20,6 @@ use tokio_util::io::ReaderStream;
 use types::enc_string::EncString;
 use types::item::CreateDirectoryParams;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct AuthToken {
-    token: String,
-    expiration_date: u64,
-}
-
 #[derive(Serialize, Deserialize, Default)]
 pub struct Repository {
     #[serde(default = "empty_connection")]
@@ -83,12 +77,29 @@ impl Repository {
             None => {}
             Some(remote_content) => { return Ok(remote_content.clone()); }
         }
-        let response = self.connection.get(format!("/repository/content/{}/", self.connection.remote_id()?)).await?.send().await?;
+        #[derive(Deserialize)]
+        struct ItemPage {
+            items: Vec<RemoteItem>,
+            next_cursor: Option<String>,
+        }
 
-        let data = self.connection.parse_result(response).await?
-            .error_for_status()?
-            .text().await?;
-        let mut content: Vec<RemoteItem> = serde_json::from_str(data.as_str())?;
+        let mut content: Vec<RemoteItem> = vec![];
+        let mut cursor = None;
+        loop {
+            let mut request = self.connection.get(format!("/repository/content/{}/", self.connection.remote_id()?)).await?;
+            if let Some(cursor) = &cursor {
+                request = request.query(&[("cursor", cursor)]);
+            }
+            let data = self.connection.parse_result(request.send().await?).await?
+                .error_for_status()?
+                .text().await?;
+            let mut page: ItemPage = serde_json::from_str(data.as_str())?;
+            content.append(&mut page.items);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
         let filesystem = Arc::new(RwLock::new(RemoteFilesystem::default()));
         for item in &mut content {
             item.set_filesystem(&filesystem);
@@ -136,55 +147,55 @@ impl Repository {
             match items_to_download.pop() {
                 None => { return Err(Error::msg("Invalid behavior")); }
                 Some(item) => {
-                    match item.read() {
-                        Ok(item) => {
-                            let item = item.cast::<RemoteItem>();
-                            if item.is_regular_file() {
-                                let downloaded_path = self.connection.metadata_directory().tmp_download_dir()?.join(format!("download_{}", item.id()).as_str());
-                                let mut data_file = File::create(downloaded_path.clone())?;
-                                match self.download_file(item, &mut data_file).await {
-                                    Ok(_) => {
-                                        let final_path = self.connection.metadata_directory().root()?.join(item.path_from_root()?);
-
-                                        fs::rename(downloaded_path, final_path.clone())?;
-
-                                        let timestamp = item.timestamp();
-                                        File::options().write(true).open(final_path)?.set_modified(UNIX_EPOCH.add(Duration::from_millis(timestamp)))?;
-                                        let root = self.connection.metadata_directory().root()?.clone();
-                                        self.update_local_item_state(&root, item as &dyn Item)?;
-                                    }
-                                    Err(err) => {
-                                        if downloaded_path.exists() {
-                                            fs::remove_file(downloaded_path)?;
-                                        }
-                                        return Err(err);
-                                    }
-                                }
-                            } else {
-                                let remote_content = self.fetch_remote_content().await?.clone();
-                                match remote_content.read().unwrap().find_from_path(&item.path_from_root()?)? {
-                                    None => {}
-                                    Some(remote_item_data) => {
-                                        let dir_path = self.connection.metadata_directory().root()?.join(item.path_from_root()?);
-                                        if dir_path.exists() {
-                                            if !dir_path.metadata()?.is_dir() {
-                                                error!("Cannot create directory {} : a file with the same name already exists !", dir_path.display());
-                                            }
-                                        } else {
-                                            fs::create_dir(dir_path.clone())?;
-                                        }
-                                        let root = self.connection.metadata_directory().root()?.clone();
-                                        self.update_local_item_state(&root, item as &dyn Item)?;
-                                        for child in remote_item_data.read().unwrap().get_children()? {
-                                            items_to_download.push(child);
-                                        }
-                                    }
-                                };
-                            }
-                        }
+                    // Work on a copy : the lock must not be held while downloading
+                    let item = match item.read() {
+                        Ok(item) => { item.cast::<RemoteItem>().clone() }
                         Err(err) => {
                             return Err(Error::msg(format!("Poison error : {}", err)));
                         }
+                    };
+                    let item = &item;
+                    if item.is_regular_file() {
+                        let downloaded_path = self.connection.metadata_directory().tmp_download_dir()?.join(format!("download_{}", item.id()).as_str());
+                        let mut data_file = File::create(downloaded_path.clone())?;
+                        match self.download_file(item, &mut data_file).await {
+                            Ok(_) => {
+                                let final_path = self.connection.metadata_directory().root()?.join(item.path_from_root()?);
+
+                                fs::rename(downloaded_path, final_path.clone())?;
+
+                                let timestamp = item.timestamp();
+                                File::options().write(true).open(final_path)?.set_modified(UNIX_EPOCH.add(Duration::from_millis(timestamp)))?;
+                                let root = self.connection.metadata_directory().root()?.clone();
+                                self.update_local_item_state(&root, item as &dyn Item)?;
+                            }
+                            Err(err) => {
+                                if downloaded_path.exists() {
+                                    fs::remove_file(downloaded_path)?;
+                                }
+                                return Err(err);
+                            }
+                        }
+                    } else {
+                        let remote_content = self.fetch_remote_content().await?.clone();
+                        match remote_content.read().unwrap().find_from_path(&item.path_from_root()?)? {
+                            None => {}
+                            Some(remote_item_data) => {
+                                let dir_path = self.connection.metadata_directory().root()?.join(item.path_from_root()?);
+                                if dir_path.exists() {
+                                    if !dir_path.metadata()?.is_dir() {
+                                        error!("Cannot create directory {} : a file with the same name already exists !", dir_path.display());
+                                    }
+                                } else {
+                                    fs::create_dir(dir_path.clone())?;
+                                }
+                                let root = self.connection.metadata_directory().root()?.clone();
+                                self.update_local_item_state(&root, item as &dyn Item)?;
+                                for child in remote_item_data.read().unwrap().get_children()? {
+                                    items_to_download.push(child);
+                                }
+                            }
+                        };
                     }
                 }
             }
@@ -257,20 +268,18 @@ impl Repository {
     pub async fn upload_item(&mut self, item_ref: Arc<RwLock<dyn Item>>) -> Result<(), Error> {
         let mut items_to_upload = vec![item_ref];
         while let Some(item_ref) = items_to_upload.pop() {
-            match item_ref.read() {
-                Ok(item) => {
-                    let item = item.cast::<LocalItem>();
-
-                    if item.is_regular_file() {
-                        self.upload_file(item).await?;
-                    } else {
-                        self.create_remote_dir(item).await?;
-                        for child in item.get_children()? {
-                            items_to_upload.push(child);
-                        }
-                    }
-                }
+            // Work on a copy : the lock must not be held while uploading
+            let item = match item_ref.read() {
+                Ok(item) => { item.cast::<LocalItem>().clone() }
                 Err(err) => { return Err(Error::msg(format!("{}", err))) }
+            };
+            if item.is_regular_file() {
+                self.upload_file(&item).await?;
+            } else {
+                self.create_remote_dir(&item).await?;
+                for child in item.get_children()? {
+                    items_to_upload.push(child);
+                }
             }
             let root = self.connection.metadata_directory().root()?.clone();
             self.update_local_item_state(&root, &*item_ref.read().unwrap())?;
@@ -299,27 +308,27 @@ impl Repository {
             }
             Some(parent) => {
                 let remote_content = self.fetch_remote_content().await?;
-                let mut remote_content = remote_content.write().unwrap();
-                match remote_content.find_from_path(&parent.read().unwrap().path_from_root()?)? {
+                let parent_path = parent.read().unwrap().path_from_root()?;
+                let remote_parent = match remote_content.read().unwrap().find_from_path(&parent_path)? {
                     None => {
                         return Err(Error::msg("Failed to find parent item from path"));
                     }
-                    Some(remote_parent) => {
-                        let dir_data = CreateDirectoryParams {
-                            name: item.name(),
-                            repository: self.connection.remote_id()?,
-                            parent_item: Some(remote_parent.read().unwrap().cast::<RemoteItem>().id().clone()),
-                        };
+                    Some(remote_parent) => { remote_parent.read().unwrap().cast::<RemoteItem>().id().clone() }
+                };
+                let dir_data = CreateDirectoryParams {
+                    name: item.name(),
+                    repository: self.connection.remote_id()?,
+                    parent_item: Some(remote_parent),
+                };
 
-                        let result = self.connection.post("/item/new-directory/".to_string()).await?
-                            .json(&vec![dir_data])
-                            .send().await?;
-                        let new_dirs: Vec<RemoteItem> = self.connection.parse_result(result).await?.json().await?;
+                let result = self.connection.post("/item/new-directory/".to_string()).await?
+                    .json(&vec![dir_data])
+                    .send().await?;
+                let new_dirs: Vec<RemoteItem> = self.connection.parse_result(result).await?.json().await?;
 
-                        for dir in new_dirs {
-                            remote_content.add_item(Arc::new(RwLock::new(dir)));
-                        }
-                    }
+                let mut remote_content = remote_content.write().unwrap();
+                for dir in new_dirs {
+                    remote_content.add_item(Arc::new(RwLock::new(dir)));
                 }
             }
         }
@@ -430,16 +439,18 @@ impl Repository {
 
     async fn remove_local_item(&mut self, item_ref: &Arc<RwLock<dyn Item>>) -> Result<(), Error> {
         if let Ok(local_filesystem) = self.fetch_local_content() {
-            let local_filesystem = &mut *local_filesystem.write().unwrap();
+            let item = item_ref.read().unwrap().cast::<LocalItem>().clone();
             let root = self.connection.metadata_directory().root()?.clone();
-            local_filesystem.remove_item(&root, item_ref.read().unwrap().cast::<LocalItem>()).await?;
+            LocalFilesystem::remove_from_disk(&root, &item).await?;
+            local_filesystem.write().unwrap().remove_item(&item)?;
         }
         Ok(())
     }
 
     async fn remove_remote_item(&mut self, scanned_ref: &Arc<RwLock<dyn Item>>, remote_ref: &Arc<RwLock<dyn Item>>) -> Result<(), Error> {
+        let remote_id = remote_ref.read().unwrap().cast::<RemoteItem>().id().clone();
         let result = self.connection.post(format!("{}move-to-trash/", "todo"))
-            .await?.json(&vec![remote_ref.read().unwrap().cast::<RemoteItem>().id()])
+            .await?.json(&vec![remote_id])
             .send().await?;
         self.connection_mut().parse_result(result).await?;
         self.remove_local_item(scanned_ref).await?;
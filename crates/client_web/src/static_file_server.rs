@@ -1,10 +1,8 @@
 use axum::extract::{Request, State};
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 use std::path::PathBuf;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
 
 pub struct StaticFileServer {}
 
@@ -14,8 +12,8 @@ pub struct StaticRouterConfig {
 }
 
 
-use axum::body::{Body};
-use axum::http::{header, StatusCode};
+use axum::http::{HeaderMap, Method, StatusCode};
+use utils::file_response::FileResponse;
 use utils::server_error::ServerError;
 
 impl StaticFileServer {
@@ -29,22 +27,20 @@ impl StaticFileServer {
     }
 
     pub async fn serve_file_from_path(file_path: PathBuf) -> Result<impl IntoResponse, ServerError> {
+        Self::serve_file_with_request(file_path, &Method::GET, &HeaderMap::new()).await
+    }
+
+    /// Serve a file, honoring range and conditional headers of the request
+    pub async fn serve_file_with_request(file_path: PathBuf, method: &Method, headers: &HeaderMap) -> Result<Response, ServerError> {
         if file_path.exists() {
             let file_name = file_path.file_name().unwrap().to_str().unwrap().to_string();
             let mime_type = match mime_guess::from_path(file_path.clone()).first_raw() {
                 None => { "application/octet-stream" }
                 Some(mime_type) => { mime_type }
             };
-            let file = File::open(file_path).await?;
-
-            let stream = ReaderStream::new(file);
-            let body = Body::from_stream(stream);
-            let headers = [
-                (header::CONTENT_TYPE, mime_type),
-                (header::CONTENT_DISPOSITION, &format!("attachment; filename=\"{}\"", file_name))
-            ];
-
-            Ok((headers, body).into_response())
+            FileResponse::new(file_path, mime_type)
+                .disposition(format!("attachment; filename=\"{}\"", file_name))
+                .respond(method, headers).await
         } else {
             Err(ServerError::msg(StatusCode::NOT_FOUND, format!("File not found ! (searching {})", file_path.display())))
         }
@@ -56,6 +52,6 @@ impl StaticFileServer {
             return Err(ServerError::msg(StatusCode::UNAUTHORIZED, "Cannot access elements outside public directory"));
         }
 
-        Self::serve_file_from_path(file_path).await
+        Self::serve_file_with_request(file_path, request.method(), request.headers()).await
     }
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Rename, move and delete of trees with many descendants. Like the integration tests, they need `FILESHARE_TEST_CONFIG`
+//! and are skipped without it.
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::TestDatabase;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput};
+use database::item::{DbItem, Trash};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use types::enc_string::EncString;
+
+/// Number of files of each tree
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const FILES_PER_DIRECTORY: usize = 100;
+
+fn large_trees(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to start the runtime");
+    let Some(database) = runtime.block_on(TestDatabase::create()).expect("Failed to create the test database") else {
+        return;
+    };
+    // Deleting the trees would otherwise remove their object, that the next ones are still using
+    runtime.block_on(database.file("object", None, 1)).expect("Failed to keep the object");
+    let parents = runtime.block_on(async {
+        [database.directory("parent_a", None).await, database.directory("parent_b", None).await]
+    }).map(|parent| parent.expect("Failed to create the parent directories"));
+
+    let mut group = c.benchmark_group("large_trees");
+    group.sample_size(10).sampling_mode(SamplingMode::Flat);
+    for files in SIZES {
+        group.throughput(Throughput::Elements(files as u64));
+        let directories = files / FILES_PER_DIRECTORY;
+        let tree = runtime.block_on(database.tree("tree", None, directories, FILES_PER_DIRECTORY)).expect("Failed to create the tree");
+
+        // Criterion calls the routines once per sample : the counter must outlive them so that no iteration is a no-op
+        let mut count = 0;
+        group.bench_with_input(BenchmarkId::new("rename", files), &tree, |b, tree| {
+            b.to_async(&runtime).iter(|| {
+                count += 1;
+                let name = EncString::encode(&format!("tree{count}"));
+                let database = &database;
+                async move { DbItem::move_to(&database.db, tree, &None, &database.repository, &name).await.expect("Failed to rename the tree") }
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("move", files), &tree, |b, tree| {
+            b.to_async(&runtime).iter(|| {
+                count += 1;
+                let parent = Some(parents[count % 2].clone());
+                let database = &database;
+                async move { DbItem::move_to(&database.db, tree, &parent, &database.repository, &EncString::encode("tree")).await.expect("Failed to move the tree") }
+            });
+        });
+
+        let item = runtime.block_on(DbItem::from_id(&database.db, &tree, Trash::Both)).expect("Failed to find the tree");
+        runtime.block_on(DbItem::delete(&item, &database.db)).expect("Failed to delete the tree");
+
+        // Only the deletion is measured : a new tree is created before each of them
+        group.bench_function(BenchmarkId::new("delete", files), |b| {
+            b.to_async(&runtime).iter_custom(|iters| {
+                let database = &database;
+                async move {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let tree = database.tree("deleted", None, directories, FILES_PER_DIRECTORY).await.expect("Failed to create the tree");
+                        let item = DbItem::from_id(&database.db, &tree, Trash::Both).await.expect("Failed to find the tree");
+                        let start = Instant::now();
+                        DbItem::delete(&item, &database.db).await.expect("Failed to delete the tree");
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                }
+            });
+        });
+    }
+    group.finish();
+    runtime.block_on(database.drop_schema()).expect("Failed to remove the test schema");
+}
+
+criterion_group!(benches, large_trees);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Embed the sql files of the migrations directory into the binary
+fn main() {
+    let migrations_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../../migrations").canonicalize().expect("Failed to find migrations directory");
+    println!("cargo:rerun-if-changed={}", migrations_dir.display());
+
+    let mut files = vec![];
+    for entry in fs::read_dir(&migrations_dir).expect("Failed to read migrations directory") {
+        let path = entry.expect("Failed to read migration").path();
+        if path.is_file() && path.extension().and_then(std::ffi::OsStr::to_str) == Some("sql") {
+            println!("cargo:rerun-if-changed={}", path.display());
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut code = String::from("pub const MIGRATION_FILES: &[(&str, &str)] = &[\n");
+    for file in files {
+        code += &format!("    ({:?}, include_str!({:?})),\n", file.file_name().unwrap().to_str().unwrap(), file.display().to_string());
+    }
+    code += "];\n";
+    fs::write(PathBuf::from(env::var("OUT_DIR").unwrap()).join("migrations.rs"), code).expect("Failed to write embedded migrations");
+}
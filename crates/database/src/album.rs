@@ -0,0 +1,95 @@
+use crate::Database;
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use std::collections::HashSet;
+use types::album::Album;
+use types::database_ids::{AlbumId, ItemId, RepositoryId};
+use types::enc_string::EncString;
+use types::item::Item;
+
+/// Albums with their cover (the first visible item if none was chosen) and their number of visible items
+const ALBUM_QUERY: &str = "SELECT albums.id, albums.repository, albums.owner, albums.name, albums.description, albums.created_at,
+    COALESCE(albums.cover, (SELECT album_items.item FROM SCHEMA_NAME.album_items JOIN SCHEMA_NAME.items ON items.id = album_items.item
+        WHERE album_items.album = albums.id AND NOT items.in_trash ORDER BY album_items.position, album_items.item LIMIT 1)) AS cover,
+    (SELECT COUNT(*) FROM SCHEMA_NAME.album_items JOIN SCHEMA_NAME.items ON items.id = album_items.item
+        WHERE album_items.album = albums.id AND NOT items.in_trash) AS num_items
+    FROM SCHEMA_NAME.albums";
+
+pub struct DbAlbum;
+
+impl DbAlbum {
+    pub async fn from_id(db: &Database, id: &AlbumId) -> Result<Album, Error> {
+        Self::find(db, id).await?.ok_or(Error::msg("Album not found"))
+    }
+
+    /// The album, or None if it does not exist
+    pub async fn find(db: &Database, id: &AlbumId) -> Result<Option<Album>, Error> {
+        Ok(query_object!(db, Album, format!("{ALBUM_QUERY} WHERE albums.id = $1"), id))
+    }
+
+    /// Albums of a repository, most recent first
+    pub async fn from_repository(db: &Database, repository: &RepositoryId) -> Result<Vec<Album>, Error> {
+        Ok(query_objects!(db, Album, format!("{ALBUM_QUERY} WHERE albums.repository = $1 ORDER BY albums.created_at DESC, albums.id DESC"), repository))
+    }
+
+    pub async fn push(db: &Database, album: &mut Album) -> Result<(), Error> {
+        let id = query_object!(db, AlbumId, "INSERT INTO SCHEMA_NAME.albums (repository, owner, name, description) VALUES ($1, $2, $3, $4) RETURNING id",
+            album.repository, album.owner, album.name, album.description).ok_or(Error::msg("Failed to insert album"))?;
+        *album = Self::from_id(db, &id).await?;
+        Ok(())
+    }
+
+    /// Rename the album or change its cover. The cover must be an item of the album, or None to use the first item.
+    pub async fn update(db: &Database, id: &AlbumId, name: &EncString, description: &Option<EncString>, cover: &Option<ItemId>) -> Result<Album, Error> {
+        if let Some(cover) = cover {
+            if query_fmt!(db, "SELECT 1 FROM SCHEMA_NAME.album_items WHERE album = $1 AND item = $2", id, cover).is_empty() {
+                return Err(Error::msg("The cover must be an item of the album"));
+            }
+        }
+        query_fmt!(db, "UPDATE SCHEMA_NAME.albums SET name = $2, description = $3, cover = $4 WHERE id = $1", id, name, description, cover);
+        Self::from_id(db, id).await
+    }
+
+    /// The items are referenced by the album : removing an album doesn't remove its files
+    pub async fn delete(db: &Database, id: &AlbumId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.albums WHERE id = $1", id);
+        Ok(())
+    }
+
+    /// Items of the album in their order. Items in the trash are hidden.
+    pub async fn items(db: &Database, id: &AlbumId) -> Result<Vec<Item>, Error> {
+        Ok(query_objects!(db, Item, "SELECT item_full_view.* FROM SCHEMA_NAME.item_full_view JOIN SCHEMA_NAME.album_items ON album_items.item = item_full_view.id
+            WHERE album_items.album = $1 AND NOT item_full_view.in_trash ORDER BY album_items.position, album_items.item", id))
+    }
+
+    /// Append items at the end of the album. Items that are already part of it keep their position.
+    pub async fn add_items(db: &Database, id: &AlbumId, items: Vec<ItemId>) -> Result<(), Error> {
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.album_items (album, item, position)
+            SELECT $1, added.item, (COALESCE((SELECT MAX(position) FROM SCHEMA_NAME.album_items WHERE album = $1), -1) + added.index)::INTEGER
+            FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS added(item, index)
+            ON CONFLICT(album, item) DO NOTHING", id, items);
+        Ok(())
+    }
+
+    pub async fn remove_items(db: &Database, id: &AlbumId, items: Vec<ItemId>) -> Result<(), Error> {
+        let transaction = db.begin().await?;
+        query_fmt!(transaction, "DELETE FROM SCHEMA_NAME.album_items WHERE album = $1 AND item = ANY($2)", id, items);
+        query_fmt!(transaction, "UPDATE SCHEMA_NAME.albums SET cover = NULL WHERE id = $1 AND cover = ANY($2)", id, items);
+        transaction.commit().await
+    }
+
+    /// Move the given items to the beginning of the album, in the given order. The other items follow them.
+    pub async fn reorder(db: &Database, id: &AlbumId, items: Vec<ItemId>) -> Result<(), Error> {
+        let transaction = db.begin().await?;
+        let current = query_objects!(transaction, ItemId, "SELECT item AS id FROM SCHEMA_NAME.album_items WHERE album = $1 ORDER BY position, item FOR UPDATE", id);
+        let existing: HashSet<ItemId> = current.iter().cloned().collect();
+        let mut seen = HashSet::new();
+        let ordered: Vec<ItemId> = items.into_iter().chain(current)
+            .filter(|item| existing.contains(item) && seen.insert(item.clone())).collect();
+        query_fmt!(transaction, "UPDATE SCHEMA_NAME.album_items SET position = (ordered.index - 1)::INTEGER
+            FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS ordered(item, index)
+            WHERE album_items.album = $1 AND album_items.item = ordered.item", id, ordered);
+        transaction.commit().await
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use crate::async_zip::collect_items;
+use crate::object::Object;
+use anyhow::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+use types::database_ids::ItemId;
+use types::item::Item;
+use crate::Database;
+
+const BLOCK_SIZE: usize = 512;
+/// Largest size that fits in the 11 octal digits of the ustar size field
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+
+/// Truncate to at most max_len bytes without splitting a character
+fn truncate(string: &str, max_len: usize) -> &str {
+    let mut len = string.len().min(max_len);
+    while !string.is_char_boundary(len) {
+        len -= 1;
+    }
+    &string[..len]
+}
+
+/// Streamed tar archive (ustar with pax extended headers for long names and huge files),
+/// optionally compressed with zstd.
+pub struct AsyncDirectoryTar {
+    items: HashMap<ItemId, Item>,
+    zstd: bool,
+}
+
+impl Default for AsyncDirectoryTar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncDirectoryTar {
+    pub fn new() -> Self {
+        Self {
+            items: HashMap::default(),
+            zstd: false,
+        }
+    }
+
+    /// Compress the archive with zstd. The archive size cannot be known in advance in this mode.
+    pub fn set_zstd(&mut self, zstd: bool) {
+        self.zstd = zstd;
+    }
+
+    pub async fn push_item(&mut self, db: &Database, item: Item) -> Result<(), Error> {
+        collect_items(db, item, &mut self.items).await
+    }
+
+    /// Items sorted by path so that the archive content is deterministic
+    fn sorted_items(&self) -> Result<Vec<(String, &Item)>, Error> {
+        let mut items = vec![];
+        for item in self.items.values() {
+            items.push((Self::format_item_name(item)?, item));
+        }
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(items)
+    }
+
+    fn format_item_name(item: &Item) -> Result<String, Error> {
+        let mut item_name = item.absolute_path.plain()?;
+        item_name.remove(0);
+        if item.directory.is_some() { item_name += "/" };
+        Ok(item_name)
+    }
+
+    fn item_size(item: &Item) -> u64 {
+        if let Some(file) = &item.file {
+            file.size as u64
+        } else { 0 }
+    }
+
+    fn padding(size: u64) -> usize {
+        ((BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64) as usize
+    }
+
+    /// Split a path in the ustar (prefix, name) fields
+    fn split_name(name: &str) -> Option<(&str, &str)> {
+        if name.len() <= 100 {
+            return Some(("", name));
+        }
+        let trimmed = name.trim_end_matches('/');
+        for (index, _) in trimmed.match_indices('/') {
+            if index <= 155 && name.len() - index - 1 <= 100 && index > 0 {
+                return Some((&name[..index], &name[index + 1..]));
+            }
+        }
+        None
+    }
+
+    /// Pax extended header records (`<length> <key>=<value>\n`, the length including itself)
+    fn pax_record(key: &str, value: &str) -> Vec<u8> {
+        let content_len = key.len() + value.len() + 3;
+        let mut len = content_len + 1;
+        while (content_len + len.to_string().len()) != len {
+            len = content_len + len.to_string().len();
+        }
+        format!("{len} {key}={value}\n").into_bytes()
+    }
+
+    fn write_octal(field: &mut [u8], value: u64) {
+        let digits = field.len() - 1;
+        let formatted = format!("{:0width$o}", value, width = digits);
+        field[..digits].copy_from_slice(&formatted.as_bytes()[formatted.len() - digits..]);
+        field[digits] = 0;
+    }
+
+    fn header(name: &str, size: u64, mtime: u64, type_flag: u8) -> [u8; BLOCK_SIZE] {
+        let mut header = [0u8; BLOCK_SIZE];
+        let (prefix, name) = Self::split_name(name).unwrap_or(("", truncate(name, 100)));
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        Self::write_octal(&mut header[100..108], if type_flag == b'5' { 0o755 } else { 0o644 });
+        Self::write_octal(&mut header[108..116], 0);
+        Self::write_octal(&mut header[116..124], 0);
+        Self::write_octal(&mut header[124..136], size.min(USTAR_MAX_SIZE));
+        Self::write_octal(&mut header[136..148], mtime.min(USTAR_MAX_SIZE));
+        header[156] = type_flag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // The checksum is computed with the checksum field filled with spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = header.iter().map(|byte| *byte as u64).sum();
+        Self::write_octal(&mut header[148..155], checksum);
+        header[155] = b' ';
+        header
+    }
+
+    /// All the headers of an entry (pax extended header if required, then the ustar header)
+    fn entry_headers(name: &str, item: &Item) -> Vec<u8> {
+        let size = Self::item_size(item);
+        let mtime = item.file.as_ref().map(|file| (file.timestamp / 1000).max(0) as u64).unwrap_or_default();
+        let type_flag = if item.directory.is_some() { b'5' } else { b'0' };
+
+        let mut records = vec![];
+        if Self::split_name(name).is_none() {
+            records.extend(Self::pax_record("path", name));
+        }
+        if size > USTAR_MAX_SIZE {
+            records.extend(Self::pax_record("size", size.to_string().as_str()));
+        }
+
+        let mut headers = vec![];
+        if !records.is_empty() {
+            let pax_name = format!("PaxHeaders/{}", name.trim_end_matches('/'));
+            headers.extend(Self::header(truncate(&pax_name, 100), records.len() as u64, mtime, b'x'));
+            headers.extend(&records);
+            headers.extend(vec![0u8; Self::padding(records.len() as u64)]);
+        }
+        headers.extend(Self::header(name, size, mtime, type_flag));
+        headers
+    }
+
+    /// Returns None when zstd compression is enabled.
+    pub fn size(&self) -> Result<Option<usize>, Error> {
+        if self.zstd {
+            return Ok(None);
+        }
+        let mut size = 0usize;
+        for (name, item) in self.sorted_items()? {
+            size += Self::entry_headers(&name, item).len();
+            size += Self::item_size(item) as usize + Self::padding(Self::item_size(item));
+        }
+        size += BLOCK_SIZE * 2;
+        Ok(Some(size))
+    }
+
+    /// Write the archive to the sink. Reading and compressing the files is done on a blocking thread.
+    pub async fn finalize<S: AsyncWrite + Unpin + Send + 'static>(self, db: &Database, sink: S) -> Result<(), Error> {
+        let mut data_paths = HashMap::new();
+        for item in self.items.values() {
+            if let Some(file) = &item.file {
+                let object = Object::from_id(db, &file.object).await?;
+                data_paths.insert(item.id().clone(), Object::data_path(object.id(), db));
+            }
+        }
+        let sink = SyncIoBridge::new(sink);
+        tokio::task::spawn_blocking(move || self.write_entries(&data_paths, sink)).await?
+    }
+
+    /// Write the archive, reading the content of the files from `data_paths`
+    fn write_entries<W: Write>(&self, data_paths: &HashMap<ItemId, PathBuf>, sink: W) -> Result<(), Error> {
+        let mut writer = TarWriter::new(sink, self.zstd)?;
+        for (name, item) in self.sorted_items()? {
+            writer.write_all(&Self::entry_headers(&name, item))?;
+
+            if item.file.is_some() {
+                let data_path = data_paths.get(item.id()).ok_or(Error::msg(format!("Missing data of item {}", item.id())))?;
+                let mut file = File::open(data_path)?;
+                let mut buf = [0u8; 65536];
+                loop {
+                    let size = file.read(&mut buf)?;
+                    if size == 0 { break; }
+                    writer.write_all(&buf[..size])?;
+                }
+                writer.write_all(&vec![0u8; Self::padding(Self::item_size(item))])?;
+            }
+        }
+        writer.write_all(&[0u8; BLOCK_SIZE * 2])?;
+        writer.finish()
+    }
+}
+
+/// Forward the archive to the sink, compressing it on the fly if required
+enum TarWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(sink: W, zstd: bool) -> Result<Self, Error> {
+        Ok(if zstd { Self::Zstd(zstd::stream::write::Encoder::new(sink, 0)?) } else { Self::Plain(sink) })
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Self::Plain(sink) => { sink.write_all(data)? }
+            Self::Zstd(encoder) => { encoder.write_all(data)? }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        let mut sink = match self {
+            Self::Plain(sink) => { sink }
+            Self::Zstd(encoder) => { encoder.finish()? }
+        };
+        sink.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncDirectoryTar, BLOCK_SIZE, USTAR_MAX_SIZE};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use types::database_ids::{ItemId, ObjectId};
+    use types::enc_path::EncPath;
+    use types::enc_string::EncString;
+    use types::item::{DirectoryData, FileData, Item};
+
+    fn item(id: i64, path: &str) -> Item {
+        let mut item = Item::default();
+        item.set_id(ItemId::from(id)).unwrap();
+        item.absolute_path = EncPath::from(path.split('/').map(EncString::from).collect::<Vec<_>>());
+        item.name = EncString::from(path.rsplit('/').next().unwrap());
+        item
+    }
+
+    fn file(id: i64, path: &str, size: u64) -> Item {
+        let mut item = item(id, path);
+        item.file = Some(FileData { size: size as i64, mimetype: EncString::from("text/plain"), timestamp: 1_700_000_000_000, object: ObjectId::from(id) });
+        item
+    }
+
+    #[test]
+    fn short_names_are_not_split() {
+        let name = "a".repeat(100);
+        assert_eq!(AsyncDirectoryTar::split_name(&name), Some(("", name.as_str())));
+    }
+
+    #[test]
+    fn long_names_are_split_on_a_separator() {
+        let name = format!("{}/{}/{}", "a".repeat(60), "b".repeat(40), "c".repeat(80));
+        assert_eq!(AsyncDirectoryTar::split_name(&name), Some((&name[..101], &name[102..])));
+
+        // The trailing separator of directories is kept in the name
+        let directory = format!("{}/{}/", "a".repeat(60), "d".repeat(99));
+        assert_eq!(AsyncDirectoryTar::split_name(&directory), Some((&directory[..60], &directory[61..])));
+
+        // The prefix is limited to 155 bytes and the name to 100 bytes
+        let name = format!("{}/{}", "a".repeat(155), "b".repeat(100));
+        assert_eq!(AsyncDirectoryTar::split_name(&name), Some((&name[..155], &name[156..])));
+        assert_eq!(AsyncDirectoryTar::split_name(&format!("{}/{}", "a".repeat(156), "b".repeat(10))), None);
+        assert_eq!(AsyncDirectoryTar::split_name(&format!("{}/{}", "a".repeat(10), "b".repeat(101))), None);
+        assert_eq!(AsyncDirectoryTar::split_name(&format!("/{}", "b".repeat(100))), None);
+    }
+
+    #[test]
+    fn pax_record_length_includes_itself() {
+        for value_len in 0..2000 {
+            let record = AsyncDirectoryTar::pax_record("path", &"a".repeat(value_len));
+            let record = String::from_utf8(record).unwrap();
+            let (len, content) = record.split_once(' ').unwrap();
+            assert_eq!(len.parse::<usize>().unwrap(), record.len(), "{record}");
+            assert_eq!(content, format!("path={}\n", "a".repeat(value_len)));
+        }
+        // The length field grows by one digit when the record reaches 100 bytes
+        assert_eq!(AsyncDirectoryTar::pax_record("path", &"a".repeat(92)).len(), 102);
+    }
+
+    #[test]
+    fn huge_files_have_a_pax_size() {
+        let headers = AsyncDirectoryTar::entry_headers("huge.bin", &file(1, "huge.bin", USTAR_MAX_SIZE + 1));
+        assert_eq!(headers.len(), BLOCK_SIZE * 3);
+        let records = String::from_utf8_lossy(&headers[BLOCK_SIZE..BLOCK_SIZE * 2]);
+        assert!(records.starts_with(&format!("19 size={}\n", USTAR_MAX_SIZE + 1)));
+        assert_eq!(&headers[BLOCK_SIZE * 2 + 124..BLOCK_SIZE * 2 + 136], b"77777777777\0");
+
+        assert_eq!(AsyncDirectoryTar::entry_headers("file.bin", &file(1, "file.bin", USTAR_MAX_SIZE)).len(), BLOCK_SIZE);
+    }
+
+    /// Archive of a few files written to disk, one of them with a name too long for ustar, and of an empty directory
+    struct TestArchive {
+        tar: AsyncDirectoryTar,
+        data_paths: HashMap<ItemId, PathBuf>,
+    }
+
+    impl TestArchive {
+        fn new(name: &str) -> Self {
+            let long_name = format!("dir/{}.txt", "l".repeat(200));
+            let contents = [("a.txt", b"hello".to_vec()), ("dir/b.txt", vec![7u8; 1000]), (long_name.as_str(), vec![])];
+            let mut tar = AsyncDirectoryTar::new();
+            let mut data_paths = HashMap::new();
+            for (id, (path, content)) in contents.iter().enumerate() {
+                let item = file(id as i64 + 1, path, content.len() as u64);
+                let data_path = std::env::temp_dir().join(format!("fileshare_tar_{}_{name}_{id}", std::process::id()));
+                std::fs::write(&data_path, content).unwrap();
+                data_paths.insert(item.id().clone(), data_path);
+                tar.items.insert(item.id().clone(), item);
+            }
+            let mut empty = item(10, "dir/sub");
+            empty.directory = Some(DirectoryData::default());
+            tar.items.insert(empty.id().clone(), empty);
+            Self { tar, data_paths }
+        }
+
+        fn write(&self) -> Vec<u8> {
+            let mut archive = vec![];
+            self.tar.write_entries(&self.data_paths, &mut archive).unwrap();
+            archive
+        }
+    }
+
+    impl Drop for TestArchive {
+        fn drop(&mut self) {
+            for path in self.data_paths.values() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn size_matches_written_archive() {
+        let archive = TestArchive::new("size");
+        let data = archive.write();
+        assert_eq!(archive.tar.size().unwrap(), Some(data.len()));
+        assert_eq!(data.len() % BLOCK_SIZE, 0);
+    }
+
+    #[test]
+    fn zstd_archive() {
+        let mut archive = TestArchive::new("zstd");
+        let plain = archive.write();
+        archive.tar.set_zstd(true);
+        assert_eq!(archive.tar.size().unwrap(), None);
+        let compressed = archive.write();
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), plain);
+    }
+}
@@ -2,132 +2,244 @@ use std::collections::HashMap;
 use crate::item::{DbItem, Trash};
 use crate::object::Object;
 use anyhow::Error;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use tokio::io::{AsyncWrite, AsyncWriteExt};
+use std::io::{BufReader, Read, Write};
+use std::path::PathBuf;
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
 use tokio_util::bytes::BufMut;
 use types::database_ids::ItemId;
 use types::item::Item;
 use crate::Database;
 
+/// Value stored in 32 bits fields when the real value is in the ZIP64 extra field
+const ZIP64_LIMIT: u32 = u32::MAX;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+const ZIP64_VERSION: u16 = 0x2D;
+const DEFLATE_VERSION: u16 = 0x14;
+const EXTENDED_TIMESTAMP_FIELD_ID: u16 = 0x5455;
+/// General purpose flag : crc and sizes are written in a data descriptor after the data
+const DATA_DESCRIPTOR_FLAG: u16 = 0x0008;
+
+const CRC32_TABLE: [u32; 256] = [
+    0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f,
+    0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988,
+    0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2,
+    0xf3b97148, 0x84be41de, 0x1adad47d, 0x6ddde4eb, 0xf4d4b551, 0x83d385c7,
+    0x136c9856, 0x646ba8c0, 0xfd62f97a, 0x8a65c9ec, 0x14015c4f, 0x63066cd9,
+    0xfa0f3d63, 0x8d080df5, 0x3b6e20c8, 0x4c69105e, 0xd56041e4, 0xa2677172,
+    0x3c03e4d1, 0x4b04d447, 0xd20d85fd, 0xa50ab56b, 0x35b5a8fa, 0x42b2986c,
+    0xdbbbc9d6, 0xacbcf940, 0x32d86ce3, 0x45df5c75, 0xdcd60dcf, 0xabd13d59,
+    0x26d930ac, 0x51de003a, 0xc8d75180, 0xbfd06116, 0x21b4f4b5, 0x56b3c423,
+    0xcfba9599, 0xb8bda50f, 0x2802b89e, 0x5f058808, 0xc60cd9b2, 0xb10be924,
+    0x2f6f7c87, 0x58684c11, 0xc1611dab, 0xb6662d3d, 0x76dc4190, 0x01db7106,
+    0x98d220bc, 0xefd5102a, 0x71b18589, 0x06b6b51f, 0x9fbfe4a5, 0xe8b8d433,
+    0x7807c9a2, 0x0f00f934, 0x9609a88e, 0xe10e9818, 0x7f6a0dbb, 0x086d3d2d,
+    0x91646c97, 0xe6635c01, 0x6b6b51f4, 0x1c6c6162, 0x856530d8, 0xf262004e,
+    0x6c0695ed, 0x1b01a57b, 0x8208f4c1, 0xf50fc457, 0x65b0d9c6, 0x12b7e950,
+    0x8bbeb8ea, 0xfcb9887c, 0x62dd1ddf, 0x15da2d49, 0x8cd37cf3, 0xfbd44c65,
+    0x4db26158, 0x3ab551ce, 0xa3bc0074, 0xd4bb30e2, 0x4adfa541, 0x3dd895d7,
+    0xa4d1c46d, 0xd3d6f4fb, 0x4369e96a, 0x346ed9fc, 0xad678846, 0xda60b8d0,
+    0x44042d73, 0x33031de5, 0xaa0a4c5f, 0xdd0d7cc9, 0x5005713c, 0x270241aa,
+    0xbe0b1010, 0xc90c2086, 0x5768b525, 0x206f85b3, 0xb966d409, 0xce61e49f,
+    0x5edef90e, 0x29d9c998, 0xb0d09822, 0xc7d7a8b4, 0x59b33d17, 0x2eb40d81,
+    0xb7bd5c3b, 0xc0ba6cad, 0xedb88320, 0x9abfb3b6, 0x03b6e20c, 0x74b1d29a,
+    0xead54739, 0x9dd277af, 0x04db2615, 0x73dc1683, 0xe3630b12, 0x94643b84,
+    0x0d6d6a3e, 0x7a6a5aa8, 0xe40ecf0b, 0x9309ff9d, 0x0a00ae27, 0x7d079eb1,
+    0xf00f9344, 0x8708a3d2, 0x1e01f268, 0x6906c2fe, 0xf762575d, 0x806567cb,
+    0x196c3671, 0x6e6b06e7, 0xfed41b76, 0x89d32be0, 0x10da7a5a, 0x67dd4acc,
+    0xf9b9df6f, 0x8ebeeff9, 0x17b7be43, 0x60b08ed5, 0xd6d6a3e8, 0xa1d1937e,
+    0x38d8c2c4, 0x4fdff252, 0xd1bb67f1, 0xa6bc5767, 0x3fb506dd, 0x48b2364b,
+    0xd80d2bda, 0xaf0a1b4c, 0x36034af6, 0x41047a60, 0xdf60efc3, 0xa867df55,
+    0x316e8eef, 0x4669be79, 0xcb61b38c, 0xbc66831a, 0x256fd2a0, 0x5268e236,
+    0xcc0c7795, 0xbb0b4703, 0x220216b9, 0x5505262f, 0xc5ba3bbe, 0xb2bd0b28,
+    0x2bb45a92, 0x5cb36a04, 0xc2d7ffa7, 0xb5d0cf31, 0x2cd99e8b, 0x5bdeae1d,
+    0x9b64c2b0, 0xec63f226, 0x756aa39c, 0x026d930a, 0x9c0906a9, 0xeb0e363f,
+    0x72076785, 0x05005713, 0x95bf4a82, 0xe2b87a14, 0x7bb12bae, 0x0cb61b38,
+    0x92d28e9b, 0xe5d5be0d, 0x7cdcefb7, 0x0bdbdf21, 0x86d3d2d4, 0xf1d4e242,
+    0x68ddb3f8, 0x1fda836e, 0x81be16cd, 0xf6b9265b, 0x6fb077e1, 0x18b74777,
+    0x88085ae6, 0xff0f6a70, 0x66063bca, 0x11010b5c, 0x8f659eff, 0xf862ae69,
+    0x616bffd3, 0x166ccf45, 0xa00ae278, 0xd70dd2ee, 0x4e048354, 0x3903b3c2,
+    0xa7672661, 0xd06016f7, 0x4969474d, 0x3e6e77db, 0xaed16a4a, 0xd9d65adc,
+    0x40df0b66, 0x37d83bf0, 0xa9bcae53, 0xdebb9ec5, 0x47b2cf7f, 0x30b5ffe9,
+    0xbdbdf21c, 0xcabac28a, 0x53b39330, 0x24b4a3a6, 0xbad03605, 0xcdd70693,
+    0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94,
+    0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d];
+
+fn update_crc(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Convert a timestamp in milliseconds since UNIX epoch (UTC) to MS-DOS (time, date)
+fn dos_date_time(timestamp: i64) -> (u16, u16) {
+    let seconds = timestamp.div_euclid(1000);
+    let days = seconds.div_euclid(86400);
+    let seconds_of_day = seconds.rem_euclid(86400);
+
+    // Days to civil date (proleptic gregorian calendar)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    // MS-DOS dates can only represent years 1980 to 2107
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+    let time = ((seconds_of_day / 3600) << 11) | (((seconds_of_day % 3600) / 60) << 5) | ((seconds_of_day % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+/// Upper bound of the deflated size (same formula as zlib's compressBound)
+fn max_compressed_size(size: u64) -> u64 {
+    size + (size >> 12) + (size >> 14) + (size >> 25) + 13
+}
+
+/// Gather the files and the empty directories contained in item (the hierarchy of other
+/// directories is implied by the absolute paths)
+pub(crate) async fn collect_items(db: &Database, item: Item, items: &mut HashMap<ItemId, Item>) -> Result<(), Error> {
+    let mut items_to_push = vec![item];
+    while let Some(item) = items_to_push.pop() {
+        if item.directory.is_some() {
+            let children = DbItem::from_parent(db, item.id(), Trash::No).await?;
+            if children.is_empty() {
+                items.insert(item.id().clone(), item);
+            } else {
+                for child in children {
+                    items_to_push.push(child)
+                }
+            }
+        } else {
+            items.insert(item.id().clone(), item);
+        }
+    }
+    Ok(())
+}
+
+/// Position and final sizes of an entry written in the archive
+struct WrittenEntry {
+    start: usize,
+    crc32: u32,
+    compressed_size: u64,
+}
+
 pub struct AsyncDirectoryZip {
-    items: HashMap<ItemId, Item>
+    items: HashMap<ItemId, Item>,
+    compression: bool,
+}
+
+impl Default for AsyncDirectoryZip {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AsyncDirectoryZip {
     pub fn new() -> Self {
         Self {
-            items: HashMap::default()
+            items: HashMap::default(),
+            compression: false,
         }
     }
 
+    /// Compress files with DEFLATE. The archive size cannot be known in advance in this mode.
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
     pub async fn push_item(&mut self, db: &Database, item: Item) -> Result<(), Error> {
-        let mut items_to_push = vec![item];
-        while let Some(item) = items_to_push.pop() {
-            if item.directory.is_some() {
-                let children = DbItem::from_parent(db, item.id(), Trash::No).await?;
-                if children.is_empty() {
-                    self.items.insert(item.id().clone(), item);
-                } else {
-                    for child in children {
-                        items_to_push.push(child)
-                    }
-                }
-            } else {
-                self.items.insert(item.id().clone(), item);
-            }
-        }
-        Ok(())
+        collect_items(db, item, &mut self.items).await
     }
 
     fn compute_file_crc(file: File) -> Result<u32, Error> {
-        const CRC32_TABLE: [u32; 256] = [
-            0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f,
-            0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988,
-            0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2,
-            0xf3b97148, 0x84be41de, 0x1adad47d, 0x6ddde4eb, 0xf4d4b551, 0x83d385c7,
-            0x136c9856, 0x646ba8c0, 0xfd62f97a, 0x8a65c9ec, 0x14015c4f, 0x63066cd9,
-            0xfa0f3d63, 0x8d080df5, 0x3b6e20c8, 0x4c69105e, 0xd56041e4, 0xa2677172,
-            0x3c03e4d1, 0x4b04d447, 0xd20d85fd, 0xa50ab56b, 0x35b5a8fa, 0x42b2986c,
-            0xdbbbc9d6, 0xacbcf940, 0x32d86ce3, 0x45df5c75, 0xdcd60dcf, 0xabd13d59,
-            0x26d930ac, 0x51de003a, 0xc8d75180, 0xbfd06116, 0x21b4f4b5, 0x56b3c423,
-            0xcfba9599, 0xb8bda50f, 0x2802b89e, 0x5f058808, 0xc60cd9b2, 0xb10be924,
-            0x2f6f7c87, 0x58684c11, 0xc1611dab, 0xb6662d3d, 0x76dc4190, 0x01db7106,
-            0x98d220bc, 0xefd5102a, 0x71b18589, 0x06b6b51f, 0x9fbfe4a5, 0xe8b8d433,
-            0x7807c9a2, 0x0f00f934, 0x9609a88e, 0xe10e9818, 0x7f6a0dbb, 0x086d3d2d,
-            0x91646c97, 0xe6635c01, 0x6b6b51f4, 0x1c6c6162, 0x856530d8, 0xf262004e,
-            0x6c0695ed, 0x1b01a57b, 0x8208f4c1, 0xf50fc457, 0x65b0d9c6, 0x12b7e950,
-            0x8bbeb8ea, 0xfcb9887c, 0x62dd1ddf, 0x15da2d49, 0x8cd37cf3, 0xfbd44c65,
-            0x4db26158, 0x3ab551ce, 0xa3bc0074, 0xd4bb30e2, 0x4adfa541, 0x3dd895d7,
-            0xa4d1c46d, 0xd3d6f4fb, 0x4369e96a, 0x346ed9fc, 0xad678846, 0xda60b8d0,
-            0x44042d73, 0x33031de5, 0xaa0a4c5f, 0xdd0d7cc9, 0x5005713c, 0x270241aa,
-            0xbe0b1010, 0xc90c2086, 0x5768b525, 0x206f85b3, 0xb966d409, 0xce61e49f,
-            0x5edef90e, 0x29d9c998, 0xb0d09822, 0xc7d7a8b4, 0x59b33d17, 0x2eb40d81,
-            0xb7bd5c3b, 0xc0ba6cad, 0xedb88320, 0x9abfb3b6, 0x03b6e20c, 0x74b1d29a,
-            0xead54739, 0x9dd277af, 0x04db2615, 0x73dc1683, 0xe3630b12, 0x94643b84,
-            0x0d6d6a3e, 0x7a6a5aa8, 0xe40ecf0b, 0x9309ff9d, 0x0a00ae27, 0x7d079eb1,
-            0xf00f9344, 0x8708a3d2, 0x1e01f268, 0x6906c2fe, 0xf762575d, 0x806567cb,
-            0x196c3671, 0x6e6b06e7, 0xfed41b76, 0x89d32be0, 0x10da7a5a, 0x67dd4acc,
-            0xf9b9df6f, 0x8ebeeff9, 0x17b7be43, 0x60b08ed5, 0xd6d6a3e8, 0xa1d1937e,
-            0x38d8c2c4, 0x4fdff252, 0xd1bb67f1, 0xa6bc5767, 0x3fb506dd, 0x48b2364b,
-            0xd80d2bda, 0xaf0a1b4c, 0x36034af6, 0x41047a60, 0xdf60efc3, 0xa867df55,
-            0x316e8eef, 0x4669be79, 0xcb61b38c, 0xbc66831a, 0x256fd2a0, 0x5268e236,
-            0xcc0c7795, 0xbb0b4703, 0x220216b9, 0x5505262f, 0xc5ba3bbe, 0xb2bd0b28,
-            0x2bb45a92, 0x5cb36a04, 0xc2d7ffa7, 0xb5d0cf31, 0x2cd99e8b, 0x5bdeae1d,
-            0x9b64c2b0, 0xec63f226, 0x756aa39c, 0x026d930a, 0x9c0906a9, 0xeb0e363f,
-            0x72076785, 0x05005713, 0x95bf4a82, 0xe2b87a14, 0x7bb12bae, 0x0cb61b38,
-            0x92d28e9b, 0xe5d5be0d, 0x7cdcefb7, 0x0bdbdf21, 0x86d3d2d4, 0xf1d4e242,
-            0x68ddb3f8, 0x1fda836e, 0x81be16cd, 0xf6b9265b, 0x6fb077e1, 0x18b74777,
-            0x88085ae6, 0xff0f6a70, 0x66063bca, 0x11010b5c, 0x8f659eff, 0xf862ae69,
-            0x616bffd3, 0x166ccf45, 0xa00ae278, 0xd70dd2ee, 0x4e048354, 0x3903b3c2,
-            0xa7672661, 0xd06016f7, 0x4969474d, 0x3e6e77db, 0xaed16a4a, 0xd9d65adc,
-            0x40df0b66, 0x37d83bf0, 0xa9bcae53, 0xdebb9ec5, 0x47b2cf7f, 0x30b5ffe9,
-            0xbdbdf21c, 0xcabac28a, 0x53b39330, 0x24b4a3a6, 0xbad03605, 0xcdd70693,
-            0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94,
-            0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d];
-
         let mut buf = BufReader::new(file);
         let mut crc = 0xFFFFFFFF;
         let mut bytes = [0; 512];
         loop {
             let read = buf.read(&mut bytes)?;
             if read == 0 { break; }
-            for i in 0..read {
-                crc = CRC32_TABLE[((crc ^ bytes[i] as u32) & 0xFF) as usize] ^ (crc >> 8);
-            }
+            crc = update_crc(crc, &bytes[..read]);
         }
 
         Ok(!crc)
     }
 
+    fn is_compressed(&self, item: &Item) -> bool {
+        self.compression && item.file.is_some()
+    }
 
-    fn local_header(item: &Item, crc32: u32) -> Result<Vec<u8>, Error> {
-        let mut item_name = item.absolute_path.plain()?;
-        item_name.remove(0);
-        if item.directory.is_some() { item_name += "/" };
+    fn item_timestamp(item: &Item) -> i64 {
+        item.file.as_ref().map(|file| file.timestamp).unwrap_or_default()
+    }
 
-        let size = match &item.file {
-            None => { 0u32 }
-            Some(file) => {
-                assert!(file.size < u32::MAX as i64);
-                file.size as u32
-            }
-        };
+    fn extended_timestamp_field(item: &Item) -> Vec<u8> {
+        let mut field = vec![];
+        if item.file.is_some() {
+            let mtime = (Self::item_timestamp(item) / 1000).clamp(0, u32::MAX as i64) as u32;
+            field.put_slice(&EXTENDED_TIMESTAMP_FIELD_ID.to_le_bytes());
+            field.put_slice(&5u16.to_le_bytes());
+            field.put_u8(0x01); // Modification time present
+            field.put_slice(&mtime.to_le_bytes());
+        }
+        field
+    }
 
-        let version = if item.file.is_some() { 0x0Au16 } else { 0x14u16 };
+    /// With data descriptors the compressed size is unknown when writing the local header, so
+    /// ZIP64 is decided from its upper bound.
+    fn local_zip64(&self, item: &Item) -> bool {
+        let size = Self::item_size(item) as u64;
+        if self.is_compressed(item) {
+            max_compressed_size(size) >= ZIP64_LIMIT as u64
+        } else {
+            size >= ZIP64_LIMIT as u64
+        }
+    }
+
+    fn local_header(&self, item: &Item, crc32: u32) -> Result<Vec<u8>, Error> {
+        let item_name = Self::format_item_name(item)?;
+
+        let compressed = self.is_compressed(item);
+        let size = if compressed { 0 } else { Self::item_size(item) as u64 };
+        let zip64 = self.local_zip64(item);
+
+        let version = if zip64 { ZIP64_VERSION } else if compressed { DEFLATE_VERSION } else if item.file.is_some() { 0x0Au16 } else { 0x14u16 };
+        let (time, date) = dos_date_time(Self::item_timestamp(item));
+
+        let mut extra_field = vec![];
+        if zip64 {
+            extra_field.put_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+            extra_field.put_slice(&16u16.to_le_bytes());
+            extra_field.put_slice(&size.to_le_bytes());
+            extra_field.put_slice(&size.to_le_bytes());
+        }
+        extra_field.put_slice(&Self::extended_timestamp_field(item));
 
         // local file header
         let signature = 0x04034b50u32.to_le_bytes();
         let version = version.to_le_bytes();
-        let flags = 0x0u16.to_le_bytes();
-        let compression_method = 0x0u16.to_le_bytes();
-        let last_modification_time = 0x0u16.to_le_bytes();
-        let last_modification_date = 0x0u16.to_le_bytes();
-        let crc32 = crc32.to_le_bytes();
-        let compressed_size = size.to_le_bytes();
-        let uncompressed_size = size.to_le_bytes();
+        let flags = (if compressed { DATA_DESCRIPTOR_FLAG } else { 0x0u16 }).to_le_bytes();
+        let compression_method = (if compressed { 0x8u16 } else { 0x0u16 }).to_le_bytes();
+        let last_modification_time = time.to_le_bytes();
+        let last_modification_date = date.to_le_bytes();
+        let crc32 = (if compressed { 0 } else { crc32 }).to_le_bytes();
+        let compressed_size = (if zip64 { ZIP64_LIMIT } else { size as u32 }).to_le_bytes();
+        let uncompressed_size = (if zip64 { ZIP64_LIMIT } else { size as u32 }).to_le_bytes();
         let file_name_length = (item_name.len() as u16).to_le_bytes();
-        let extra_field_length = 0u16.to_le_bytes();
+        let extra_field_length = (extra_field.len() as u16).to_le_bytes();
         let file_name = item_name.as_bytes();
 
         let mut local_file_header = vec![];
@@ -143,37 +255,70 @@ impl AsyncDirectoryZip {
         local_file_header.put_slice(&file_name_length);
         local_file_header.put_slice(&extra_field_length);
         local_file_header.put_slice(file_name);
+        local_file_header.put_slice(&extra_field);
         Ok(local_file_header)
     }
 
-    fn make_central_directory(item: &Item, start: usize, crc32: u32) -> Result<Vec<u8>, Error> {
-        let size = if let Some(file) = &item.file {
-            file.size
-        } else { 0 };
-        assert!(start < u32::MAX as usize);
-        assert!(size < u32::MAX as i64);
+    /// Data descriptor written after compressed data. Sizes are 8 bytes long if the local header is ZIP64.
+    fn data_descriptor(crc32: u32, compressed_size: u64, size: u64, zip64: bool) -> Vec<u8> {
+        let mut descriptor = vec![];
+        descriptor.put_slice(&0x08074b50u32.to_le_bytes());
+        descriptor.put_slice(&crc32.to_le_bytes());
+        if zip64 {
+            descriptor.put_slice(&compressed_size.to_le_bytes());
+            descriptor.put_slice(&size.to_le_bytes());
+        } else {
+            descriptor.put_slice(&(compressed_size as u32).to_le_bytes());
+            descriptor.put_slice(&(size as u32).to_le_bytes());
+        }
+        descriptor
+    }
 
-        let mut item_name = item.absolute_path.plain()?;
-        item_name.remove(0);
-        if item.directory.is_some() { item_name += "/" };
+    fn make_central_directory(&self, item: &Item, entry: &WrittenEntry) -> Result<Vec<u8>, Error> {
+        let size = Self::item_size(item) as u64;
+        let compressed_size = entry.compressed_size;
+        let start = entry.start as u64;
+        let compressed = self.is_compressed(item);
+        let item_name = Self::format_item_name(item)?;
+        let (time, date) = dos_date_time(Self::item_timestamp(item));
+
+        // The ZIP64 extra field only contains the values that overflow, in this order
+        let size_overflow = size >= ZIP64_LIMIT as u64 || compressed_size >= ZIP64_LIMIT as u64;
+        let start_overflow = start >= ZIP64_LIMIT as u64;
+        let mut extra_field = vec![];
+        if size_overflow || start_overflow {
+            let mut values = vec![];
+            if size_overflow {
+                values.put_slice(&size.to_le_bytes());
+                values.put_slice(&compressed_size.to_le_bytes());
+            }
+            if start_overflow {
+                values.put_slice(&start.to_le_bytes());
+            }
+            extra_field.put_slice(&ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+            extra_field.put_slice(&(values.len() as u16).to_le_bytes());
+            extra_field.put_slice(&values);
+        }
+        let zip64 = !extra_field.is_empty();
+        extra_field.put_slice(&Self::extended_timestamp_field(item));
 
         let signature = 0x02014b50u32.to_le_bytes();
         let version = 0x3Fu16.to_le_bytes();
-        let version_required = 0x0Au16.to_le_bytes();
-        let flags = 0x0u16.to_le_bytes();
-        let compression_method = 0x0u16.to_le_bytes();
-        let last_modification_time = 0x0u16.to_le_bytes(); // @TODO : calculer
-        let last_modification_date = 0x0u16.to_le_bytes(); // @TODO : calculer
-        let crc32 = crc32.to_le_bytes();
-        let compressed_size = (size as u32).to_le_bytes();
-        let uncompressed_size = (size as u32).to_le_bytes();
+        let version_required = (if zip64 { ZIP64_VERSION } else if compressed { DEFLATE_VERSION } else { 0x0Au16 }).to_le_bytes();
+        let flags = (if compressed { DATA_DESCRIPTOR_FLAG } else { 0x0u16 }).to_le_bytes();
+        let compression_method = (if compressed { 0x8u16 } else { 0x0u16 }).to_le_bytes();
+        let last_modification_time = time.to_le_bytes();
+        let last_modification_date = date.to_le_bytes();
+        let crc32 = entry.crc32.to_le_bytes();
+        let compressed_size = (if size_overflow { ZIP64_LIMIT } else { compressed_size as u32 }).to_le_bytes();
+        let uncompressed_size = (if size_overflow { ZIP64_LIMIT } else { size as u32 }).to_le_bytes();
         let file_name_length = (item_name.len() as u16).to_le_bytes();
-        let extra_field_length = 0u16.to_le_bytes();
+        let extra_field_length = (extra_field.len() as u16).to_le_bytes();
         let file_comment_length = 0u16.to_le_bytes();
         let disk_number = 0u16.to_le_bytes();
         let internal_file_attributes = 0u16.to_le_bytes();
         let external_file_attributes = 0u32.to_le_bytes();
-        let relative_offset = (start as u32).to_le_bytes(); // Should stay at 0 for ZIP64
+        let relative_offset = (if start_overflow { ZIP64_LIMIT } else { start as u32 }).to_le_bytes();
         let file_name = item_name.as_bytes();
 
         let mut directory = vec![];
@@ -196,23 +341,48 @@ impl AsyncDirectoryZip {
         directory.put_slice(&external_file_attributes);
         directory.put_slice(&relative_offset);
         directory.put_slice(file_name);
+        directory.put_slice(&extra_field);
         Ok(directory)
     }
 
+    /// End of central directory record, preceded by the ZIP64 end of central directory record and
+    /// locator when the entry count, the central directory size or its offset overflow.
     fn end_of_directory(&self, central_directory_start: usize, central_directory_end: usize) -> Vec<u8> {
-        assert!(self.items.len() < u16::MAX as usize);
-        assert!(central_directory_start < u32::MAX as usize);
-        assert!(central_directory_end < u32::MAX as usize);
+        let count = self.items.len() as u64;
+        let start = central_directory_start as u64;
+        let size = (central_directory_end - central_directory_start) as u64;
+        let zip64 = count >= u16::MAX as u64 || start >= ZIP64_LIMIT as u64 || size >= ZIP64_LIMIT as u64;
+
+        let mut directory = vec![];
+        if zip64 {
+            // ZIP64 end of central directory record
+            directory.put_slice(&0x06064b50u32.to_le_bytes());
+            directory.put_slice(&44u64.to_le_bytes()); // Size of the remaining record
+            directory.put_slice(&0x3Fu16.to_le_bytes());
+            directory.put_slice(&ZIP64_VERSION.to_le_bytes());
+            directory.put_slice(&0u32.to_le_bytes());
+            directory.put_slice(&0u32.to_le_bytes());
+            directory.put_slice(&count.to_le_bytes());
+            directory.put_slice(&count.to_le_bytes());
+            directory.put_slice(&size.to_le_bytes());
+            directory.put_slice(&start.to_le_bytes());
+
+            // ZIP64 end of central directory locator
+            directory.put_slice(&0x07064b50u32.to_le_bytes());
+            directory.put_slice(&0u32.to_le_bytes());
+            directory.put_slice(&(central_directory_end as u64).to_le_bytes());
+            directory.put_slice(&1u32.to_le_bytes());
+        }
+
         let signature = 0x06054b50u32.to_le_bytes();
         let disk = 0u16.to_le_bytes();
         let central_directory_start_disk = 0u16.to_le_bytes();
-        let central_directory_record_count_on_disk = (self.items.len() as u16).to_le_bytes();
-        let central_directory_record_count = (self.items.len() as u16).to_le_bytes();
-        let central_directory_size = ((central_directory_end - central_directory_start) as u32).to_le_bytes();
-        let central_directory_start = (central_directory_start as u32).to_le_bytes();
+        let central_directory_record_count_on_disk = (if zip64 { u16::MAX } else { count as u16 }).to_le_bytes();
+        let central_directory_record_count = (if zip64 { u16::MAX } else { count as u16 }).to_le_bytes();
+        let central_directory_size = (if zip64 { ZIP64_LIMIT } else { size as u32 }).to_le_bytes();
+        let central_directory_start = (if zip64 { ZIP64_LIMIT } else { start as u32 }).to_le_bytes();
         let comment_length = 0u16.to_le_bytes();
 
-        let mut directory = vec![];
         directory.put_slice(&signature);
         directory.put_slice(&disk);
         directory.put_slice(&central_directory_start_disk);
@@ -224,19 +394,27 @@ impl AsyncDirectoryZip {
         directory
     }
 
-    pub fn size(&self) -> Result<usize, Error> {
-        const LOCAL_HEADER_SIZE: usize = 30;
-        const CENTRAL_DIRECTORY_SIZE: usize = 46;
-        const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
-
-        let mut size = self.items.len() * (LOCAL_HEADER_SIZE + CENTRAL_DIRECTORY_SIZE);
+    /// Exact size of the generated archive. The headers are built the same way as in finalize()
+    /// since ZIP64 extra fields depend on the entry offsets.
+    /// Returns None when compression is enabled.
+    pub fn size(&self) -> Result<Option<usize>, Error> {
+        if self.compression {
+            return Ok(None);
+        }
+        let mut location = 0usize;
+        let mut blocs = vec![];
         for item in self.items.values() {
-            size += Self::format_item_name(item)?.len() * 2;
-            size += Self::item_size(item);
+            blocs.push((WrittenEntry { start: location, crc32: 0, compressed_size: Self::item_size(item) as u64 }, item));
+            location += self.local_header(item, 0)?.len();
+            location += Self::item_size(item);
         }
-        size += END_OF_CENTRAL_DIRECTORY_SIZE;
+        let central_directory_start = location;
+        for (entry, item) in blocs {
+            location += self.make_central_directory(item, &entry)?.len();
+        }
+        location += self.end_of_directory(central_directory_start, location).len();
 
-        Ok(size)
+        Ok(Some(location))
     }
 
     fn item_size(item: &Item) -> usize {
@@ -251,48 +429,297 @@ impl AsyncDirectoryZip {
         Ok(item_name)
     }
 
-    pub async fn finalize<S: Unpin + AsyncWrite>(&mut self, db: &Database, mut sink: S) -> Result<(), Error> {
+    /// Write the archive to the sink. Reading and compressing the files is done on a blocking thread.
+    pub async fn finalize<S: AsyncWrite + Unpin + Send + 'static>(self, db: &Database, sink: S) -> Result<(), Error> {
+        let mut data_paths = HashMap::new();
+        for item in self.items.values() {
+            if let Some(file) = &item.file {
+                let object = Object::from_id(db, &file.object).await?;
+                data_paths.insert(item.id().clone(), Object::data_path(object.id(), db));
+            }
+        }
+        let sink = SyncIoBridge::new(sink);
+        tokio::task::spawn_blocking(move || self.write_entries(&data_paths, sink)).await?
+    }
+
+    /// Write the archive, reading the content of the files from `data_paths`
+    fn write_entries<W: Write>(&self, data_paths: &HashMap<ItemId, PathBuf>, mut sink: W) -> Result<(), Error> {
         let mut location = 0usize;
 
         let mut blocs = vec![];
 
         for item in self.items.values() {
-            let mut object = None;
-            let crc32 = if let Some(file) = &item.file {
-                let found_object = Object::from_id(db, &file.object).await?;
-                let crc = Self::compute_file_crc(File::open(Object::data_path(found_object.id(), db))?)?;
-                object = Some(found_object);
-                crc
-            } else { 0 };
-
-            blocs.push((location, item.clone(), crc32));
-
-            // Write local header
-            let header = Self::local_header(item, crc32)?;
-            location += header.len();
-            sink.write_all(header.as_slice()).await?;
-
-            if let Some(object) = &object {
-                let mut file = File::open(Object::data_path(object.id(), db))?;
-                let mut buf = [0u8; 4096];
-                while let Ok(size) = file.read(&mut buf) {
-                    if size == 0 { break; }
-                    location += size;
-                    sink.write_all(&buf[..size]).await?;
+            let start = location;
+            let data_path = match &item.file {
+                None => { None }
+                Some(_) => { Some(data_paths.get(item.id()).ok_or(Error::msg(format!("Missing data of item {}", item.id())))?) }
+            };
+
+            if self.is_compressed(item) {
+                let header = self.local_header(item, 0)?;
+                location += header.len();
+                sink.write_all(header.as_slice())?;
+
+                let mut crc = 0xFFFFFFFF;
+                let mut compressed_size = 0u64;
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                if let Some(data_path) = data_path {
+                    let mut file = File::open(data_path)?;
+                    let mut buf = [0u8; 65536];
+                    loop {
+                        let size = file.read(&mut buf)?;
+                        if size == 0 { break; }
+                        crc = update_crc(crc, &buf[..size]);
+                        encoder.write_all(&buf[..size])?;
+                        // Forward the compressed data as soon as it is available
+                        let compressed = std::mem::take(encoder.get_mut());
+                        compressed_size += compressed.len() as u64;
+                        sink.write_all(compressed.as_slice())?;
+                    }
                 }
-                sink.flush().await?;
+                let compressed = encoder.finish()?;
+                compressed_size += compressed.len() as u64;
+                sink.write_all(compressed.as_slice())?;
+                location += compressed_size as usize;
+
+                let descriptor = Self::data_descriptor(!crc, compressed_size, Self::item_size(item) as u64, self.local_zip64(item));
+                location += descriptor.len();
+                sink.write_all(descriptor.as_slice())?;
+                sink.flush()?;
+                blocs.push((WrittenEntry { start, crc32: !crc, compressed_size }, item.clone()));
+            } else {
+                let crc32 = match data_path {
+                    None => { 0 }
+                    Some(data_path) => { Self::compute_file_crc(File::open(data_path)?)? }
+                };
+
+                // Write local header
+                let header = self.local_header(item, crc32)?;
+                location += header.len();
+                sink.write_all(header.as_slice())?;
+
+                if let Some(data_path) = data_path {
+                    let mut file = File::open(data_path)?;
+                    let mut buf = [0u8; 4096];
+                    while let Ok(size) = file.read(&mut buf) {
+                        if size == 0 { break; }
+                        location += size;
+                        sink.write_all(&buf[..size])?;
+                    }
+                    sink.flush()?;
+                }
+                blocs.push((WrittenEntry { start, crc32, compressed_size: Self::item_size(item) as u64 }, item.clone()));
             }
         }
         let central_directory_start = location;
-        for (start, item, crc32) in blocs {
-            let data = Self::make_central_directory(&item, start, crc32)?;
+        for (entry, item) in blocs {
+            let data = self.make_central_directory(&item, &entry)?;
             location += data.len();
-            sink.write_all(data.as_slice()).await?;
+            sink.write_all(data.as_slice())?;
         }
 
         let end_of_directory = self.end_of_directory(central_directory_start, location);
-        sink.write_all(end_of_directory.as_slice()).await?;
-
+        sink.write_all(end_of_directory.as_slice())?;
+        sink.flush()?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dos_date_time, AsyncDirectoryZip, WrittenEntry, ZIP64_LIMIT};
+    use std::collections::HashMap;
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+    use types::database_ids::{ItemId, ObjectId};
+    use types::enc_path::EncPath;
+    use types::enc_string::EncString;
+    use types::item::{DirectoryData, FileData, Item};
+
+    fn item(id: i64, path: &[&str]) -> Item {
+        let mut item = Item::default();
+        item.set_id(ItemId::from(id)).unwrap();
+        item.name = EncString::from(*path.last().unwrap());
+        item.absolute_path = EncPath::from(path.iter().map(|name| EncString::from(*name)).collect::<Vec<_>>());
+        item
+    }
+
+    fn file(id: i64, path: &[&str], size: u64) -> Item {
+        let mut item = item(id, path);
+        item.file = Some(FileData { size: size as i64, mimetype: EncString::from("text/plain"), timestamp: 1_700_000_000_000, object: ObjectId::from(id) });
+        item
+    }
+
+    fn directory(id: i64, path: &[&str]) -> Item {
+        let mut item = item(id, path);
+        item.directory = Some(DirectoryData::default());
+        item
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    /// Archive of a few files written to disk and of an empty directory
+    struct TestArchive {
+        zip: AsyncDirectoryZip,
+        data_paths: HashMap<ItemId, PathBuf>,
+        contents: Vec<(String, Vec<u8>)>,
+    }
+
+    impl TestArchive {
+        fn new(name: &str) -> Self {
+            let contents = vec![
+                ("a.txt".to_string(), b"hello".to_vec()),
+                ("dir/b.txt".to_string(), b"compressible ".repeat(1000)),
+                ("dir/empty.txt".to_string(), vec![]),
+            ];
+            let mut zip = AsyncDirectoryZip::new();
+            let mut data_paths = HashMap::new();
+            for (id, (path, content)) in contents.iter().enumerate() {
+                let item = file(id as i64 + 1, &path.split('/').collect::<Vec<_>>(), content.len() as u64);
+                let data_path = std::env::temp_dir().join(format!("fileshare_zip_{}_{name}_{id}", std::process::id()));
+                std::fs::write(&data_path, content).unwrap();
+                data_paths.insert(item.id().clone(), data_path);
+                zip.items.insert(item.id().clone(), item);
+            }
+            let empty = directory(10, &["dir", "sub"]);
+            zip.items.insert(empty.id().clone(), empty);
+            Self { zip, data_paths, contents }
+        }
+
+        fn write(&self) -> Vec<u8> {
+            let mut archive = vec![];
+            self.zip.write_entries(&self.data_paths, &mut archive).unwrap();
+            archive
+        }
+
+        fn check(&self, archive: Vec<u8>) {
+            let mut reader = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+            assert_eq!(reader.len(), self.contents.len() + 1);
+            for (path, content) in &self.contents {
+                let mut entry = reader.by_name(path).unwrap();
+                let mut data = vec![];
+                entry.read_to_end(&mut data).unwrap();
+                assert_eq!(&data, content);
+            }
+            assert!(reader.by_name("dir/sub/").unwrap().is_dir());
+        }
+    }
+
+    impl Drop for TestArchive {
+        fn drop(&mut self) {
+            for path in self.data_paths.values() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn stored_archive() {
+        let archive = TestArchive::new("stored");
+        let data = archive.write();
+        assert_eq!(archive.zip.size().unwrap(), Some(data.len()));
+        archive.check(data);
+    }
+
+    #[test]
+    fn compressed_archive() {
+        let mut archive = TestArchive::new("compressed");
+        archive.zip.set_compression(true);
+        assert_eq!(archive.zip.size().unwrap(), None);
+        let data = archive.write();
+        assert!(data.len() < 1000);
+        archive.check(data);
+    }
+
+    #[test]
+    fn dos_dates() {
+        // 2024-02-29 13:45:30 UTC
+        assert_eq!(dos_date_time(1_709_214_330_000), ((13 << 11) | (45 << 5) | 15, (44 << 9) | (2 << 5) | 29));
+        // 1980-01-01 00:00:00 UTC is the first representable date
+        assert_eq!(dos_date_time(315_532_800_000), (0, (1 << 5) | 1));
+        // 2107-12-31 23:59:59 UTC, with a two seconds precision
+        let last = ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+        assert_eq!(dos_date_time(4_354_819_199_000), last);
+    }
+
+    #[test]
+    fn dos_dates_are_clamped() {
+        let first = (0, (1 << 5) | 1);
+        assert_eq!(dos_date_time(315_532_799_000), first);
+        assert_eq!(dos_date_time(0), first);
+        assert_eq!(dos_date_time(-86_400_000), first);
+        let last = ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+        assert_eq!(dos_date_time(4_354_819_200_000), last);
+        assert_eq!(dos_date_time(i64::MAX / 1000), last);
+    }
+
+    #[test]
+    fn local_header_size_limit() {
+        let zip = AsyncDirectoryZip::new();
+        let below = zip.local_header(&file(1, &["a"], ZIP64_LIMIT as u64 - 1), 0).unwrap();
+        assert_eq!((u32_at(&below, 18), u32_at(&below, 22)), (ZIP64_LIMIT - 1, ZIP64_LIMIT - 1));
+        assert_eq!(u16_at(&below, 28), 9); // Extended timestamp only
+
+        let size = ZIP64_LIMIT as u64;
+        let at = zip.local_header(&file(1, &["a"], size), 0).unwrap();
+        assert_eq!((u32_at(&at, 18), u32_at(&at, 22)), (ZIP64_LIMIT, ZIP64_LIMIT));
+        assert_eq!(u16_at(&at, 28), 9 + 20);
+        let extra = &at[31..];
+        assert_eq!((u16_at(extra, 0), u16_at(extra, 2)), (0x0001, 16));
+        assert_eq!(&extra[4..12], &size.to_le_bytes());
+        assert_eq!(&extra[12..20], &size.to_le_bytes());
+    }
+
+    #[test]
+    fn central_directory_limits() {
+        let zip = AsyncDirectoryZip::new();
+        let small = file(1, &["a"], 10);
+        let entry = |start: usize, compressed_size: u64| WrittenEntry { start, crc32: 0, compressed_size };
+
+        let below = zip.make_central_directory(&small, &entry(ZIP64_LIMIT as usize - 1, 10)).unwrap();
+        assert_eq!(u32_at(&below, 42), ZIP64_LIMIT - 1);
+        assert_eq!(u16_at(&below, 30), 9);
+
+        // Only the offset overflows : the ZIP64 field contains only the offset
+        let start = ZIP64_LIMIT as usize;
+        let at = zip.make_central_directory(&small, &entry(start, 10)).unwrap();
+        assert_eq!((u32_at(&at, 20), u32_at(&at, 24), u32_at(&at, 42)), (10, 10, ZIP64_LIMIT));
+        let extra = &at[47..];
+        assert_eq!((u16_at(extra, 0), u16_at(extra, 2)), (0x0001, 8));
+        assert_eq!(&extra[4..12], &(start as u64).to_le_bytes());
+
+        // Sizes and offset overflow : sizes come first
+        let size = ZIP64_LIMIT as u64 + 5;
+        let both = zip.make_central_directory(&file(1, &["a"], size), &entry(start, size)).unwrap();
+        assert_eq!((u32_at(&both, 20), u32_at(&both, 24)), (ZIP64_LIMIT, ZIP64_LIMIT));
+        let extra = &both[47..];
+        assert_eq!(u16_at(extra, 2), 24);
+        assert_eq!(&extra[4..12], &size.to_le_bytes());
+        assert_eq!(&extra[12..20], &size.to_le_bytes());
+        assert_eq!(&extra[20..28], &(start as u64).to_le_bytes());
+    }
+
+    #[test]
+    fn end_of_directory_limits() {
+        let zip = AsyncDirectoryZip::new();
+        let start = ZIP64_LIMIT as usize - 1;
+        let below = zip.end_of_directory(start, start + 100);
+        assert_eq!(below.len(), 22);
+        assert_eq!(u32_at(&below, 16), ZIP64_LIMIT - 1);
+
+        let start = ZIP64_LIMIT as usize;
+        let at = zip.end_of_directory(start, start + 100);
+        assert_eq!(at.len(), 56 + 20 + 22);
+        assert_eq!(u32_at(&at, 0), 0x06064b50);
+        assert_eq!(&at[48..56], &(start as u64).to_le_bytes());
+        // The locator points to the ZIP64 record, written right after the central directory
+        assert_eq!(&at[64..72], &(start as u64 + 100).to_le_bytes());
+        assert_eq!(u32_at(&at, 76 + 16), ZIP64_LIMIT);
+    }
+}
@@ -7,12 +7,14 @@ use crate::query_fmt;
 #[macro_export]
 macro_rules! query_upgrade {
     ($db:expr, $old_schema_name:expr, $query:expr) => {{
-        $db.db().query(&$query.replace("OLD_SCHEMA_NAME", &$old_schema_name).replace("SCHEMA_NAME", &$db.schema_name), &[]).await?
+        let rows = $db.db().await?.query(&$query.replace("OLD_SCHEMA_NAME", &$old_schema_name).replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        rows
     }};
 
     ($db:expr, $old_schema_name:expr, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        $db.db().query(&$query.replace("OLD_SCHEMA_NAME", &$old_schema_name).replace("SCHEMA_NAME", &$db.schema_name), params).await?
+        let rows = $db.db().await?.query(&$query.replace("OLD_SCHEMA_NAME", &$old_schema_name).replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        rows
     }};
 }
 
@@ -20,12 +22,13 @@ pub struct Upgrade {}
 
 impl Upgrade {
     pub async fn run(db: &Database, old_schema_name: &String) -> Result<(), Error> {
-        Self::clean_local_data(db).await?;
-        Self::copy_users(db, old_schema_name).await?;
-        Self::copy_repository(db, old_schema_name).await?;
-        Self::copy_subscriptions(db, old_schema_name).await?;
-        Self::copy_items(db, old_schema_name).await?;
-        Ok(())
+        let transaction = db.begin().await?;
+        Self::clean_local_data(&transaction).await?;
+        Self::copy_users(&transaction, old_schema_name).await?;
+        Self::copy_repository(&transaction, old_schema_name).await?;
+        Self::copy_subscriptions(&transaction, old_schema_name).await?;
+        Self::copy_items(&transaction, old_schema_name).await?;
+        transaction.commit().await
     }
 
     async fn clean_local_data(db: &Database) -> Result<(), Error> {
@@ -0,0 +1,137 @@
+use crate::file_version::DbFileVersion;
+use crate::item::{DbItem, Trash};
+use crate::object::Object;
+use crate::Database;
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use types::conversion::{Conversion, ConversionOutput, ConversionStatus};
+use types::database_ids::{ConversionId, ObjectId, UserId};
+use types::enc_string::EncString;
+use types::item::{FileData, Item};
+
+pub struct DbConversion;
+
+impl DbConversion {
+    pub async fn from_id(db: &Database, id: &ConversionId) -> Result<Conversion, Error> {
+        query_object!(db, Conversion, "SELECT * FROM SCHEMA_NAME.conversions WHERE id = $1", id).ok_or(Error::msg("Conversion not found"))
+    }
+
+    /// Conversions requested by a user, most recent first
+    pub async fn from_owner(db: &Database, owner: &UserId) -> Result<Vec<Conversion>, Error> {
+        Ok(query_objects!(db, Conversion, "SELECT * FROM SCHEMA_NAME.conversions WHERE owner = $1 ORDER BY id DESC", owner))
+    }
+
+    pub async fn push(db: &Database, conversion: &mut Conversion) -> Result<(), Error> {
+        *conversion = query_object!(db, Conversion, "INSERT INTO SCHEMA_NAME.conversions (item, owner, source, target, quality, output) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+            conversion.item, conversion.owner, conversion.source, conversion.target, conversion.quality, conversion.output).ok_or(Error::msg("Failed to insert conversion"))?;
+        Ok(())
+    }
+
+    /// The conversion is running : its progress starts again from 0
+    pub async fn start(db: &Database, id: &ConversionId) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.conversions SET status = 'running', progress = 0 WHERE id = $1", id);
+        Ok(())
+    }
+
+    pub async fn set_status(db: &Database, id: &ConversionId, status: ConversionStatus, error: Option<&str>) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.conversions SET status = $1, last_error = $2 WHERE id = $3", status, error, id);
+        Ok(())
+    }
+
+    pub async fn set_progress(db: &Database, id: &ConversionId, progress: f32) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.conversions SET progress = $1 WHERE id = $2", progress, id);
+        Ok(())
+    }
+
+    /// Store the converted file. Fails if the conversion was cancelled meanwhile.
+    pub async fn set_result(db: &Database, id: &ConversionId, result: &ObjectId, size: i64) -> Result<(), Error> {
+        let rows = query_fmt!(db, "UPDATE SCHEMA_NAME.conversions SET status = 'done', progress = 1, result = $1, result_size = $2, last_error = NULL WHERE id = $3 RETURNING id",
+            result, size, id);
+        if rows.is_empty() {
+            return Err(Error::msg("Conversion was cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Apply a finished conversion : the converted file becomes a new item, or the new version of the file. In the
+    /// latter case, the original content stays in the history of the file, subject to the retention limit.
+    pub async fn confirm(db: &Database, conversion: &Conversion) -> Result<Item, Error> {
+        let result = match (&conversion.status, &conversion.result) {
+            (ConversionStatus::Done, Some(result)) => { result.clone() }
+            _ => { return Err(Error::msg("The conversion is not finished")) }
+        };
+        let transaction = db.begin().await?;
+        let original = DbItem::from_id(&transaction, &conversion.item, Trash::Both).await?;
+        let file = FileData {
+            size: conversion.result_size.unwrap_or_default(),
+            mimetype: EncString::from(conversion.target.mimetype()),
+            timestamp: original.file.as_ref().map(|file| file.timestamp).unwrap_or_default(),
+            object: result,
+        };
+        let name = Self::converted_name(&original.name, conversion.target.extension())?;
+
+        let item = match conversion.output {
+            ConversionOutput::NewItem => {
+                let mut item = Item::default();
+                item.repository = original.repository.clone();
+                item.owner = conversion.owner.clone();
+                item.name = Self::free_name(&transaction, &original, name).await?;
+                item.description = original.description.clone();
+                item.parent_item = original.parent_item.clone();
+                item.in_trash = original.in_trash;
+                DbFileVersion::set_current(&transaction, &mut item, file, &conversion.owner).await?;
+                item
+            }
+            ConversionOutput::NewVersion => {
+                if original.file.as_ref().map(|file| &file.object) != Some(&conversion.source) {
+                    return Err(Error::msg("The file was modified since the conversion was requested"));
+                }
+                let mut item = original.clone();
+                if name.encoded() != item.name.encoded() {
+                    item.name = Self::free_name(&transaction, &original, name).await?;
+                }
+                DbFileVersion::set_current(&transaction, &mut item, file, &conversion.owner).await?;
+                item
+            }
+        };
+
+        query_fmt!(transaction, "DELETE FROM SCHEMA_NAME.conversions WHERE id = $1", conversion.id());
+        let unused = DbFileVersion::unused_objects(&transaction, vec![conversion.source.clone()]).await?;
+        Object::delete_objects(&transaction, &unused).await?;
+        transaction.commit().await?;
+        // The path was updated by the database
+        DbItem::from_id(db, item.id(), Trash::Both).await
+    }
+
+    /// Discard a conversion and its converted file. The original file is left untouched.
+    pub async fn cancel(db: &Database, conversion: &Conversion) -> Result<(), Error> {
+        let transaction = db.begin().await?;
+        query_fmt!(transaction, "DELETE FROM SCHEMA_NAME.conversions WHERE id = $1", conversion.id());
+        let mut candidates = vec![conversion.source.clone()];
+        if let Some(result) = &conversion.result {
+            candidates.push(result.clone());
+        }
+        let unused = DbFileVersion::unused_objects(&transaction, candidates).await?;
+        Object::delete_objects(&transaction, &unused).await?;
+        transaction.commit().await
+    }
+
+    /// The given name if no other item of the directory of `item` uses it, or the first available name derived from it
+    async fn free_name(db: &Database, item: &Item, name: EncString) -> Result<EncString, Error> {
+        match DbItem::find_child(db, &item.repository, &item.parent_item, &name).await? {
+            None => { Ok(name) }
+            Some(_) => { DbItem::available_name(db, &item.repository, &item.parent_item, &name).await }
+        }
+    }
+
+    /// Name of the original file with the extension of the converted format
+    fn converted_name(name: &EncString, extension: &str) -> Result<EncString, Error> {
+        let plain = name.plain()?;
+        let stem = match plain.rfind('.') {
+            Some(index) if index > 0 => { &plain[..index] }
+            _ => { plain.as_str() }
+        };
+        Ok(EncString::encode(format!("{stem}.{extension}").as_str()))
+    }
+}
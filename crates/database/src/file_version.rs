@@ -0,0 +1,110 @@
+use crate::object::Object;
+use crate::repository::DbRepository;
+use crate::item::DbItem;
+use crate::Database;
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use serde::Serialize;
+use types::database_ids::{FileVersionId, ItemId, ObjectId, RepositoryId, UserId};
+use types::enc_string::EncString;
+use types::item::{FileData, Item};
+
+/// Revision of a file. The most recent version is the current content of the file.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct FileVersion {
+    id: FileVersionId,
+    pub item: ItemId,
+    pub size: i64,
+    pub mimetype: EncString,
+    pub timestamp: i64,
+    pub object: ObjectId,
+    pub uploader: Option<UserId>,
+}
+
+impl FileVersion {
+    pub fn id(&self) -> &FileVersionId {
+        &self.id
+    }
+
+    pub fn file_data(&self) -> FileData {
+        FileData {
+            size: self.size,
+            mimetype: self.mimetype.clone(),
+            timestamp: self.timestamp,
+            object: self.object.clone(),
+        }
+    }
+}
+
+pub struct DbFileVersion;
+
+impl DbFileVersion {
+    pub async fn from_id(db: &Database, id: &FileVersionId) -> Result<FileVersion, Error> {
+        query_object!(db, FileVersion, "SELECT * FROM SCHEMA_NAME.file_versions WHERE id = $1", id).ok_or(Error::msg("File version not found"))
+    }
+
+    /// Versions of an item, most recent first
+    pub async fn from_item(db: &Database, item: &ItemId) -> Result<Vec<FileVersion>, Error> {
+        Ok(query_objects!(db, FileVersion, "SELECT * FROM SCHEMA_NAME.file_versions WHERE item = $1 ORDER BY id DESC", item))
+    }
+
+    /// Replace the content of a file with a new revision, keeping the previous one in the history
+    pub async fn set_current(db: &Database, item: &mut Item, file: FileData, uploader: &UserId) -> Result<(), Error> {
+        if item.file.is_some() {
+            // Files created before version history was available have no recorded version yet
+            query_fmt!(db, "INSERT INTO SCHEMA_NAME.file_versions (item, size, mimetype, timestamp, object, uploader)
+                        SELECT id, size, mimetype, timestamp, object, $2 FROM SCHEMA_NAME.files
+                        WHERE id = $1 AND object IS NOT NULL AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.file_versions WHERE item = $1)",
+                item.id(), item.owner);
+        }
+
+        item.file = Some(file.clone());
+        DbItem::push(item, db).await?;
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.file_versions (item, size, mimetype, timestamp, object, uploader) VALUES ($1, $2, $3, $4, $5, $6)",
+            item.id(), file.size, file.mimetype, file.timestamp, file.object, uploader);
+
+        if let Some(max_versions) = DbRepository::from_id(db, &item.repository).await?.max_file_versions {
+            let unused = Self::apply_retention(db, item.id(), max_versions).await?;
+            Object::delete_objects(db, &unused).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the oldest versions of an item above the limit. Returns the objects that are not used anymore.
+    async fn apply_retention(db: &Database, item: &ItemId, max_versions: i64) -> Result<Vec<ObjectId>, Error> {
+        let removed = query_fmt!(db, "DELETE FROM SCHEMA_NAME.file_versions WHERE item = $1 AND id NOT IN (
+                        SELECT id FROM SCHEMA_NAME.file_versions WHERE item = $1 ORDER BY id DESC LIMIT $2) RETURNING object",
+            item, max_versions.max(1));
+        let mut candidates = vec![];
+        for row in removed {
+            candidates.push(row.try_get::<&str, ObjectId>("object")?);
+        }
+        Self::unused_objects(db, candidates).await
+    }
+
+    /// Apply the retention limit of a repository to all its files (after the limit was changed)
+    pub async fn apply_repository_retention(db: &Database, repository: &RepositoryId) -> Result<(), Error> {
+        let max_versions = match DbRepository::from_id(db, repository).await?.max_file_versions {
+            None => { return Ok(()) }
+            Some(max_versions) => { max_versions }
+        };
+        let items = query_objects!(db, ItemId, "SELECT DISTINCT item AS id FROM SCHEMA_NAME.file_versions WHERE item IN (SELECT id FROM SCHEMA_NAME.items WHERE repository = $1)", repository);
+        for item in items {
+            let unused = Self::apply_retention(db, &item, max_versions).await?;
+            Object::delete_objects(db, &unused).await?;
+        }
+        Ok(())
+    }
+
+    /// Objects that are neither the content of a file, of a version nor used by a conversion
+    pub(crate) async fn unused_objects(db: &Database, candidates: Vec<ObjectId>) -> Result<Vec<ObjectId>, Error> {
+        if candidates.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(query_objects!(db, ObjectId, "SELECT id FROM SCHEMA_NAME.objects WHERE id = ANY($1)
+                        AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.files WHERE object = objects.id)
+                        AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.file_versions WHERE object = objects.id)
+                        AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.conversions WHERE source = objects.id OR result = objects.id)", candidates))
+    }
+}
@@ -1,17 +1,23 @@
 use crate::object::{Object};
 use crate::{Database};
+use crate::query_builder::QueryBuilder;
 use types::enc_path::EncPath;
 use types::enc_string::EncString;
 use crate::{query_fmt, query_object, query_objects};
 use anyhow::Error;
+use chrono::NaiveDate;
 use postgres_from_row::FromRow;
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use types::database_ids::{DatabaseIdTrait, ItemId, ObjectId, RepositoryId, UserId};
-use types::item::Item;
+use types::item::{Item, MediaMetadata};
 
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 pub enum Trash {
     Yes,
+    #[default]
     No,
     Both,
 }
@@ -26,6 +32,21 @@ impl Display for Trash {
     }
 }
 
+/// What to do when an item with the same name already exists in the destination directory
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Reject the whole request
+    #[default]
+    Fail,
+    /// Leave the conflicting item where it is
+    Skip,
+    /// Pick a free name (ex: `photo (1).jpg`)
+    Rename,
+    /// Delete the existing item
+    Overwrite,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ItemSearchRepositoryField {
     pub repository: RepositoryId,
@@ -42,6 +63,177 @@ pub struct ItemSearchData {
     pub  min_size: Option<i64>,
     pub mime_type: Option<EncString>,
     pub owners: Option<Vec<UserId>>,
+    #[serde(default)]
+    pub trash: Trash,
+    /// Full-text search over names, descriptions and file contents
+    pub query: Option<String>,
+    /// Photos and videos taken between two dates (ms)
+    pub taken_before: Option<i64>,
+    pub taken_after: Option<i64>,
+    /// Make or model of the camera
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// Full-text search match
+#[derive(Serialize, Debug, FromRow)]
+pub struct SearchResult {
+    pub id: ItemId,
+    pub rank: f32,
+    pub snippet: Option<String>,
+}
+
+/// Maximum number of items returned in a single listing page
+const MAX_PAGE_SIZE: i64 = 5000;
+const DEFAULT_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemSort {
+    #[default]
+    Name,
+    Size,
+    Timestamp,
+    Mimetype,
+    /// Date the photo or video was taken, or its modification date if unknown
+    #[serde(rename = "capture_date")]
+    CaptureDate,
+}
+
+impl ItemSort {
+    /// Sort key (directories have no mimetype and no timestamp, their size is the size of their content)
+    fn expression(&self) -> &'static str {
+        match self {
+            ItemSort::Name => { "name" }
+            ItemSort::Size => { "COALESCE(size, content_size, 0)" }
+            ItemSort::Timestamp => { "COALESCE(timestamp, 0)" }
+            ItemSort::Mimetype => { "COALESCE(mimetype, '')" }
+            ItemSort::CaptureDate => { "COALESCE(capture_date, timestamp, 0)" }
+        }
+    }
+
+    fn key(&self, item: &Item) -> String {
+        match self {
+            ItemSort::Name => { item.name.encoded().to_string() }
+            ItemSort::Size => {
+                match (&item.file, &item.directory) {
+                    (Some(file), _) => { file.size }
+                    (None, Some(directory)) => { directory.content_size }
+                    (None, None) => { 0 }
+                }.to_string()
+            }
+            ItemSort::Timestamp => { item.file.as_ref().map(|file| file.timestamp).unwrap_or_default().to_string() }
+            ItemSort::Mimetype => { item.file.as_ref().map(|file| file.mimetype.encoded().to_string()).unwrap_or_default() }
+            ItemSort::CaptureDate => {
+                item.metadata.as_ref().and_then(|metadata| metadata.capture_date)
+                    .or(item.file.as_ref().map(|file| file.timestamp)).unwrap_or_default().to_string()
+            }
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, ItemSort::Size | ItemSort::Timestamp | ItemSort::CaptureDate)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Pagination parameters of item listings. The cursor is the `next_cursor` of the previous page.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ListingOptions {
+    #[serde(default)]
+    pub sort: ItemSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// One page of an item listing
+#[derive(Serialize, Debug)]
+pub struct ItemPage {
+    pub items: Vec<Item>,
+    /// Number of items in the whole listing
+    pub total: i64,
+    /// None if this is the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Photos and videos (mimetypes are stored url-encoded)
+const MEDIA_CONDITION: &str = "is_regular_file AND (STARTS_WITH(mimetype, 'image%2F') OR STARTS_WITH(mimetype, 'video%2F'))";
+/// Date used to place a file in the timeline (ms)
+const TIMELINE_DATE: &str = "COALESCE(capture_date, timestamp)";
+
+/// Part of the timeline. Dates are grouped in the time zone of the client, given as an offset to UTC in minutes.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct TimelinePeriod {
+    pub year: Option<i32>,
+    /// Requires the year
+    pub month: Option<i32>,
+    /// Requires the month
+    pub day: Option<i32>,
+    #[serde(default)]
+    pub utc_offset: i32,
+}
+
+impl TimelinePeriod {
+    /// Reject incomplete periods and dates that do not exist (ex: February 31st)
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.utc_offset.abs() > 14 * 60 {
+            return Err(Error::msg("Invalid utc offset"));
+        }
+        let (year, month, day) = match (self.year, self.month, self.day) {
+            (None, None, None) => { return Ok(()) }
+            (Some(year), month, None) => { (year, month.unwrap_or(1), 1) }
+            (Some(year), Some(month), Some(day)) => { (year, month, day) }
+            _ => { return Err(Error::msg("Invalid timeline period")) }
+        };
+        let date = match (u32::try_from(month), u32::try_from(day)) {
+            (Ok(month), Ok(day)) if (1..=9999).contains(&year) => { NaiveDate::from_ymd_opt(year, month, day) }
+            _ => { None }
+        };
+        match date {
+            None => { Err(Error::msg(format!("Invalid timeline date : {year}-{month}-{day}"))) }
+            Some(_) => { Ok(()) }
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct TimelineCount {
+    year: i32,
+    month: i32,
+    day: i32,
+    count: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TimelineDay {
+    pub day: i32,
+    pub count: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TimelineMonth {
+    pub month: i32,
+    pub count: i64,
+    pub days: Vec<TimelineDay>,
+}
+
+/// Number of photos and videos taken each year, month and day, most recent first
+#[derive(Serialize, Debug)]
+pub struct TimelineYear {
+    pub year: i32,
+    pub count: i64,
+    pub months: Vec<TimelineMonth>,
 }
 
 pub struct DbItem;
@@ -74,64 +266,276 @@ impl DbItem {
         Ok(query_objects!(db, Item, format!("SELECT * FROM SCHEMA_NAME.item_full_view WHERE parent_item IS NULL and repository = $1 {filter}"), repository))
     }
 
-    pub async fn repository_trash_root(db: &Database, repository: &RepositoryId) -> Result<Vec<Item>, Error> {
-        Ok(query_objects!(db, Item, "SELECT * FROM SCHEMA_NAME.item_full_view WHERE in_trash AND repository = $1 AND (parent_item IS NULL OR parent_item IN (SELECT id FROM SCHEMA_NAME.item_full_view WHERE repository = $1 AND NOT in_trash))", repository))
+    /// Paginated content of a set of directories
+    pub async fn from_parents_paged(db: &Database, parents: Vec<ItemId>, filter: Trash, options: &ListingOptions) -> Result<ItemPage, Error> {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        let parents = query.bind(parents);
+        query.condition(format!("parent_item = ANY({parents}) {filter}"));
+        Self::paginate(db, query, options).await
     }
-    
-    pub async fn search(db: &Database, filter: ItemSearchData) -> Result<Vec<Item>, Error> {
 
-        if filter.repositories.is_empty() {
-            Err(Error::msg("No repository specified"))?;
+    /// Paginated root items of a set of repositories
+    pub async fn repository_root_paged(db: &Database, repositories: Vec<RepositoryId>, filter: Trash, options: &ListingOptions) -> Result<ItemPage, Error> {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        let repositories = query.bind(repositories);
+        query.condition(format!("parent_item IS NULL AND repository = ANY({repositories}) {filter}"));
+        Self::paginate(db, query, options).await
+    }
+
+    /// Paginated content of a whole repository
+    pub async fn from_repository_paged(db: &Database, repository: &RepositoryId, filter: Trash, options: &ListingOptions) -> Result<ItemPage, Error> {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        let repository = query.bind(repository.clone());
+        query.condition(format!("repository = {repository} {filter}"));
+        Self::paginate(db, query, options).await
+    }
+
+    /// Photos and videos of a repository taken during the given period, whatever their directory
+    pub async fn timeline_paged(db: &Database, repository: &RepositoryId, period: &TimelinePeriod, options: &ListingOptions) -> Result<ItemPage, Error> {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        Self::timeline_conditions(&mut query, repository, period)?;
+        let mut options = options.clone();
+        options.sort = ItemSort::CaptureDate;
+        Self::paginate(db, query, &options).await
+    }
+
+    /// Number of photos and videos of a repository per day of the given period
+    pub async fn timeline(db: &Database, repository: &RepositoryId, period: &TimelinePeriod) -> Result<Vec<TimelineYear>, Error> {
+        // The offset is always the first bound parameter
+        let mut query = QueryBuilder::new(format!("SELECT EXTRACT(YEAR FROM date)::INTEGER AS year, EXTRACT(MONTH FROM date)::INTEGER AS month, EXTRACT(DAY FROM date)::INTEGER AS day, COUNT(*) AS count FROM (
+                SELECT (TO_TIMESTAMP(({TIMELINE_DATE} + $1) / 1000.0) AT TIME ZONE 'UTC')::DATE AS date FROM SCHEMA_NAME.item_full_view").as_str());
+        query.bind(period.utc_offset as i64 * 60 * 1000);
+        Self::timeline_conditions(&mut query, repository, period)?;
+        query.suffix(") AS dates GROUP BY date ORDER BY date DESC");
+
+        let mut years: Vec<TimelineYear> = vec![];
+        for count in query.fetch::<TimelineCount>(db).await? {
+            let year = match years.last_mut() {
+                Some(year) if year.year == count.year => { year }
+                _ => {
+                    years.push(TimelineYear { year: count.year, count: 0, months: vec![] });
+                    years.last_mut().unwrap()
+                }
+            };
+            year.count += count.count;
+            let month = match year.months.last_mut() {
+                Some(month) if month.month == count.month => { month }
+                _ => {
+                    year.months.push(TimelineMonth { month: count.month, count: 0, days: vec![] });
+                    year.months.last_mut().unwrap()
+                }
+            };
+            month.count += count.count;
+            month.days.push(TimelineDay { day: count.day, count: count.count });
         }
+        Ok(years)
+    }
 
-        let mut repository_req = String::new();
-        for repository in filter.repositories {
-            repository_req += format!("repository = {} AND ", repository.repository).as_str();
-            let mut item_req = String::new();
-            for (i, item) in repository.root_items.iter().enumerate() {
-                item_req += format!("STARTS_WITH(absolute_path, SELECT(absolute_path FROM item WHERE id = {item}))").as_str();
-                if i != repository.root_items.len() - 1 { item_req += " OR " }
+    /// Restrict the query to the photos and videos of the repository taken during the period. The bounds of the
+    /// period are computed in the time zone of the client.
+    fn timeline_conditions(query: &mut QueryBuilder, repository: &RepositoryId, period: &TimelinePeriod) -> Result<(), Error> {
+        period.validate()?;
+        let repository = query.bind(repository.clone());
+        query.condition(format!("repository = {repository} AND NOT in_trash AND {MEDIA_CONDITION}"));
+
+        let interval = match (period.year, period.month, period.day) {
+            (None, _, _) => { return Ok(()) }
+            (Some(_), None, _) => { "1 year" }
+            (Some(_), Some(_), None) => { "1 month" }
+            (Some(_), Some(_), Some(_)) => { "1 day" }
+        };
+        let start = format!("MAKE_DATE({}, {}, {})", query.bind(period.year.unwrap_or_default()), query.bind(period.month.unwrap_or(1)), query.bind(period.day.unwrap_or(1)));
+        let offset = query.bind(period.utc_offset as i64 * 60 * 1000);
+        query.condition(format!("{TIMELINE_DATE} >= (EXTRACT(EPOCH FROM {start}::TIMESTAMP) * 1000)::BIGINT - {offset}"));
+        query.condition(format!("{TIMELINE_DATE} < (EXTRACT(EPOCH FROM {start} + INTERVAL '{interval}') * 1000)::BIGINT - {offset}"));
+        Ok(())
+    }
+
+    /// Keyset pagination : items are ordered by (sort key, id) and the cursor holds the values of
+    /// the last returned item, so that pages stay consistent when items are added or removed.
+    async fn paginate(db: &Database, mut query: QueryBuilder, options: &ListingOptions) -> Result<ItemPage, Error> {
+        let total = query.count(db).await?;
+        let limit = options.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let key = options.sort.expression();
+        let (comparator, order) = match options.order {
+            SortOrder::Asc => { (">", "ASC") }
+            SortOrder::Desc => { ("<", "DESC") }
+        };
+
+        if let Some(cursor) = &options.cursor {
+            let (id, value) = cursor.split_once('.').ok_or(Error::msg("Invalid cursor"))?;
+            let id = query.bind(ItemId::from(i64::from_str(id).map_err(|_| Error::msg("Invalid cursor"))?));
+            let value = if options.sort.is_numeric() {
+                query.bind(i64::from_str(value).map_err(|_| Error::msg("Invalid cursor"))?)
+            } else {
+                query.bind(value.to_string())
+            };
+            query.condition(format!("({key}, id) {comparator} ({value}, {id})"));
+        }
+        query.suffix(format!("ORDER BY {key} {order}, id {order} LIMIT {}", limit + 1));
+
+        let mut items: Vec<Item> = query.fetch(db).await?;
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(|last| format!("{}.{}", last.id(), options.sort.key(last)))
+        } else { None };
+        Ok(ItemPage { items, total, next_cursor })
+    }
+
+    /// Find an item by name in a directory, or in the repository root if parent is None
+    pub async fn find_child(db: &Database, repository: &RepositoryId, parent: &Option<ItemId>, name: &EncString) -> Result<Option<Item>, Error> {
+        Ok(match parent {
+            None => { query_object!(db, Item, "SELECT * FROM SCHEMA_NAME.item_full_view WHERE parent_item IS NULL AND repository = $1 AND name = $2", repository, name) }
+            Some(parent) => { query_object!(db, Item, "SELECT * FROM SCHEMA_NAME.item_full_view WHERE parent_item = $1 AND name = $2", parent, name) }
+        })
+    }
+
+    /// First name derived from the given one that is not used in the directory (`name (1).ext`, `name (2).ext`...)
+    pub async fn available_name(db: &Database, repository: &RepositoryId, parent: &Option<ItemId>, name: &EncString) -> Result<EncString, Error> {
+        let plain = name.plain()?;
+        let (stem, extension) = match plain.rfind('.') {
+            Some(index) if index > 0 => { plain.split_at(index) }
+            _ => { (plain.as_str(), "") }
+        };
+        let mut index = 1;
+        loop {
+            let candidate = EncString::encode(format!("{stem} ({index}){extension}").as_str());
+            if Self::find_child(db, repository, parent, &candidate).await?.is_none() {
+                return Ok(candidate);
             }
-            repository_req += format!("({item_req}) AND").as_str();
+            index += 1;
         }
+    }
 
-        let name = if let Some(name) = filter.name_filter {
-            format!("LOWER(name) LIKE '%' || LOWER('{name}') || '%' AND")
-        } else { String::new() };
+    /// Move an item and its content to another directory (or repository root), optionally renaming it
+    pub async fn move_to(db: &Database, item: &ItemId, parent: &Option<ItemId>, repository: &RepositoryId, name: &EncString) -> Result<(), Error> {
+        let transaction = db.begin().await?;
+        query_fmt!(transaction, "CALL SCHEMA_NAME.move_item($1, $2, $3, $4)", item, parent, repository, name);
+        query_fmt!(transaction, "UPDATE SCHEMA_NAME.item_search SET plain_name = $2 WHERE id = $1", item, name.plain()?);
+        transaction.commit().await
+    }
 
-        let before = if let Some(before) = filter.before {
-            format!("timestamp <= {before} AND")
-        } else { String::new() };
+    /// Recursively copy an item. Copied files share the objects of the source files, their search data and metadata.
+    pub async fn copy_to(db: &Database, item: &ItemId, parent: &Option<ItemId>, repository: &RepositoryId, name: &EncString, owner: &UserId) -> Result<ItemId, Error> {
+        let transaction = db.begin().await?;
+        let copy = query_object!(transaction, ItemId, "SELECT SCHEMA_NAME.copy_item($1, $2, $3, $4, $5) AS id", item, parent, repository, name, owner).ok_or(Error::msg("Failed to copy item"))?;
+        query_fmt!(transaction, "INSERT INTO SCHEMA_NAME.item_search (id, plain_name) VALUES ($1, $2) ON CONFLICT(id) DO UPDATE SET plain_name = $2", copy, name.plain()?);
+        transaction.commit().await?;
+        Ok(copy)
+    }
 
-        let after = if let Some(after) = filter.after {
-            format!("timestamp >= {after} AND")
-        } else { String::new() };
+    pub async fn repository_trash_root(db: &Database, repository: &RepositoryId) -> Result<Vec<Item>, Error> {
+        Ok(query_objects!(db, Item, "SELECT * FROM SCHEMA_NAME.item_full_view WHERE in_trash AND repository = $1 AND (parent_item IS NULL OR parent_item IN (SELECT id FROM SCHEMA_NAME.item_full_view WHERE repository = $1 AND NOT in_trash))", repository))
+    }
+    
+    pub async fn search(db: &Database, filter: ItemSearchData) -> Result<Vec<Item>, Error> {
+        Self::search_query(filter)?.fetch(db).await
+    }
 
-        let max_size = if let Some(max_size) = filter.max_size {
-            format!("size <= {max_size} AND")
-        } else { String::new() };
+    /// Full-text search, ordered by relevance
+    pub async fn search_ranked(db: &Database, filter: ItemSearchData) -> Result<Vec<SearchResult>, Error> {
+        if filter.query.is_none() {
+            return Err(Error::msg("No search query specified"));
+        }
+        Self::search_query(filter)?.fetch(db).await
+    }
 
-        let min_size = if let Some(min_size) = filter.min_size {
-            format!("size >= {min_size} AND")
-        } else { String::new() };
+    /// Build the search request. Items are matched if they belong to any of the repositories (and
+    /// are contained in one of the root items if specified) and to any of the owners.
+    pub fn search_query(filter: ItemSearchData) -> Result<QueryBuilder, Error> {
+        if filter.repositories.is_empty() {
+            Err(Error::msg("No repository specified"))?;
+        }
 
-        let mimetype = if let Some(mimetype) = filter.mime_type {
-            format!("LOWER(mimetype) LIKE '%' || LOWER('{}') || '%' AND", mimetype.encoded())
-        } else { String::new() };
+        let mut query = match &filter.query {
+            None => { QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view") }
+            Some(text) => {
+                // The search text is always the first bound parameter
+                let mut query = QueryBuilder::new("SELECT item_full_view.*,
+                    ts_rank(item_search.search_vector, websearch_to_tsquery('simple', $1)) AS rank,
+                    ts_headline('simple', coalesce(item_search.content, item_search.plain_description, item_search.plain_name), websearch_to_tsquery('simple', $1), 'MaxFragments=2, MaxWords=20, MinWords=5, StartSel=**, StopSel=**') AS snippet
+                    FROM SCHEMA_NAME.item_full_view JOIN SCHEMA_NAME.item_search USING(id)");
+                let text = query.bind(text.clone());
+                query.condition(format!("item_search.search_vector @@ websearch_to_tsquery('simple', {text})"));
+                query.suffix("ORDER BY rank DESC LIMIT 200");
+                query
+            }
+        };
+        query.condition("is_regular_file");
 
-        let owners = if let Some(owners) = filter.owners {
-            let mut owner_req = String::new();
-            for owner in owners { owner_req += format!("owner = {owner} AND").as_str() }
-            owner_req
-        } else { String::new() };
+        let mut repositories = vec![];
+        for repository in filter.repositories {
+            let repository_condition = format!("repository = {}", query.bind(repository.repository));
+            if repository.root_items.is_empty() {
+                repositories.push(repository_condition);
+            } else {
+                let roots = query.bind(repository.root_items);
+                repositories.push(format!("({repository_condition} AND EXISTS (SELECT 1 FROM SCHEMA_NAME.items AS root WHERE root.id = ANY({roots}) AND root.repository = item_full_view.repository AND (root.id = item_full_view.id OR STARTS_WITH(item_full_view.absolute_path, root.absolute_path || '/'))))"));
+            }
+        }
+        query.condition(QueryBuilder::any_of(repositories));
 
-        Ok(query_objects!(&db, Item, format!("SELECT * FROM SCHEMA_NAME.item_full_view WHERE {repository_req} {name} {before} {after} {max_size} {min_size} {mimetype} {owners} is_regular_file")))
+        if let Some(name) = filter.name_filter {
+            let name = query.bind(QueryBuilder::escape_like(name.encoded()));
+            query.condition(format!("LOWER(name) LIKE '%' || LOWER({name}) || '%' ESCAPE '\\'"));
+        }
+        if let Some(before) = filter.before {
+            let before = query.bind(before);
+            query.condition(format!("timestamp <= {before}"));
+        }
+        if let Some(after) = filter.after {
+            let after = query.bind(after);
+            query.condition(format!("timestamp >= {after}"));
+        }
+        if let Some(max_size) = filter.max_size {
+            let max_size = query.bind(max_size);
+            query.condition(format!("size <= {max_size}"));
+        }
+        if let Some(min_size) = filter.min_size {
+            let min_size = query.bind(min_size);
+            query.condition(format!("size >= {min_size}"));
+        }
+        if let Some(mimetype) = filter.mime_type {
+            let mimetype = query.bind(QueryBuilder::escape_like(mimetype.encoded()));
+            query.condition(format!("LOWER(mimetype) LIKE '%' || LOWER({mimetype}) || '%' ESCAPE '\\'"));
+        }
+        if let Some(taken_before) = filter.taken_before {
+            let taken_before = query.bind(taken_before);
+            query.condition(format!("capture_date <= {taken_before}"));
+        }
+        if let Some(taken_after) = filter.taken_after {
+            let taken_after = query.bind(taken_after);
+            query.condition(format!("capture_date >= {taken_after}"));
+        }
+        if let Some(camera) = filter.camera {
+            let camera = query.bind(QueryBuilder::escape_like(&camera));
+            query.condition(format!("LOWER(COALESCE(camera_make, '') || ' ' || COALESCE(camera_model, '')) LIKE '%' || LOWER({camera}) || '%' ESCAPE '\\'"));
+        }
+        for (column, value) in [("lens", filter.lens), ("artist", filter.artist), ("album", filter.album)] {
+            if let Some(value) = value {
+                let value = query.bind(QueryBuilder::escape_like(&value));
+                query.condition(format!("LOWER({column}) LIKE '%' || LOWER({value}) || '%' ESCAPE '\\'"));
+            }
+        }
+        if let Some(owners) = filter.owners {
+            if !owners.is_empty() {
+                let owners = query.bind(owners);
+                query.condition(format!("owner = ANY({owners})"));
+            }
+        }
+        match filter.trash {
+            Trash::Yes => { query.condition("in_trash"); }
+            Trash::No => { query.condition("NOT in_trash"); }
+            Trash::Both => {}
+        }
+        Ok(query)
     }
 
     pub async fn delete(item: &Item, db: &Database) -> Result<(), Error> {
-        let childs = query_objects!(db, ObjectId, r#"SELECT UNNEST(SCHEMA_NAME.remove_item($1)) AS id GROUP BY id;"#, item.id());
-        Object::delete_objects(db, &childs).await?;
-        Ok(())
+        let transaction = db.begin().await?;
+        let childs = query_objects!(transaction, ObjectId, r#"SELECT UNNEST(SCHEMA_NAME.remove_item($1)) AS id GROUP BY id;"#, item.id());
+        Object::delete_objects(&transaction, &childs).await?;
+        transaction.commit().await
     }
 
     pub async fn push(item: &mut Item, db: &Database) -> Result<(), Error> {
@@ -139,15 +543,17 @@ impl DbItem {
             return Err(Error::msg("Cannot push : neither a file or a directory"));
         }
 
+        let transaction = db.begin().await?;
+
         if item.id().is_valid() {
-            query_fmt!(db, "INSERT INTO SCHEMA_NAME.items
+            query_fmt!(transaction, "INSERT INTO SCHEMA_NAME.items
                         (id, repository, owner, name, is_regular_file, description, parent_item, absolute_path, in_trash) VALUES
                         ($1, $2, $3, $4, $5, $6, $7, $8, $9)
                         ON CONFLICT(id) DO UPDATE SET
                         id = $1, repository = $2, owner = $3, name = $4, is_regular_file = $5, description = $6, parent_item = $7, absolute_path = $8, in_trash = $9;",
                 item.id(), item.repository, item.owner, item.name, item.file.is_some(), item.description, item.parent_item, item.absolute_path, item.in_trash);
         } else {
-            let res = query_object!(db, ItemId, "INSERT INTO SCHEMA_NAME.items
+            let res = query_object!(transaction, ItemId, "INSERT INTO SCHEMA_NAME.items
                         (repository, owner, name, is_regular_file, description, parent_item, absolute_path, in_trash) VALUES
                         ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
                 item.repository, item.owner, item.name, item.file.is_some(), item.description, item.parent_item, item.absolute_path, item.in_trash);
@@ -156,21 +562,232 @@ impl DbItem {
             }
         }
 
+        query_fmt!(transaction, "INSERT INTO SCHEMA_NAME.item_search
+                        (id, plain_name, plain_description) VALUES
+                        ($1, $2, $3)
+                        ON CONFLICT(id) DO UPDATE SET
+                        plain_name = $2, plain_description = $3;",
+            item.id(), item.name.plain()?, item.description.as_ref().map(|description| description.plain()).transpose()?);
+
         if let Some(file) = &item.file {
-            query_fmt!(db, "INSERT INTO SCHEMA_NAME.files
+            query_fmt!(transaction, "INSERT INTO SCHEMA_NAME.files
                         (id, size, mimetype, timestamp, object) VALUES
                         ($1, $2, $3, $4, $5)
                         ON CONFLICT(id) DO UPDATE SET
                         id = $1, size = $2, mimetype = $3, timestamp = $4, object = $5;",
                 item.id(), file.size, file.mimetype, file.timestamp, file.object);
         } else if let Some(directory) = &item.directory {
-            query_fmt!(db, "INSERT INTO SCHEMA_NAME.directories
+            query_fmt!(transaction, "INSERT INTO SCHEMA_NAME.directories
                         (id, open_upload) VALUES
                         ($1, $2)
                         ON CONFLICT(id) DO UPDATE SET
                         id = $1, open_upload = $2;",
                 item.id(), directory.open_upload);
         }
+        transaction.commit().await
+    }
+
+    /// Store the text extracted from the file content for full-text search
+    pub async fn set_search_content(db: &Database, id: &ItemId, content: &str) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.item_search SET content = $2 WHERE id = $1", id, content);
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Store the metadata read from the content of the file, or remove it if the new content has none
+    pub async fn set_metadata(db: &Database, id: &ItemId, metadata: Option<&MediaMetadata>) -> Result<(), Error> {
+        let metadata = match metadata {
+            None => {
+                query_fmt!(db, "DELETE FROM SCHEMA_NAME.item_metadata WHERE id = $1", id);
+                return Ok(());
+            }
+            Some(metadata) => { metadata }
+        };
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.item_metadata
+                        (id, extracted_at, capture_date, camera_make, camera_model, lens, latitude, longitude, width, height, orientation, duration, video_codec, audio_codec, artist, album, title, track) VALUES
+                        ($1, (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+                        ON CONFLICT(id) DO UPDATE SET
+                        extracted_at = EXCLUDED.extracted_at, capture_date = $2, camera_make = $3, camera_model = $4, lens = $5, latitude = $6, longitude = $7, width = $8, height = $9,
+                        orientation = $10, duration = $11, video_codec = $12, audio_codec = $13, artist = $14, album = $15, title = $16, track = $17;",
+            id, metadata.capture_date, metadata.camera_make, metadata.camera_model, metadata.lens, metadata.latitude, metadata.longitude, metadata.width, metadata.height,
+            metadata.orientation, metadata.duration, metadata.video_codec, metadata.audio_codec, metadata.artist, metadata.album, metadata.title, metadata.track);
+        Ok(())
+    }
+
+    /// Create the missing search entries (items created before full-text search was available)
+    pub async fn index_missing(db: &Database) -> Result<usize, Error> {
+        let items = query_objects!(db, Item, "SELECT * FROM SCHEMA_NAME.item_full_view WHERE id NOT IN (SELECT id FROM SCHEMA_NAME.item_search)");
+        for item in &items {
+            query_fmt!(db, "INSERT INTO SCHEMA_NAME.item_search (id, plain_name, plain_description) VALUES ($1, $2, $3) ON CONFLICT(id) DO NOTHING",
+                item.id(), item.name.plain()?, item.description.as_ref().map(|description| description.plain()).transpose()?);
+        }
+        Ok(items.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbItem, ItemSearchData, TimelinePeriod};
+    use types::database_ids::RepositoryId;
+    use crate::query_builder::QueryBuilder;
+    use serde_json::json;
+    use types::enc_string::EncString;
+
+    const REPOSITORY_1: &str = "repository = $1";
+
+    /// Every optional filter, in the order of the conditions : field, value, condition (`{}` stands for the
+    /// placeholder) and bound parameter
+    const FILTERS: &[(&str, &str, &str, &str)] = &[
+        ("name_filter", r#""photo""#, "LOWER(name) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"photo\""),
+        ("before", "100", "timestamp <= {}", "100"),
+        ("after", "50", "timestamp >= {}", "50"),
+        ("max_size", "2000", "size <= {}", "2000"),
+        ("min_size", "1000", "size >= {}", "1000"),
+        ("mime_type", r#""image""#, "LOWER(mimetype) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"image\""),
+        ("taken_before", "200", "capture_date <= {}", "200"),
+        ("taken_after", "150", "capture_date >= {}", "150"),
+        ("camera", r#""canon""#, "LOWER(COALESCE(camera_make, '') || ' ' || COALESCE(camera_model, '')) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"canon\""),
+        ("lens", r#""50mm""#, "LOWER(lens) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"50mm\""),
+        ("artist", r#""bach""#, "LOWER(artist) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"bach\""),
+        ("album", r#""live""#, "LOWER(album) LIKE '%' || LOWER({}) || '%' ESCAPE '\\'", "\"live\""),
+        ("owners", r#"["5", "6"]"#, "owner = ANY({})", "[UserId(5), UserId(6)]"),
+    ];
+
+    fn search(filter: serde_json::Value) -> QueryBuilder {
+        DbItem::search_query(serde_json::from_value::<ItemSearchData>(filter).unwrap()).unwrap()
+    }
+
+    fn conditions(query: &QueryBuilder) -> String {
+        let sql = query.sql();
+        sql[sql.find(" WHERE ").expect("missing WHERE clause") + 7..].to_string()
+    }
+
+    fn params(query: &QueryBuilder) -> Vec<String> {
+        query.params().iter().map(|param| format!("{param:?}")).collect()
+    }
+
+    #[test]
+    fn requires_a_repository() {
+        let filter = serde_json::from_value::<ItemSearchData>(json!({"repositories": []})).unwrap();
+        assert!(DbItem::search_query(filter).is_err());
+    }
+
+    #[test]
+    fn repository_only() {
+        let query = search(json!({"repositories": [{"repository": "1", "root_items": []}]}));
+        assert_eq!(query.sql(), format!("SELECT * FROM SCHEMA_NAME.item_full_view WHERE is_regular_file AND ({REPOSITORY_1}) AND NOT in_trash"));
+        assert_eq!(params(&query), vec!["RepositoryId(1)"]);
+    }
+
+    #[test]
+    fn repositories_are_alternatives() {
+        let query = search(json!({"repositories": [
+            {"repository": "1", "root_items": []},
+            {"repository": "2", "root_items": ["10", "11"]}
+        ]}));
+        let conditions = conditions(&query);
+        assert!(conditions.starts_with(&format!("is_regular_file AND ({REPOSITORY_1} OR (repository = $2 AND EXISTS (")));
+        assert!(conditions.contains("root.id = ANY($3)"));
+        assert_eq!(params(&query), vec!["RepositoryId(1)", "RepositoryId(2)", "[ItemId(10), ItemId(11)]"]);
+    }
+
+    #[test]
+    fn trash() {
+        for (trash, condition) in [("yes", " AND in_trash"), ("no", " AND NOT in_trash"), ("both", "")] {
+            let query = search(json!({"repositories": [{"repository": "1", "root_items": []}], "trash": trash}));
+            assert_eq!(conditions(&query), format!("is_regular_file AND ({REPOSITORY_1}){condition}"));
+        }
+    }
+
+    #[test]
+    fn every_combination_of_filters() {
+        for combination in 0..1u32 << FILTERS.len() {
+            let mut filter = json!({"repositories": [{"repository": "1", "root_items": []}]});
+            let mut expected_conditions = vec!["is_regular_file".to_string(), format!("({REPOSITORY_1})")];
+            let mut expected_params = vec!["RepositoryId(1)".to_string()];
+            for (index, (field, value, condition, param)) in FILTERS.iter().enumerate() {
+                if combination & 1 << index != 0 {
+                    filter[field] = serde_json::from_str(value).unwrap();
+                    expected_params.push(param.to_string());
+                    expected_conditions.push(condition.replace("{}", &format!("${}", expected_params.len())));
+                }
+            }
+            expected_conditions.push("NOT in_trash".to_string());
+            let query = search(filter.clone());
+            assert_eq!(conditions(&query), expected_conditions.join(" AND "), "{filter}");
+            assert_eq!(params(&query), expected_params, "{filter}");
+        }
+    }
+
+    #[test]
+    fn empty_owner_list_is_ignored() {
+        let query = search(json!({"repositories": [{"repository": "1", "root_items": []}], "owners": []}));
+        assert_eq!(conditions(&query), format!("is_regular_file AND ({REPOSITORY_1}) AND NOT in_trash"));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        let query = search(json!({
+            "repositories": [{"repository": "1", "root_items": []}],
+            "name_filter": "my_file",
+            "mime_type": "%",
+            "camera": r"EOS_5D\100%"
+        }));
+        assert_eq!(params(&query), vec!["RepositoryId(1)", r#""my\\_file""#, r#""\\%""#, r#""EOS\\_5D\\\\100\\%""#]);
+    }
+
+    #[test]
+    fn name_filter_is_matched_on_the_encoded_name() {
+        // Names are stored encoded : the filter must match the stored form and keep its '%' literal
+        let name = EncString::encode("100% sure");
+        let query = search(json!({"repositories": [{"repository": "1", "root_items": []}], "name_filter": name.encoded()}));
+        assert_eq!(name.encoded(), "100%25%20sure");
+        assert_eq!(params(&query), vec!["RepositoryId(1)", r#""100\\%25\\%20sure""#]);
+    }
+
+    #[test]
+    fn full_text_query_is_the_first_parameter() {
+        let query = search(json!({
+            "repositories": [{"repository": "1", "root_items": []}],
+            "query": "holiday photos",
+            "min_size": 10
+        }));
+        let sql = query.sql();
+        assert!(sql.contains("websearch_to_tsquery('simple', $1)"));
+        assert!(sql.ends_with("AND size >= $3 AND NOT in_trash ORDER BY rank DESC LIMIT 200"));
+        assert!(conditions(&query).starts_with("item_search.search_vector @@ websearch_to_tsquery('simple', $1) AND is_regular_file AND (repository = $2)"));
+        assert_eq!(params(&query), vec!["\"holiday photos\"", "RepositoryId(1)", "10"]);
+    }
+
+    fn period(year: Option<i32>, month: Option<i32>, day: Option<i32>) -> TimelinePeriod {
+        TimelinePeriod { year, month, day, utc_offset: 0 }
+    }
+
+    #[test]
+    fn timeline_periods() {
+        for valid in [period(None, None, None), period(Some(2024), None, None), period(Some(2024), Some(12), None), period(Some(2024), Some(2), Some(29)), period(Some(1), Some(1), Some(1)), period(Some(9999), Some(12), Some(31))] {
+            assert!(valid.validate().is_ok(), "{valid:?}");
+        }
+        let invalid = [
+            period(Some(2024), Some(2), Some(31)), period(Some(2023), Some(2), Some(29)), period(Some(2024), Some(4), Some(31)),
+            period(Some(2024), Some(13), None), period(Some(2024), Some(0), None), period(Some(2024), Some(-1), None), period(Some(2024), Some(1), Some(0)),
+            period(Some(0), None, None), period(Some(-5), Some(1), Some(1)), period(Some(10000), None, None), period(Some(i32::MAX), None, None),
+            // Incomplete periods
+            period(None, Some(1), None), period(None, None, Some(1)), period(Some(2024), None, Some(1)),
+        ];
+        for invalid in invalid {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+        assert!(TimelinePeriod { utc_offset: 15 * 60, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn timeline_conditions() {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        assert!(DbItem::timeline_conditions(&mut query, &RepositoryId::from(1), &period(Some(2024), Some(2), Some(31))).is_err());
+
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.item_full_view");
+        DbItem::timeline_conditions(&mut query, &RepositoryId::from(1), &TimelinePeriod { year: Some(2024), month: Some(2), day: None, utc_offset: 60 }).unwrap();
+        assert!(conditions(&query).contains("MAKE_DATE($2, $3, $4) + INTERVAL '1 month'"));
+        assert_eq!(params(&query), vec!["RepositoryId(1)", "2024", "2", "1", "3600000"]);
+    }
+}
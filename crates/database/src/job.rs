@@ -0,0 +1,66 @@
+use crate::Database;
+use crate::{query_fmt, query_object};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::database_ids::{JobId, ObjectId};
+use types::job::{Job, JobKind, JobStatus};
+
+pub struct DbJob;
+
+impl DbJob {
+    pub async fn from_object(db: &Database, kind: &JobKind, object: &ObjectId, size: i32) -> Result<Option<Job>, Error> {
+        Ok(query_object!(db, Job, "SELECT * FROM SCHEMA_NAME.jobs WHERE kind = $1 AND object = $2 AND size = $3 AND conversion IS NULL", kind, object, size))
+    }
+
+    /// Queue a job, or get the existing one if the same job is already queued for this object
+    pub async fn push(db: &Database, job: &mut Job) -> Result<(), Error> {
+        *job = query_object!(db, Job, "INSERT INTO SCHEMA_NAME.jobs (kind, object, mimetype, size, conversion) VALUES ($1, $2, $3, $4, $5)
+                        ON CONFLICT(kind, object, size, COALESCE(conversion, 0)) DO UPDATE SET mimetype = jobs.mimetype RETURNING *",
+            job.kind, job.object, job.mimetype, job.size, job.conversion).ok_or(Error::msg("Failed to insert job"))?;
+        Ok(())
+    }
+
+    /// Take the next pending job that is ready to start and mark it as running.
+    /// Concurrent callers never receive the same job.
+    pub async fn start_next(db: &Database) -> Result<Option<Job>, Error> {
+        Ok(query_object!(db, Job, "UPDATE SCHEMA_NAME.jobs SET status = 'running', attempts = attempts + 1 WHERE id = (
+                SELECT id FROM SCHEMA_NAME.jobs WHERE status = 'pending' AND next_attempt <= $1 ORDER BY next_attempt, id LIMIT 1 FOR UPDATE SKIP LOCKED
+            ) RETURNING *", Self::now()?))
+    }
+
+    /// Date of the next retry of a pending job, if any
+    pub async fn next_attempt(db: &Database) -> Result<Option<i64>, Error> {
+        let rows = query_fmt!(db, "SELECT MIN(next_attempt) AS next_attempt FROM SCHEMA_NAME.jobs WHERE status = 'pending'");
+        Ok(rows.first().and_then(|row| row.get::<&str, Option<i64>>("next_attempt")))
+    }
+
+    /// The job is done : it is removed from the queue
+    pub async fn complete(db: &Database, id: &JobId) -> Result<(), Error> {
+        query_fmt!(db, "DELETE FROM SCHEMA_NAME.jobs WHERE id = $1", id);
+        Ok(())
+    }
+
+    /// Put the job back in the queue until `next_attempt`, or mark it as failed. A failed job can be requeued with
+    /// `retry_failed` once `next_attempt` is passed.
+    pub async fn fail(db: &Database, id: &JobId, error: &str, status: JobStatus, next_attempt: i64) -> Result<(), Error> {
+        query_fmt!(db, "UPDATE SCHEMA_NAME.jobs SET status = $1, last_error = $2, next_attempt = $3 WHERE id = $4", status, error, next_attempt, id);
+        Ok(())
+    }
+
+    /// Queue a failed job again with all its attempts, if its cooldown is over
+    pub async fn retry_failed(db: &Database, id: &JobId) -> Result<Option<Job>, Error> {
+        Ok(query_object!(db, Job, "UPDATE SCHEMA_NAME.jobs SET status = 'pending', attempts = 0, next_attempt = $2
+                WHERE id = $1 AND status = 'failed' AND next_attempt <= $2 RETURNING *", id, Self::now()?))
+    }
+
+    /// Requeue the jobs that were interrupted (server stopped while they were running)
+    pub async fn restart_interrupted(db: &Database) -> Result<usize, Error> {
+        let rows = query_fmt!(db, "UPDATE SCHEMA_NAME.jobs SET status = 'pending' WHERE status = 'running' RETURNING id");
+        Ok(rows.len())
+    }
+
+    pub fn now() -> Result<i64, Error> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)
+    }
+}
@@ -1,12 +1,16 @@
-use std::fs;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::PathBuf;
-use std::str::FromStr;
+use std::sync::Arc;
 use anyhow::{Error};
-use tokio::net::TcpStream;
-use tokio_postgres::{Client, Config, Connection};
-use tokio_postgres::tls::NoTlsStream;
-use tracing::info;
-use utils::config::{BackendConfig};
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::NoTls;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::warn;
+use utils::config::{BackendConfig, PostgresConfig};
+use crate::migration::DbMigration;
+use crate::transaction::{DbConnection, TransactionState};
 
 pub mod item;
 pub mod object;
@@ -14,100 +18,122 @@ pub mod repository;
 pub mod user;
 pub mod subscription;
 pub mod async_zip;
+pub mod async_tar;
 pub mod compatibility_upgrade;
+pub mod upload;
+pub mod query_builder;
+pub mod file_version;
+pub mod job;
+pub mod conversion;
+pub mod album;
+pub mod migration;
+pub mod transaction;
 
 pub struct Database {
-    db: Client,
+    pool: Pool,
+    transaction: Option<Arc<TransactionState>>,
     pub schema_name: String,
     pub file_storage_path: PathBuf,
     pub thumbnail_storage_path: PathBuf,
-}
-
-async fn connect_raw(s: &str) -> Result<(Client, Connection<TcpStream, NoTlsStream>), Error> {
-    let socket = TcpStream::connect("127.0.0.1:5432").await?;
-    let config = s.parse::<Config>()?;
-    Ok(config.connect_raw(socket, tokio_postgres::NoTls).await?)
-}
-
-async fn connect(s: &str) -> Result<Client, Error> {
-    let (client, connection) = connect_raw(s).await?;
-    tokio::spawn(connection);
-    Ok(client)
+    pub upload_storage_path: PathBuf,
 }
 
 impl Database {
+    /// Connect to the database and apply the pending migrations
     pub async fn new(config: &BackendConfig) -> Result<Self, Error> {
-        let db = connect(format!("host={} port={} user={} password={} dbname={} sslmode={}", config.postgres.url, config.postgres.port, config.postgres.username, config.postgres.secret, config.postgres.database, if config.postgres.ssl_mode { "enable" } else { "disable" }).as_str()).await?;
-        let database = Self { db, schema_name: config.postgres.scheme_name.to_string(), file_storage_path: config.file_storage_path.clone(), thumbnail_storage_path: config.thumbnail_storage_path.clone() };
-        database.migrate(PathBuf::from("./migrations"), config.postgres.scheme_name.as_str()).await?;
+        let database = Self::connect(config)?;
+        DbMigration::apply(&database).await?;
         Ok(database)
     }
 
+    /// Connect to the database without touching its schema (to revert migrations)
+    pub fn connect(config: &BackendConfig) -> Result<Self, Error> {
+        let pool = Self::create_pool(&config.postgres)?;
+        Ok(Self { pool, transaction: None, schema_name: config.postgres.scheme_name.to_string(), file_storage_path: config.file_storage_path.clone(), thumbnail_storage_path: config.thumbnail_storage_path.clone(), upload_storage_path: config.upload_storage_path.clone() })
+    }
 
-    pub async fn migrate(&self, migrations_dir: PathBuf, schema_name: &str) -> Result<(), Error> {
-        let mut entries = vec![];
-        for entry in fs::read_dir(migrations_dir)? { entries.push(entry?); }
-
-        entries.sort_by(|a, b| {
-            let a = a.file_name();
-            let b = b.file_name();
-            let mut a_split = a.to_str().unwrap().split("_");
-            let mut b_split = b.to_str().unwrap().split("_");
+    /// Closed connections (server restart, network failure...) are discarded by the pool and
+    /// replaced by new ones when a connection is requested.
+    fn create_pool(config: &PostgresConfig) -> Result<Pool, Error> {
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&config.url)
+            .port(config.port)
+            .user(&config.username)
+            .password(&config.secret)
+            .dbname(&config.database)
+            .ssl_mode(if config.ssl_mode { SslMode::Require } else { SslMode::Disable });
+
+        let manager_config = ManagerConfig { recycling_method: RecyclingMethod::Fast };
+        let manager = if config.ssl_mode {
+            Manager::from_config(pg_config, Self::tls_connector(config)?, manager_config)
+        } else {
+            Manager::from_config(pg_config, NoTls, manager_config)
+        };
+        Ok(Pool::builder(manager).max_size(config.max_connections.max(1)).build()?)
+    }
 
-            if let Some(a) = a_split.next() {
-                if let Some(b) = b_split.next() {
-                    if let Ok(a) = i32::from_str(a) {
-                        if let Ok(b) = i32::from_str(b) {
-                            return a.cmp(&b);
-                        }
-                    }
+    fn tls_connector(config: &PostgresConfig) -> Result<MakeRustlsConnect, Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        match &config.ssl_root_certificate {
+            Some(path) => {
+                for certificate in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)) {
+                    roots.add(certificate?)?;
                 }
             }
-            a.cmp(&b)
-        });
-
-        for entry in entries {
-            let path = entry.path();
-            if path.is_file() && path.extension().and_then(std::ffi::OsStr::to_str) == Some("sql") {
-                let sql = fs::read_to_string(path)?.replace("SCHEMA_NAME", schema_name);
-
-                match self.db
-                    .simple_query(&sql,
-                    ).await {
-                    Ok(_) => {
-                        info!("Successfully executed migrations {}", entry.file_name().to_str().unwrap());
-                    }
-                    Err(error) => {
-                        return Err(Error::msg(format!("Failed run migration migrate {} : {}", entry.file_name().to_str().unwrap(), error)));
-                    }
-                };
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                for error in native.errors {
+                    warn!("Failed to load system certificates : {error}");
+                }
+                roots.add_parsable_certificates(native.certs);
             }
         }
+        let tls_config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(MakeRustlsConnect::new(tls_config))
+    }
 
-        Ok(())
+    /// Get a connection from the pool, or the connection of the current transaction
+    pub async fn db(&self) -> Result<DbConnection<'_>, Error> {
+        match &self.transaction {
+            None => { Ok(DbConnection::Pooled(self.pool.get().await?)) }
+            Some(state) => { Ok(DbConnection::Transaction(state.connection().await)) }
+        }
     }
 
-    pub fn db(&self) -> &Client {
-        &self.db
+    fn with_transaction(&self, transaction: Arc<TransactionState>) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            transaction: Some(transaction),
+            schema_name: self.schema_name.clone(),
+            file_storage_path: self.file_storage_path.clone(),
+            thumbnail_storage_path: self.thumbnail_storage_path.clone(),
+            upload_storage_path: self.upload_storage_path.clone(),
+        }
     }
 }
 
 #[macro_export]
 macro_rules! query_fmt {
     ($db:expr, $query:expr) => {{
-        $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?
+        let rows = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        rows
     }};
 
     ($db:expr, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?
+        let rows = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        rows
     }};
 }
 
 #[macro_export]
 macro_rules! query_objects {
     ($db:expr, $StructType:ty, $query:expr) => {{
-        let query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        let query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
         let mut rows = Vec::with_capacity(query.len());
         for row in query {
             rows.push(<$StructType>::try_from_row(&row)?);
@@ -116,7 +142,7 @@ macro_rules! query_objects {
     }};
     ($db:expr, $StructType:ty, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        let query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        let query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
         let mut rows = Vec::with_capacity(query.len());
         for row in query {
             rows.push(<$StructType>::try_from_row(&row)?);
@@ -128,7 +154,7 @@ macro_rules! query_objects {
 #[macro_export]
 macro_rules! query_object {
     ($db:expr, $StructType:ty, $query:expr) => {{
-        let mut query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
+        let mut query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), &[]).await?;
         if query.len() > 1 {
             return Err(Error::msg("Received more than one expected item"))
         }
@@ -139,7 +165,7 @@ macro_rules! query_object {
     }};
     ($db:expr, $StructType:ty, $query:expr, $( $bound_values:expr),*) => {{
         let params: &[&(dyn postgres_types::ToSql + Sync)] = &[$(&$bound_values,)*];
-        let mut query = $db.db().query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
+        let mut query = $db.db().await?.query(&$query.replace("SCHEMA_NAME", &$db.schema_name), params).await?;
         if query.len() > 1 {
             return Err(Error::msg("Received more than one expected item"))
         }
@@ -0,0 +1,144 @@
+use crate::transaction::DbConnection;
+use crate::Database;
+use anyhow::Error;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+}
+
+/// A migration embedded in the binary : "{version}_{name}.sql", with an optional "{version}_{name}.down.sql" to revert it
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    up: &'static str,
+    down: Option<&'static str>,
+}
+
+impl Migration {
+    /// Line endings are normalized so that a checkout on another platform doesn't look like an edited migration
+    pub fn checksum(&self) -> String {
+        blake3::hash(self.up.replace("\r\n", "\n").as_bytes()).to_hex().to_string()
+    }
+
+    /// Parse the embedded migration files, sorted by version
+    pub fn embedded() -> Result<Vec<Migration>, Error> {
+        let mut migrations: Vec<Migration> = vec![];
+        let mut down_scripts = HashMap::new();
+        for (file_name, sql) in embedded::MIGRATION_FILES {
+            let stem = file_name.strip_suffix(".sql").unwrap_or(file_name);
+            let (stem, is_down) = match stem.strip_suffix(".down") {
+                None => { (stem, false) }
+                Some(stem) => { (stem, true) }
+            };
+            let (version, name) = stem.split_once('_').ok_or(Error::msg(format!("Invalid migration file name {file_name}")))?;
+            let version = i64::from_str(version).map_err(|_| Error::msg(format!("Invalid migration version in {file_name}")))?;
+            if is_down {
+                down_scripts.insert(version, *sql);
+            } else {
+                if migrations.iter().any(|migration| migration.version == version) {
+                    return Err(Error::msg(format!("Duplicated migration version {version}")));
+                }
+                migrations.push(Migration { version, name: name.to_string(), up: sql, down: None });
+            }
+        }
+        for migration in &mut migrations {
+            migration.down = down_scripts.remove(&migration.version);
+        }
+        if let Some(version) = down_scripts.keys().next() {
+            return Err(Error::msg(format!("Down migration {version} has no matching migration")));
+        }
+        migrations.sort_by_key(|migration| migration.version);
+        Ok(migrations)
+    }
+}
+
+pub struct DbMigration;
+
+impl DbMigration {
+    /// Apply every pending migration, each one in its own transaction
+    pub async fn apply(db: &Database) -> Result<(), Error> {
+        let migrations = Migration::embedded()?;
+        let mut client = db.db().await?;
+
+        // Prevent multiple instances from migrating the same schema simultaneously
+        client.batch_execute(&format!("SELECT pg_advisory_lock(hashtext('{}.schema_migrations'))", db.schema_name)).await?;
+        let result = Self::apply_pending(db, &mut client, &migrations).await;
+        client.batch_execute(&format!("SELECT pg_advisory_unlock(hashtext('{}.schema_migrations'))", db.schema_name)).await?;
+        result
+    }
+
+    async fn apply_pending(db: &Database, client: &mut DbConnection<'_>, migrations: &[Migration]) -> Result<(), Error> {
+        client.batch_execute(&"CREATE SCHEMA IF NOT EXISTS SCHEMA_NAME;
+            CREATE TABLE IF NOT EXISTS SCHEMA_NAME.schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name VARCHAR NOT NULL,
+                checksum VARCHAR(64) NOT NULL,
+                applied_at BIGINT NOT NULL
+            );".replace("SCHEMA_NAME", &db.schema_name)).await?;
+
+        let mut applied = HashMap::new();
+        for row in client.query(&"SELECT version, checksum FROM SCHEMA_NAME.schema_migrations".replace("SCHEMA_NAME", &db.schema_name), &[]).await? {
+            applied.insert(row.try_get::<&str, i64>("version")?, row.try_get::<&str, String>("checksum")?);
+        }
+
+        let mut edited = vec![];
+        for migration in migrations {
+            if let Some(checksum) = applied.get(&migration.version) {
+                if *checksum != migration.checksum() {
+                    edited.push(format!("{}_{}", migration.version, migration.name));
+                }
+            }
+        }
+        if !edited.is_empty() {
+            return Err(Error::msg(format!("Migrations were modified after being applied : {}. Add a new migration instead.", edited.join(", "))));
+        }
+        for version in applied.keys() {
+            if !migrations.iter().any(|migration| migration.version == *version) {
+                warn!("Migration {version} is applied but unknown to this version of the server");
+            }
+        }
+
+        for migration in migrations.iter().filter(|migration| !applied.contains_key(&migration.version)) {
+            let transaction = client.transaction().await?;
+            if let Err(error) = transaction.batch_execute(&migration.up.replace("SCHEMA_NAME", &db.schema_name)).await {
+                return Err(Error::msg(format!("Failed to apply migration {}_{} : {}", migration.version, migration.name, error)));
+            }
+            transaction.execute(&"INSERT INTO SCHEMA_NAME.schema_migrations (version, name, checksum, applied_at) VALUES ($1, $2, $3, $4)".replace("SCHEMA_NAME", &db.schema_name),
+                                &[&migration.version, &migration.name, &migration.checksum(), &(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)]).await?;
+            transaction.commit().await?;
+            info!("Successfully applied migration {}_{}", migration.version, migration.name);
+        }
+        Ok(())
+    }
+
+    /// Revert the applied migrations above the target version using their down scripts, most recent first
+    pub async fn rollback(db: &Database, target_version: i64) -> Result<(), Error> {
+        let migrations = Migration::embedded()?;
+        let mut client = db.db().await?;
+
+        let mut to_revert = vec![];
+        for row in client.query(&"SELECT version FROM SCHEMA_NAME.schema_migrations WHERE version > $1 ORDER BY version DESC".replace("SCHEMA_NAME", &db.schema_name), &[&target_version]).await? {
+            let version = row.try_get::<&str, i64>("version")?;
+            match migrations.iter().find(|migration| migration.version == version) {
+                Some(Migration { down: Some(_), .. }) => { to_revert.push(version) }
+                _ => { return Err(Error::msg(format!("Migration {version} cannot be reverted : no down migration available"))) }
+            }
+        }
+
+        for version in to_revert {
+            let migration = migrations.iter().find(|migration| migration.version == version).unwrap();
+            let transaction = client.transaction().await?;
+            if let Err(error) = transaction.batch_execute(&migration.down.unwrap().replace("SCHEMA_NAME", &db.schema_name)).await {
+                return Err(Error::msg(format!("Failed to revert migration {}_{} : {}", migration.version, migration.name, error)));
+            }
+            transaction.execute(&"DELETE FROM SCHEMA_NAME.schema_migrations WHERE version = $1".replace("SCHEMA_NAME", &db.schema_name), &[&version]).await?;
+            transaction.commit().await?;
+            info!("Successfully reverted migration {}_{}", migration.version, migration.name);
+        }
+        Ok(())
+    }
+}
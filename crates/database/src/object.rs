@@ -20,8 +20,55 @@ impl Object {
         db.file_storage_path.join(object.to_string().as_str())
     }
 
-    pub fn thumbnail_path(object: &ObjectId, db: &Database) -> PathBuf {
-        db.thumbnail_storage_path.join(object.to_string().as_str())
+    /// HLS playlists and segments of videos, stored next to the object
+    pub fn hls_path(object: &ObjectId, db: &Database) -> PathBuf {
+        db.file_storage_path.join(format!("{object}.hls"))
+    }
+
+    /// Files generated from the object (thumbnails, previews...) are cached in a directory per kind
+    fn derived_path(object: &ObjectId, kind: &str, db: &Database) -> PathBuf {
+        db.thumbnail_storage_path.join(kind).join(object.to_string().as_str())
+    }
+
+    /// Each thumbnail size is cached in its own directory
+    pub fn thumbnail_path(object: &ObjectId, size: usize, db: &Database) -> PathBuf {
+        Self::derived_path(object, &size.to_string(), db)
+    }
+
+    /// Sprite sheet of video frames (jpeg)
+    pub fn video_sprites_path(object: &ObjectId, db: &Database) -> PathBuf {
+        Self::derived_path(object, "video_sprites", db)
+    }
+
+    /// WebVTT track describing the video sprite sheet
+    pub fn video_sprites_vtt_path(object: &ObjectId, db: &Database) -> PathBuf {
+        Self::derived_path(object, "video_sprites_vtt", db)
+    }
+
+    /// Animated hover preview of videos (webp)
+    pub fn video_preview_path(object: &ObjectId, db: &Database) -> PathBuf {
+        Self::derived_path(object, "video_preview", db)
+    }
+
+    /// Jpeg rendition of raw photos
+    pub fn rendition_path(object: &ObjectId, db: &Database) -> PathBuf {
+        Self::derived_path(object, "rendition", db)
+    }
+
+    /// Every cached file generated from the object : thumbnails of every size (including sizes that are no
+    /// longer configured), video previews...
+    pub fn derived_paths(object: &ObjectId, db: &Database) -> Result<Vec<PathBuf>, Error> {
+        // Thumbnails generated before size presets were stored at the root of the thumbnail directory
+        let mut paths = vec![db.thumbnail_storage_path.join(object.to_string().as_str())];
+        if db.thumbnail_storage_path.exists() {
+            for entry in fs::read_dir(&db.thumbnail_storage_path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    paths.push(entry.path().join(object.to_string().as_str()));
+                }
+            }
+        }
+        Ok(paths.into_iter().filter(|path| path.is_file()).collect())
     }
     
     pub async fn from_id(db: &Database, id: &ObjectId) -> Result<Self, Error> {
@@ -37,34 +84,40 @@ impl Object {
     }
 
     pub async fn insert(db: &Database, file: &Path, hash: &String) -> Result<Self, Error> {
-        let new_object = query_object!(db, Self, "INSERT INTO SCHEMA_NAME.objects (hash) VALUES ($1) RETURNING *", hash).ok_or(Error::msg("Failed to insert object"))?;
+        let transaction = db.begin().await?;
+        let new_object = query_object!(transaction, Self, "INSERT INTO SCHEMA_NAME.objects (hash) VALUES ($1) RETURNING *", hash).ok_or(Error::msg("Failed to insert object"))?;
         if !Object::data_path(new_object.id(), db).parent().unwrap().exists() {
             fs::create_dir_all(Object::data_path(new_object.id(), db).parent().unwrap())?;
         }
-        match fs::rename(file, Object::data_path(new_object.id(), db)) {
-            Ok(_) => {}
-            Err(err) => {
-                query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.objects WHERE id = $1;"#, *new_object.id);
-                return Err(Error::msg(format!("Failed to store new object : {err}")));
-            }
-        };
+        transaction.rename_file(file.to_path_buf(), Object::data_path(new_object.id(), db)).map_err(|err| Error::msg(format!("Failed to store new object : {err}")))?;
+        transaction.commit().await?;
         Ok(new_object)
     }
 
+    /// Store a new file, or reuse the object with the same content if one exists. The file is moved or removed.
+    pub async fn store(db: &Database, file: &Path, hash: &String) -> Result<Self, Error> {
+        for existing in Object::from_hash(db, hash).await? {
+            if existing.equals_to_file(db, file.to_path_buf()).await? {
+                db.remove_file(file.to_path_buf())?;
+                return Ok(existing);
+            }
+        }
+        Object::insert(db, file, hash).await
+    }
+
     pub async fn delete(&self, db: &Database) -> Result<(), Error> {
         Self::delete_objects(db, &vec![self.id.clone()]).await
     }
 
     pub async fn delete_objects(db: &Database, objects: &Vec<ObjectId>) -> Result<(), Error> {
+        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.objects WHERE id = any($1);"#, objects);
         for object in objects {
-            if Object::data_path(object, db).exists() {
-                fs::remove_file(Object::data_path(object, db))?;
-            }
-            if Object::thumbnail_path(object, db).exists() {
-                fs::remove_file(Object::thumbnail_path(object, db))?;
+            db.remove_file(Object::data_path(object, db))?;
+            db.remove_file(Object::hls_path(object, db))?;
+            for derived in Object::derived_paths(object, db)? {
+                db.remove_file(derived)?;
             }
         }
-        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.objects WHERE id = any($1);"#, objects);
         Ok(())
     }
     
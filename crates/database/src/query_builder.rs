@@ -0,0 +1,133 @@
+use crate::Database;
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use postgres_types::ToSql;
+
+/// Build a SELECT query from a list of conditions. Every value is bound as a parameter and never
+/// formatted into the SQL string.
+pub struct QueryBuilder {
+    base: String,
+    conditions: Vec<String>,
+    suffix: String,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl QueryBuilder {
+    /// `base` is the query without WHERE clause (ex: `SELECT * FROM SCHEMA_NAME.items`)
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.to_string(),
+            conditions: vec![],
+            suffix: String::new(),
+            params: vec![],
+        }
+    }
+
+    /// Register a parameter and return its placeholder (`$n`)
+    pub fn bind<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    /// Add a condition. Conditions are joined with AND.
+    pub fn condition<S: Into<String>>(&mut self, condition: S) -> &mut Self {
+        self.conditions.push(condition.into());
+        self
+    }
+
+    /// Text appended after the WHERE clause (ORDER BY, LIMIT...)
+    pub fn suffix<S: Into<String>>(&mut self, suffix: S) -> &mut Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    /// Join conditions with OR. An empty list never matches.
+    pub fn any_of(conditions: Vec<String>) -> String {
+        if conditions.is_empty() {
+            return "FALSE".to_string();
+        }
+        format!("({})", conditions.join(" OR "))
+    }
+
+    /// Escape LIKE wildcards so that the value is matched literally (use with `ESCAPE '\'`)
+    pub fn escape_like(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    }
+
+    pub fn sql(&self) -> String {
+        let mut sql = self.base.clone();
+        if !self.conditions.is_empty() {
+            sql += " WHERE ";
+            sql += self.conditions.join(" AND ").as_str();
+        }
+        if !self.suffix.is_empty() {
+            sql += " ";
+            sql += self.suffix.as_str();
+        }
+        sql
+    }
+
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|param| param.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+
+    /// Number of rows matched by the query
+    pub async fn count(&self, db: &Database) -> Result<i64, Error> {
+        let sql = format!("SELECT COUNT(*) AS count FROM ({}) AS counted", self.sql());
+        let row = db.db().await?.query_one(&sql.replace("SCHEMA_NAME", &db.schema_name), &self.params()).await?;
+        Ok(row.try_get::<&str, i64>("count")?)
+    }
+
+    pub async fn fetch<T: FromRow>(&self, db: &Database) -> Result<Vec<T>, Error> {
+        let rows = db.db().await?.query(&self.sql().replace("SCHEMA_NAME", &db.schema_name), &self.params()).await?;
+        let mut objects = Vec::with_capacity(rows.len());
+        for row in rows {
+            objects.push(T::try_from_row(&row)?);
+        }
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryBuilder;
+
+    fn params(query: &QueryBuilder) -> Vec<String> {
+        query.params().iter().map(|param| format!("{param:?}")).collect()
+    }
+
+    #[test]
+    fn without_condition() {
+        let query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.items");
+        assert_eq!(query.sql(), "SELECT * FROM SCHEMA_NAME.items");
+        assert!(query.params().is_empty());
+    }
+
+    #[test]
+    fn placeholders_follow_bind_order() {
+        let mut query = QueryBuilder::new("SELECT * FROM SCHEMA_NAME.items");
+        let name = query.bind("a".to_string());
+        let size = query.bind(12i64);
+        assert_eq!((name.as_str(), size.as_str()), ("$1", "$2"));
+        query.condition(format!("name = {name}")).condition(format!("size > {size}")).suffix("ORDER BY name");
+        assert_eq!(query.sql(), "SELECT * FROM SCHEMA_NAME.items WHERE name = $1 AND size > $2 ORDER BY name");
+        assert_eq!(params(&query), vec!["\"a\"", "12"]);
+    }
+
+    #[test]
+    fn any_of() {
+        assert_eq!(QueryBuilder::any_of(vec![]), "FALSE");
+        assert_eq!(QueryBuilder::any_of(vec!["a".to_string()]), "(a)");
+        assert_eq!(QueryBuilder::any_of(vec!["a".to_string(), "b".to_string()]), "(a OR b)");
+    }
+
+    #[test]
+    fn escape_like() {
+        assert_eq!(QueryBuilder::escape_like("photo"), "photo");
+        assert_eq!(QueryBuilder::escape_like("100%"), r"100\%");
+        assert_eq!(QueryBuilder::escape_like("my_file"), r"my\_file");
+        assert_eq!(QueryBuilder::escape_like(r"a\b"), r"a\\b");
+        // The escape character is escaped first so that the escapes added for wildcards are kept
+        assert_eq!(QueryBuilder::escape_like(r"\%_"), r"\\\%\_");
+    }
+}
@@ -9,6 +9,7 @@ use serde::{Serialize};
 use types::database_ids::{DatabaseIdTrait, RepositoryId, UserId};
 use types::repository::Repository;
 use crate::item::DbItem;
+use crate::upload::DbUpload;
 
 #[derive(Serialize, Default)]
 pub struct RepositoryContributorStats {
@@ -63,16 +64,16 @@ impl DbRepository {
         }
         if repository.id().is_valid() {
             query_fmt!(db, "INSERT INTO SCHEMA_NAME.repository
-                        (id, url_name, owner, description, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                        (id, url_name, owner, description, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload, max_file_versions, hls_streaming) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
                         ON CONFLICT(id) DO UPDATE SET
-                        id = $1, url_name = $2, owner = $3, description = $4, status = $5, display_name = $6, max_file_size = $7, visitor_file_lifetime = $8, allow_visitor_upload = $9;",
-                repository.id(), repository.url_name, repository.owner, repository.description, repository.status, repository.display_name, repository.max_file_size, repository.visitor_file_lifetime, repository.allow_visitor_upload);
+                        id = $1, url_name = $2, owner = $3, description = $4, status = $5, display_name = $6, max_file_size = $7, visitor_file_lifetime = $8, allow_visitor_upload = $9, max_file_versions = $10, hls_streaming = $11;",
+                repository.id(), repository.url_name, repository.owner, repository.description, repository.status, repository.display_name, repository.max_file_size, repository.visitor_file_lifetime, repository.allow_visitor_upload, repository.max_file_versions, repository.hls_streaming);
         } else {
             let res = query_object!(db, RepositoryId, "INSERT INTO SCHEMA_NAME.repository
-                        (url_name, owner, description, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload) VALUES
-                        ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
-                repository.url_name, repository.owner, repository.description, repository.status, repository.display_name, repository.max_file_size, repository.visitor_file_lifetime, repository.allow_visitor_upload);
+                        (url_name, owner, description, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload, max_file_versions, hls_streaming) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+                repository.url_name, repository.owner, repository.description, repository.status, repository.display_name, repository.max_file_size, repository.visitor_file_lifetime, repository.allow_visitor_upload, repository.max_file_versions, repository.hls_streaming);
             if let Some(res) = res {
                 repository.set_id(res)?;
             }
@@ -81,13 +82,23 @@ impl DbRepository {
     }
 
     pub async fn delete(repository: &Repository, db: &Database) -> Result<(), Error> {
-        for item in DbItem::from_repository(db, &repository.id(), Both).await? {
-            DbItem::delete(&item, db).await?;
+        let transaction = db.begin().await?;
+        for item in DbItem::from_repository(&transaction, repository.id(), Both).await? {
+            DbItem::delete(&item, &transaction).await?;
         }
-        for subscriptions in Subscription::from_repository(db, &repository.id()).await? {
-            subscriptions.delete(db).await?
+        for subscriptions in Subscription::from_repository(&transaction, repository.id()).await? {
+            subscriptions.delete(&transaction).await?
         }
-        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.repository WHERE id = $1;"#, repository.id());
+        for upload in DbUpload::from_repository(&transaction, repository.id()).await? {
+            DbUpload::delete(&upload, &transaction).await?;
+        }
+        query_fmt!(transaction, r#"DELETE FROM SCHEMA_NAME.repository WHERE id = $1;"#, repository.id());
+        transaction.commit().await
+    }
+
+    /// Recompute the content size and item count of every directory of the repository
+    pub async fn update_directory_sizes(repository: &RepositoryId, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, "CALL SCHEMA_NAME.update_directory_sizes($1, NULL)", repository);
         Ok(())
     }
 
@@ -0,0 +1,198 @@
+use crate::Database;
+use anyhow::Error;
+use deadpool_postgres::{ClientWrapper, Object};
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::MutexGuard;
+use tracing::{error, warn};
+
+/// Connection and pending file operations of a running transaction
+pub struct TransactionState {
+    connection: tokio::sync::Mutex<Option<Object>>,
+    removed_on_commit: std::sync::Mutex<Vec<PathBuf>>,
+    restored_on_rollback: std::sync::Mutex<Vec<(PathBuf, PathBuf)>>,
+    /// A joined transaction was dropped without being committed
+    aborted: AtomicBool,
+}
+
+/// Connection returned by [`Database::db`] : a pooled connection, or the one of the current transaction
+#[allow(clippy::large_enum_variant)]
+pub enum DbConnection<'a> {
+    Pooled(Object),
+    Transaction(MutexGuard<'a, Option<Object>>),
+}
+
+impl Deref for DbConnection<'_> {
+    type Target = ClientWrapper;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DbConnection::Pooled(connection) => { connection }
+            DbConnection::Transaction(connection) => { connection.as_ref().expect("Transaction is already closed") }
+        }
+    }
+}
+
+impl DerefMut for DbConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            DbConnection::Pooled(connection) => { connection }
+            DbConnection::Transaction(connection) => { connection.as_mut().expect("Transaction is already closed") }
+        }
+    }
+}
+
+/// All-or-nothing set of operations. The transaction derefs to a [`Database`] that can be given to any
+/// database function, which will then run its queries inside the transaction.
+///
+/// Dropping the transaction without committing rolls it back. Beginning a transaction inside another one joins
+/// the enclosing transaction : only the outermost commit is applied, and fails if a nested one was not committed.
+pub struct DbTransaction {
+    database: Database,
+    owner: bool,
+    committed: bool,
+}
+
+impl TransactionState {
+    pub(crate) async fn connection(&self) -> MutexGuard<'_, Option<Object>> {
+        self.connection.lock().await
+    }
+}
+
+impl Drop for TransactionState {
+    /// The connection is still here if the transaction could neither be committed nor rolled back : it is closed
+    /// instead of going back to the pool with the transaction still open.
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.get_mut().take() {
+            drop(Object::take(connection));
+        }
+    }
+}
+
+impl DbTransaction {
+    pub(crate) async fn begin(db: &Database) -> Result<Self, Error> {
+        if let Some(state) = &db.transaction {
+            return Ok(Self { database: db.with_transaction(state.clone()), owner: false, committed: false });
+        }
+        let connection = db.pool.get().await?;
+        connection.batch_execute("BEGIN").await?;
+        let state = Arc::new(TransactionState {
+            connection: tokio::sync::Mutex::new(Some(connection)),
+            removed_on_commit: Default::default(),
+            restored_on_rollback: Default::default(),
+            aborted: AtomicBool::new(false),
+        });
+        Ok(Self { database: db.with_transaction(state), owner: true, committed: false })
+    }
+
+    pub async fn commit(mut self) -> Result<(), Error> {
+        if !self.owner {
+            self.committed = true;
+            return Ok(());
+        }
+        let state = self.state().clone();
+        if state.aborted.load(Ordering::SeqCst) {
+            return Err(Error::msg("Cannot commit : a nested transaction was not committed"));
+        }
+        let mut connection = state.connection.lock().await;
+        connection.as_ref().expect("Transaction is already closed").batch_execute("COMMIT").await?;
+        self.committed = true;
+        drop(connection.take());
+
+        for path in state.removed_on_commit.lock().unwrap().drain(..) {
+            if let Err(err) = remove_path(&path) {
+                warn!("Failed to remove {} after commit : {err}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> &Arc<TransactionState> {
+        self.database.transaction.as_ref().expect("Not a transaction")
+    }
+}
+
+impl Deref for DbTransaction {
+    type Target = Database;
+
+    fn deref(&self) -> &Self::Target {
+        &self.database
+    }
+}
+
+impl Drop for DbTransaction {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let state = self.state();
+        if !self.owner {
+            state.aborted.store(true, Ordering::SeqCst);
+            return;
+        }
+        for (current, original) in state.restored_on_rollback.lock().unwrap().drain(..).rev() {
+            if let Err(err) = fs::rename(&current, &original) {
+                error!("Failed to restore {} after rollback : {err}", original.display());
+            }
+        }
+        let connection = match state.connection.try_lock() {
+            Ok(mut connection) => { connection.take() }
+            Err(_) => {
+                warn!("Transaction dropped while its connection is in use : the connection will be closed once released");
+                None
+            }
+        };
+        if let Some(connection) = connection {
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => {
+                    runtime.spawn(async move {
+                        if let Err(err) = connection.batch_execute("ROLLBACK").await {
+                            warn!("Failed to rollback transaction : {err}");
+                            // Closing the connection aborts the transaction anyway
+                            drop(Object::take(connection));
+                        }
+                    });
+                }
+                Err(_) => { drop(Object::take(connection)); }
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Start a transaction, or join the current one
+    pub async fn begin(&self) -> Result<DbTransaction, Error> {
+        DbTransaction::begin(self).await
+    }
+
+    /// Remove a file or a directory, or wait for the current transaction to be committed
+    pub fn remove_file(&self, path: PathBuf) -> Result<(), Error> {
+        match &self.transaction {
+            None => { remove_path(&path)?; }
+            Some(state) => { state.removed_on_commit.lock().unwrap().push(path); }
+        }
+        Ok(())
+    }
+
+    /// Move a file. It is moved back if the current transaction is rolled back.
+    pub fn rename_file(&self, from: PathBuf, to: PathBuf) -> Result<(), Error> {
+        fs::rename(&from, &to)?;
+        if let Some(state) = &self.transaction {
+            state.restored_on_rollback.lock().unwrap().push((to, from));
+        }
+        Ok(())
+    }
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else if path.exists() {
+        fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::Database;
+use crate::{query_fmt, query_object, query_objects};
+use anyhow::Error;
+use postgres_from_row::FromRow;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::database_ids::{ItemId, RepositoryId, UserId};
+use types::enc_string::EncString;
+
+/// Pending upload metadata. The received bytes are stored in the upload storage directory until
+/// the upload is complete and promoted to an object.
+#[derive(Debug, Default, FromRow, Clone)]
+pub struct UploadSession {
+    id: String,
+    pub owner: UserId,
+    pub repository: RepositoryId,
+    pub parent_item: Option<ItemId>,
+    pub name: EncString,
+    pub description: Option<EncString>,
+    pub size: i64,
+    pub mimetype: EncString,
+    pub timestamp: i64,
+    pub upload_offset: i64,
+    pub last_update: i64,
+}
+
+impl UploadSession {
+    pub fn new(owner: UserId, repository: RepositoryId, name: EncString, size: i64) -> Self {
+        Self {
+            owner,
+            repository,
+            name,
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+}
+
+pub struct DbUpload;
+
+impl DbUpload {
+    pub fn data_path(id: &str, db: &Database) -> PathBuf {
+        db.upload_storage_path.join(id)
+    }
+
+    pub async fn from_id(db: &Database, id: &str) -> Result<UploadSession, Error> {
+        query_object!(db, UploadSession, "SELECT * FROM SCHEMA_NAME.uploads WHERE id = $1", id).ok_or(Error::msg("Upload not found"))
+    }
+
+    pub async fn from_user(db: &Database, id: &UserId) -> Result<Vec<UploadSession>, Error> {
+        Ok(query_objects!(db, UploadSession, "SELECT * FROM SCHEMA_NAME.uploads WHERE owner = $1", id))
+    }
+
+    pub async fn from_repository(db: &Database, id: &RepositoryId) -> Result<Vec<UploadSession>, Error> {
+        Ok(query_objects!(db, UploadSession, "SELECT * FROM SCHEMA_NAME.uploads WHERE repository = $1", id))
+    }
+
+    pub async fn push(upload: &mut UploadSession, db: &Database) -> Result<(), Error> {
+        upload.last_update = Self::now()?;
+        query_fmt!(db, "INSERT INTO SCHEMA_NAME.uploads
+                        (id, owner, repository, parent_item, name, description, size, mimetype, timestamp, upload_offset, last_update) VALUES
+                        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                        ON CONFLICT(id) DO UPDATE SET
+                        owner = $2, repository = $3, parent_item = $4, name = $5, description = $6, size = $7, mimetype = $8, timestamp = $9, upload_offset = $10, last_update = $11;",
+            upload.id, upload.owner, upload.repository, upload.parent_item, upload.name, upload.description, upload.size, upload.mimetype, upload.timestamp, upload.upload_offset, upload.last_update);
+        Ok(())
+    }
+
+    /// Only update the received byte count (called after each received chunk)
+    pub async fn set_offset(upload: &mut UploadSession, db: &Database, offset: i64) -> Result<(), Error> {
+        upload.upload_offset = offset;
+        upload.last_update = Self::now()?;
+        query_fmt!(db, "UPDATE SCHEMA_NAME.uploads SET upload_offset = $1, last_update = $2 WHERE id = $3", upload.upload_offset, upload.last_update, upload.id);
+        Ok(())
+    }
+
+    /// Remove the session and its partial data
+    pub async fn delete(upload: &UploadSession, db: &Database) -> Result<(), Error> {
+        query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.uploads WHERE id = $1;"#, upload.id);
+        db.remove_file(Self::data_path(&upload.id, db))?;
+        Ok(())
+    }
+
+    /// Remove every session that was not updated since `max_age_ms`
+    pub async fn delete_expired(db: &Database, max_age_ms: i64) -> Result<usize, Error> {
+        let expired = query_objects!(db, UploadSession, "SELECT * FROM SCHEMA_NAME.uploads WHERE last_update < $1", Self::now()? - max_age_ms);
+        for upload in &expired {
+            Self::delete(upload, db).await?;
+        }
+        Ok(expired.len())
+    }
+
+    fn now() -> Result<i64, Error> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::repository::DbRepository;
 use crate::subscription::Subscription;
+use crate::upload::DbUpload;
 use crate::{query_fmt, query_object, query_objects};
 use crate::Database;
 use anyhow::Error;
@@ -125,13 +126,16 @@ impl DbUser {
     }
 
     pub async fn delete(user: &User, db: &Database) -> Result<(), Error> {
-        for repository in DbRepository::from_user(db, &user.id()).await? {
+        for upload in DbUpload::from_user(db, user.id()).await? {
+            DbUpload::delete(&upload, db).await?;
+        }
+        for repository in DbRepository::from_user(db, user.id()).await? {
             DbRepository::delete(&repository, db).await?;
         }
         for token in DbAuthToken::from_user(db, user.id()).await? {
             DbAuthToken::delete(&token, db).await?;
         }
-        for subscriptions in Subscription::from_user(db, &user.id()).await? {
+        for subscriptions in Subscription::from_user(db, user.id()).await? {
             subscriptions.delete(db).await?
         }
         query_fmt!(db, r#"DELETE FROM SCHEMA_NAME.users WHERE id = $1;"#, user.id());
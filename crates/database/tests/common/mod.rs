@@ -0,0 +1,148 @@
+//! Tests and benchmarks run against a real PostgreSQL server, described by the server configuration file given in the
+//! `FILESHARE_TEST_CONFIG` environment variable. Each run uses its own schema, which is removed afterward. They are
+//! skipped when the variable is not set.
+#![allow(dead_code)]
+
+use anyhow::Error;
+use database::item::{DbItem, Trash};
+use database::Database;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use types::database_ids::{ItemId, ObjectId, RepositoryId, UserId};
+use types::enc_string::EncString;
+use types::item::{DirectoryData, FileData, Item};
+use utils::config::Config;
+
+static SCHEMA_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Empty database with a user owning a repository, and an object shared by all the files
+pub struct TestDatabase {
+    pub db: Database,
+    pub schema: String,
+    pub owner: UserId,
+    pub repository: RepositoryId,
+    pub object: ObjectId,
+}
+
+impl TestDatabase {
+    /// None if no test server is configured
+    pub async fn create() -> Result<Option<Self>, Error> {
+        let Ok(path) = std::env::var("FILESHARE_TEST_CONFIG") else {
+            eprintln!("FILESHARE_TEST_CONFIG is not set : skipped");
+            return Ok(None);
+        };
+        let mut config = Config::from_file(PathBuf::from(path))?.backend_config;
+        let schema = format!("fileshare_test_{}_{}", std::process::id(), SCHEMA_COUNTER.fetch_add(1, Ordering::SeqCst));
+        config.postgres.scheme_name = schema.clone();
+        let db = Database::new(&config).await?;
+        let ids = match Self::insert_fixtures(&db, &schema).await {
+            Ok(ids) => { ids }
+            Err(err) => {
+                db.db().await?.batch_execute(&format!("DROP SCHEMA {schema} CASCADE")).await?;
+                return Err(err);
+            }
+        };
+        Ok(Some(Self { owner: ids.0, repository: ids.1, object: ids.2, db, schema }))
+    }
+
+    async fn insert_fixtures(db: &Database, schema: &str) -> Result<(UserId, RepositoryId, ObjectId), Error> {
+        let ids = db.db().await?.query(&"WITH owner AS (
+                INSERT INTO SCHEMA_NAME.users (id, email, name, login, user_role) VALUES (1, 'test@test', 'test', 'test', 'admin') RETURNING id
+            ), repository AS (
+                INSERT INTO SCHEMA_NAME.repository (url_name, owner, status, display_name, max_file_size, visitor_file_lifetime, allow_visitor_upload)
+                SELECT 'test', id, 'private', 'test', 0, 0, false FROM owner RETURNING id, owner
+            ), object AS (
+                INSERT INTO SCHEMA_NAME.objects (hash) VALUES ('test') RETURNING id
+            )
+            SELECT repository.owner, repository.id AS repository, object.id AS object FROM repository, object".replace("SCHEMA_NAME", schema), &[]).await?;
+        let row = ids.first().ok_or(Error::msg("Failed to create the test repository"))?;
+        Ok((
+            UserId::from(row.get::<&str, i64>("owner")),
+            RepositoryId::from(row.get::<&str, i64>("repository")),
+            ObjectId::from(row.get::<&str, i64>("object")),
+        ))
+    }
+
+    pub async fn drop_schema(&self) -> Result<(), Error> {
+        self.db.db().await?.batch_execute(&format!("DROP SCHEMA {} CASCADE", self.schema)).await?;
+        Ok(())
+    }
+
+    pub async fn directory(&self, name: &str, parent: Option<&ItemId>) -> Result<ItemId, Error> {
+        let mut item = self.item(name, parent);
+        item.directory = Some(DirectoryData::default());
+        DbItem::push(&mut item, &self.db).await?;
+        Ok(item.id().clone())
+    }
+
+    pub async fn file(&self, name: &str, parent: Option<&ItemId>, size: i64) -> Result<ItemId, Error> {
+        let mut item = self.item(name, parent);
+        item.file = Some(FileData { size, mimetype: EncString::encode("text/plain"), timestamp: 0, object: self.object.clone() });
+        DbItem::push(&mut item, &self.db).await?;
+        Ok(item.id().clone())
+    }
+
+    /// Directory containing `directories` subdirectories of `files_per_directory` files each. They are inserted in bulk :
+    /// the triggers maintaining the paths and the directory sizes are bypassed, and the sizes are recomputed once the
+    /// statistics are up to date.
+    pub async fn tree(&self, name: &str, parent: Option<&ItemId>, directories: usize, files_per_directory: usize) -> Result<ItemId, Error> {
+        let root = self.directory(name, parent).await?;
+        let (schema, repository, owner, object) = (&self.schema, *self.repository, *self.owner, *self.object);
+        let root_id = *root;
+        self.db.db().await?.batch_execute(&format!("
+            BEGIN;
+            ALTER TABLE {schema}.items DISABLE TRIGGER trig_ins_ensure_items_path_up_to_date;
+            SELECT set_config('{schema}.skip_directory_sizes', 'on', true);
+            INSERT INTO {schema}.items (repository, owner, name, is_regular_file, parent_item, absolute_path)
+                SELECT {repository}, {owner}, 'directory' || i, false, root.id, root.absolute_path || '/directory' || i
+                FROM {schema}.items AS root, generate_series(1, {directories}) AS i WHERE root.id = {root_id};
+            INSERT INTO {schema}.directories (id, open_upload) SELECT id, false FROM {schema}.items WHERE parent_item = {root_id};
+            INSERT INTO {schema}.items (repository, owner, name, is_regular_file, parent_item, absolute_path)
+                SELECT {repository}, {owner}, 'file' || i, true, directory.id, directory.absolute_path || '/file' || i
+                FROM {schema}.items AS directory, generate_series(1, {files_per_directory}) AS i WHERE directory.parent_item = {root_id};
+            INSERT INTO {schema}.files (id, size, mimetype, timestamp, object)
+                SELECT file.id, 1, 'text/plain', 0, {object} FROM {schema}.items AS file JOIN {schema}.items AS directory ON file.parent_item = directory.id
+                WHERE directory.parent_item = {root_id};
+            ALTER TABLE {schema}.items ENABLE TRIGGER trig_ins_ensure_items_path_up_to_date;
+            COMMIT;
+            ANALYZE {schema}.items, {schema}.files, {schema}.directories;
+            CALL {schema}.update_directory_sizes({repository}, NULL);")).await?;
+        Ok(root)
+    }
+
+    /// Number of files and total size of a directory
+    pub async fn content(&self, directory: &ItemId) -> Result<(i64, i64), Error> {
+        let item = DbItem::from_id(&self.db, directory, Trash::Both).await?;
+        let directory = item.directory.ok_or(Error::msg("Not a directory"))?;
+        Ok((directory.num_items, directory.content_size))
+    }
+
+    fn item(&self, name: &str, parent: Option<&ItemId>) -> Item {
+        let mut item = Item::default();
+        item.repository = self.repository.clone();
+        item.owner = self.owner.clone();
+        item.name = EncString::encode(name);
+        item.parent_item = parent.cloned();
+        item
+    }
+}
+
+/// Run a test in a new schema. The schema is removed even if the test fails.
+pub async fn run<F, Fut>(test: F)
+where
+    F: FnOnce(Arc<TestDatabase>) -> Fut,
+    Fut: Future<Output=Result<(), Error>> + Send + 'static,
+{
+    let Some(database) = TestDatabase::create().await.expect("Failed to create the test database") else {
+        return;
+    };
+    let database = Arc::new(database);
+    let result = tokio::spawn(test(database.clone())).await;
+    database.drop_schema().await.expect("Failed to remove the test schema");
+    match result {
+        Ok(result) => { result.expect("Test failed") }
+        Err(err) => { std::panic::resume_unwind(err.into_panic()) }
+    }
+}
@@ -0,0 +1,64 @@
+//! A confirmed conversion becomes a new item or the new version of the converted file.
+mod common;
+
+use anyhow::Error;
+use common::{run, TestDatabase};
+use database::conversion::DbConversion;
+use database::file_version::DbFileVersion;
+use database::item::{DbItem, Trash};
+use database::repository::DbRepository;
+use types::conversion::{Conversion, ConversionOutput, ConversionQuality, ConversionTarget};
+use types::database_ids::{ItemId, ObjectId};
+
+async fn object(database: &TestDatabase, hash: &str) -> Result<ObjectId, Error> {
+    let rows = database.db.db().await?.query(&format!("INSERT INTO {}.objects (hash) VALUES ($1) RETURNING id", database.schema), &[&hash]).await?;
+    Ok(ObjectId::from(rows[0].get::<&str, i64>("id")))
+}
+
+/// Convert the current content of a file to a new version, and confirm it
+async fn convert(database: &TestDatabase, item: &ItemId, hash: &str) -> Result<ObjectId, Error> {
+    let source = DbItem::from_id(&database.db, item, Trash::Both).await?.file.ok_or(Error::msg("Not a file"))?.object;
+    let result = object(database, hash).await?;
+    let mut conversion = Conversion::new(item.clone(), database.owner.clone(), source, ConversionTarget::Webp, ConversionQuality::default(), ConversionOutput::NewVersion);
+    DbConversion::push(&database.db, &mut conversion).await?;
+    DbConversion::set_result(&database.db, conversion.id(), &result, 10).await?;
+    let conversion = DbConversion::from_id(&database.db, conversion.id()).await?;
+    DbConversion::confirm(&database.db, &conversion).await?;
+    Ok(result)
+}
+
+async fn versions(database: &TestDatabase, item: &ItemId) -> Result<Vec<ObjectId>, Error> {
+    Ok(DbFileVersion::from_item(&database.db, item).await?.into_iter().map(|version| version.object).collect())
+}
+
+#[tokio::test]
+async fn new_version_keeps_original_in_history() {
+    run(|database| async move {
+        let file = database.file("image.png", None, 100).await?;
+        let result = convert(&database, &file, "converted").await?;
+
+        let item = DbItem::from_id(&database.db, &file, Trash::Both).await?;
+        assert_eq!(item.file.map(|file| file.object), Some(result.clone()));
+        assert_eq!(versions(&database, &file).await?, vec![result, database.object.clone()]);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn new_version_applies_retention() {
+    run(|database| async move {
+        let mut repository = DbRepository::from_id(&database.db, &database.repository).await?;
+        repository.max_file_versions = Some(2);
+        DbRepository::push(&mut repository, &database.db).await?;
+
+        let file = database.file("image.png", None, 100).await?;
+        let first = convert(&database, &file, "first").await?;
+        let second = convert(&database, &file, "second").await?;
+
+        assert_eq!(versions(&database, &file).await?, vec![second, first.clone()]);
+        // The original object is not used by any other file : it is removed with its version
+        let remaining = database.db.db().await?.query(&format!("SELECT id FROM {}.objects WHERE id = $1", database.schema), &[&database.object]).await?;
+        assert!(remaining.is_empty());
+        Ok(())
+    }).await
+}
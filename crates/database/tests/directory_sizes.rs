@@ -0,0 +1,183 @@
+//! The number of files and the size of directories are maintained by triggers on every change of the tree, and can be
+//! recomputed from scratch with `update_directory_sizes`.
+mod common;
+
+use anyhow::Error;
+use common::{run, TestDatabase};
+use database::item::{DbItem, Trash};
+use database::repository::DbRepository;
+use types::database_ids::ItemId;
+use types::enc_string::EncString;
+
+const DEPTH: usize = 40;
+
+/// A chain of nested directories, with a file of size `level + 1` in each of them
+struct Chain {
+    directories: Vec<ItemId>,
+    files: Vec<ItemId>,
+}
+
+impl Chain {
+    async fn create(database: &TestDatabase, name: &str, parent: Option<&ItemId>) -> Result<Self, Error> {
+        let mut chain = Self { directories: vec![], files: vec![] };
+        let mut parent = parent.cloned();
+        for level in 0..DEPTH {
+            let directory = database.directory(&format!("{name}{level}"), parent.as_ref()).await?;
+            chain.files.push(database.file("file", Some(&directory), level as i64 + 1).await?);
+            chain.directories.push(directory.clone());
+            parent = Some(directory);
+        }
+        Ok(chain)
+    }
+
+    /// Content of the directory at this level, when nothing was changed
+    fn expected(level: usize) -> (i64, i64) {
+        ((DEPTH - level) as i64, (level + 1..=DEPTH).sum::<usize>() as i64)
+    }
+}
+
+async fn assert_content(database: &TestDatabase, directory: &ItemId, expected: (i64, i64), context: &str) -> Result<(), Error> {
+    assert_eq!(database.content(directory).await?, expected, "{context} : directory {directory:?}");
+    Ok(())
+}
+
+/// Recomputing the sizes from scratch must give the same result as the incremental updates
+async fn assert_recompute_unchanged(database: &TestDatabase, directories: &[ItemId]) -> Result<(), Error> {
+    let mut before = vec![];
+    for directory in directories {
+        before.push(database.content(directory).await?);
+    }
+    DbRepository::update_directory_sizes(&database.repository, &database.db).await?;
+    for (directory, before) in directories.iter().zip(before) {
+        assert_content(database, directory, before, "recompute").await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_in_deep_tree() {
+    run(|database| async move {
+        let chain = Chain::create(&database, "dir", None).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            assert_content(&database, directory, Chain::expected(level), "insert").await?;
+        }
+        assert_recompute_unchanged(&database, &chain.directories).await
+    }).await;
+}
+
+#[tokio::test]
+async fn resize_file() {
+    run(|database| async move {
+        let chain = Chain::create(&database, "dir", None).await?;
+        let mut file = DbItem::from_id(&database.db, &chain.files[DEPTH - 1], Trash::No).await?;
+        file.file.as_mut().unwrap().size += 100;
+        DbItem::push(&mut file, &database.db).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            let (num_items, content_size) = Chain::expected(level);
+            assert_content(&database, directory, (num_items, content_size + 100), "resize").await?;
+        }
+        assert_recompute_unchanged(&database, &chain.directories).await
+    }).await;
+}
+
+#[tokio::test]
+async fn move_subtree() {
+    run(|database| async move {
+        let chain = Chain::create(&database, "dir", None).await?;
+        let other = database.directory("other", None).await?;
+        let other_child = database.directory("child", Some(&other)).await?;
+        let moved_level = DEPTH / 2;
+        let moved = Chain::expected(moved_level);
+
+        DbItem::move_to(&database.db, &chain.directories[moved_level], &Some(other_child.clone()), &database.repository, &EncString::encode("moved")).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            let expected = if level < moved_level {
+                let (num_items, content_size) = Chain::expected(level);
+                (num_items - moved.0, content_size - moved.1)
+            } else {
+                Chain::expected(level)
+            };
+            assert_content(&database, directory, expected, "move").await?;
+        }
+        assert_content(&database, &other, moved, "move destination").await?;
+        assert_content(&database, &other_child, moved, "move destination").await?;
+
+        // Back to its original place
+        DbItem::move_to(&database.db, &chain.directories[moved_level], &Some(chain.directories[moved_level - 1].clone()), &database.repository, &EncString::encode("back")).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            assert_content(&database, directory, Chain::expected(level), "move back").await?;
+        }
+        assert_content(&database, &other, (0, 0), "move back").await?;
+        assert_recompute_unchanged(&database, &chain.directories).await
+    }).await;
+}
+
+#[tokio::test]
+async fn trash_and_restore_subtree() {
+    run(|database| async move {
+        let chain = Chain::create(&database, "dir", None).await?;
+        let trashed_level = DEPTH / 2;
+        let trashed = Chain::expected(trashed_level);
+
+        let mut item = DbItem::from_id(&database.db, &chain.directories[trashed_level], Trash::No).await?;
+        item.in_trash = true;
+        DbItem::push(&mut item, &database.db).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            // The trashed subtree keeps its content, it is just not counted in its parents anymore
+            let expected = if level < trashed_level {
+                let (num_items, content_size) = Chain::expected(level);
+                (num_items - trashed.0, content_size - trashed.1)
+            } else {
+                Chain::expected(level)
+            };
+            assert_content(&database, directory, expected, "trash").await?;
+        }
+        assert_recompute_unchanged(&database, &chain.directories).await?;
+
+        item.in_trash = false;
+        DbItem::push(&mut item, &database.db).await?;
+        for (level, directory) in chain.directories.iter().enumerate() {
+            assert_content(&database, directory, Chain::expected(level), "restore").await?;
+        }
+        assert_recompute_unchanged(&database, &chain.directories).await
+    }).await;
+}
+
+#[tokio::test]
+async fn delete_subtree() {
+    run(|database| async move {
+        let chain = Chain::create(&database, "dir", None).await?;
+        let deleted_level = DEPTH / 2;
+        let deleted = Chain::expected(deleted_level);
+
+        let item = DbItem::from_id(&database.db, &chain.directories[deleted_level], Trash::No).await?;
+        DbItem::delete(&item, &database.db).await?;
+        for (level, directory) in chain.directories[..deleted_level].iter().enumerate() {
+            let (num_items, content_size) = Chain::expected(level);
+            assert_content(&database, directory, (num_items - deleted.0, content_size - deleted.1), "delete").await?;
+        }
+        assert!(DbItem::from_id(&database.db, &chain.directories[DEPTH - 1], Trash::Both).await.is_err());
+        assert_recompute_unchanged(&database, &chain.directories[..deleted_level]).await
+    }).await;
+}
+
+#[tokio::test]
+async fn recompute_repairs_sizes() {
+    run(|database| async move {
+        let first = Chain::create(&database, "first", None).await?;
+        let second = Chain::create(&database, "second", Some(&first.directories[DEPTH / 2])).await?;
+        database.db.db().await?.batch_execute(&format!("UPDATE {}.directories SET num_items = 0, content_size = 12345", database.schema)).await?;
+
+        DbRepository::update_directory_sizes(&database.repository, &database.db).await?;
+        let nested = Chain::expected(0);
+        for (level, directory) in first.directories.iter().enumerate() {
+            let (num_items, content_size) = Chain::expected(level);
+            let expected = if level <= DEPTH / 2 { (num_items + nested.0, content_size + nested.1) } else { (num_items, content_size) };
+            assert_content(&database, directory, expected, "recompute").await?;
+        }
+        for (level, directory) in second.directories.iter().enumerate() {
+            assert_content(&database, directory, Chain::expected(level), "recompute").await?;
+        }
+        Ok(())
+    }).await;
+}
@@ -0,0 +1,39 @@
+//! Migrations are applied once, and the server refuses to start when an applied migration was edited.
+mod common;
+
+use common::run;
+use database::migration::DbMigration;
+
+#[tokio::test]
+async fn applying_again_does_nothing() {
+    run(|database| async move {
+        let count = |rows: Vec<tokio_postgres::Row>| rows[0].get::<usize, i64>(0);
+        let query = format!("SELECT COUNT(*) FROM {}.schema_migrations", database.schema);
+        let applied = count(database.db.db().await?.query(&query, &[]).await?);
+        DbMigration::apply(&database.db).await?;
+        assert_eq!(count(database.db.db().await?.query(&query, &[]).await?), applied);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn edited_migrations_are_rejected() {
+    run(|database| async move {
+        database.db.db().await?.batch_execute(&format!("UPDATE {}.schema_migrations SET checksum = 'edited' WHERE version = 1", database.schema)).await?;
+        let error = DbMigration::apply(&database.db).await.expect_err("An edited migration was accepted").to_string();
+        assert!(error.starts_with("Migrations were modified after being applied : 1_"), "{error}");
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn unknown_migrations_cannot_be_reverted() {
+    run(|database| async move {
+        database.db.db().await?.batch_execute(&format!("INSERT INTO {}.schema_migrations (version, name, checksum, applied_at) VALUES (100000, 'future', '', 0)", database.schema)).await?;
+        // Migrations applied by a newer server are tolerated
+        DbMigration::apply(&database.db).await?;
+        let error = DbMigration::rollback(&database.db, 99999).await.expect_err("An unknown migration was reverted").to_string();
+        assert_eq!(error, "Migration 100000 cannot be reverted : no down migration available");
+        Ok(())
+    }).await
+}
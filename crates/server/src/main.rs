@@ -17,6 +17,8 @@ use api::app_ctx::AppCtx;
 use api::{RequestContext, RootRoutes};
 use client_web::WebClient;
 use database::compatibility_upgrade::Upgrade;
+use database::Database;
+use database::migration::DbMigration;
 use database::user::DbUser;
 use types::enc_string::EncString;
 use utils::config::{Config, WebClientConfig};
@@ -109,6 +111,52 @@ async fn main() {
         }
     };
 
+    let mut upgrade = false;
+    let mut upgrade_schema = None;
+    let mut rollback = false;
+    let mut rollback_version = None;
+    for arg in env::args() {
+        if upgrade {
+            upgrade_schema = Some(arg);
+            upgrade = false;
+        } else if rollback {
+            rollback_version = Some(arg);
+            rollback = false;
+        } else if arg == "--upgrade" {
+            upgrade = true;
+        } else if arg == "--rollback" {
+            rollback = true;
+        }
+    }
+
+    // Migrations are reverted before the pending ones could be applied
+    if let Some(rollback_version) = rollback_version {
+        let target_version = match i64::from_str(&rollback_version) {
+            Ok(target_version) => { target_version }
+            Err(_) => {
+                error!("Invalid migration version {rollback_version}");
+                return;
+            }
+        };
+        let database = match Database::connect(&config.backend_config) {
+            Ok(database) => { database }
+            Err(err) => {
+                error!("Failed to connect to the database : {err}");
+                return;
+            }
+        };
+        info!("Reverting migrations above version {target_version}");
+        match DbMigration::rollback(&database, target_version).await {
+            Ok(_) => {
+                info!("Successfully reverted migrations above version {target_version}");
+            }
+            Err(err) => {
+                error!("Failed to revert migrations : {err}");
+            }
+        };
+        return;
+    }
+
     let ctx = Arc::new(match AppCtx::new(config.clone()).await {
         Ok(ctx) => { ctx }
         Err(error) => {
@@ -117,33 +165,22 @@ async fn main() {
         }
     });
 
-
-    if env::args().len() > 0 {
-        let mut upgrade = false;
-        let mut upgrade_schema = None;
-        for arg in env::args() {
-            if upgrade {
-                upgrade_schema = Some(arg);
-                upgrade = false;
-            } else if arg == "--upgrade" {
-                upgrade = true;
+    if let Some(upgrade_schema) = upgrade_schema {
+        info!("Upgrading from old schema {upgrade_schema}");
+        match Upgrade::run(&ctx.database, &upgrade_schema).await {
+            Ok(_) => {
+                info!("Successfully upgraded database from {upgrade_schema}");
+                return;
             }
-        }
-        if let Some(upgrade_schema) = upgrade_schema {
-            info!("Upgrading from old schema {upgrade_schema}");
-            match Upgrade::run(&ctx.database, &upgrade_schema).await {
-                Ok(_) => {
-                    info!("Successfully upgraded database from {upgrade_schema}");
-                    return;
-                }
-                Err(err) => {
-                    error!("Failed to upgrade database : {err}");
-                    return;
-                }
-            };
-        }
+            Err(err) => {
+                error!("Failed to upgrade database : {err}");
+                return;
+            }
+        };
     }
 
+    ctx.start_jobs();
+
     start_web_client(config.web_client_config.clone()).await;
 
     // Start web client
@@ -177,10 +214,7 @@ pub async fn middleware_get_request_context(jar: CookieJar, State(ctx): State<Ar
     };
 
     if let Some(token) = token {
-        context.connected_user = tokio::sync::RwLock::new(match DbUser::from_auth_token(&ctx.database, &token?).await {
-            Ok(connected_user) => { Some(connected_user) }
-            Err(_) => { None }
-        })
+        context.connected_user = tokio::sync::RwLock::new(DbUser::from_auth_token(&ctx.database, &token?).await.ok())
     }
 
     let uri = request.uri().clone();
@@ -0,0 +1,117 @@
+use crate::video_preview::VideoPreview;
+use anyhow::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Encoder speed of svt-av1 (0 is the slowest and most efficient)
+const AV1_PRESET: u32 = 8;
+const OPUS_BITRATE: u32 = 128;
+
+/// Re-encode videos and images to other codecs or formats to save space
+pub struct Converter {}
+
+impl Converter {
+    /// Re-encode a video to AV1 with Opus audio, in a mp4 container. `crf` is the constant rate factor of the encoder
+    /// (0-63, lower is better). `progress` receives the completion of the conversion, from 0 to 1.
+    pub fn video_to_av1(input_path: &Path, output_path: &Path, crf: u32, progress: impl Fn(f32)) -> Result<(), Error> {
+        let duration = VideoPreview::duration(input_path)?;
+        let temp_path = output_path.with_extension("tmp");
+
+        let mut cmd = match Command::new("ffmpeg")
+            .arg("-v")
+            .arg("error")
+            .arg("-y")
+            .arg("-nostats")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-map")
+            .arg("0:v:0")
+            .arg("-map")
+            .arg("0:a?")
+            .arg("-map_metadata")
+            .arg("0")
+            .arg("-c:v")
+            .arg("libsvtav1")
+            .arg("-preset")
+            .arg(AV1_PRESET.to_string())
+            .arg("-crf")
+            .arg(crf.to_string())
+            .arg("-pix_fmt")
+            .arg("yuv420p")
+            .arg("-c:a")
+            .arg("libopus")
+            .arg("-b:a")
+            .arg(format!("{OPUS_BITRATE}k"))
+            .arg("-movflags")
+            .arg("+faststart")
+            .arg("-progress")
+            .arg("pipe:1")
+            .arg("-f")
+            .arg("mp4")
+            .arg(&temp_path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn() {
+            Ok(cmd) => { cmd }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support video conversion because ffmpeg is not available : {}", err)))
+            }
+        };
+
+        // Progress is reported as key=value lines, the encoded duration being out_time_us
+        if let Some(stdout) = cmd.stdout.take() {
+            for line in BufReader::new(stdout).lines() {
+                if let Some(time) = line?.strip_prefix("out_time_us=") {
+                    if let Ok(time) = i64::from_str(time.trim()) {
+                        if duration > 0f32 {
+                            progress((time as f32 / 1_000_000f32 / duration).clamp(0f32, 1f32));
+                        }
+                    }
+                }
+            }
+        }
+        let status = cmd.wait()?;
+        if !status.success() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::msg(format!("ffmpeg failed to convert video : {}", status)));
+        }
+        fs::rename(temp_path, output_path)?;
+        Ok(())
+    }
+
+    /// Re-encode an image with imagemagick. `format` is the output format understood by imagemagick (webp, jpeg,
+    /// avif...) and `quality` goes from 1 to 100.
+    pub fn recompress_image(input_path: &Path, output_path: &Path, format: &str, quality: u32) -> Result<(), Error> {
+        let temp_path = output_path.with_extension("tmp");
+        let mut output_str = OsString::from(format!("{format}:"));
+        output_str.push(temp_path.as_os_str());
+
+        let mut cmd = Command::new("convert");
+        cmd.arg(input_path).arg("-auto-orient");
+        if format == "jpeg" {
+            // Jpeg has no transparency
+            cmd.arg("-background").arg("white").arg("-alpha").arg("remove");
+        }
+        let output = match cmd
+            .arg("-quality")
+            .arg(quality.to_string())
+            .arg(&output_str)
+            .stderr(Stdio::inherit())
+            .output() {
+            Ok(output) => { output }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support image conversion because imagemagick is not available : {}", err)))
+            }
+        };
+        if !output.status.success() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::msg(format!("Imagemagick failed to convert image : {}", output.status)));
+        }
+        fs::rename(temp_path, output_path)?;
+        Ok(())
+    }
+}
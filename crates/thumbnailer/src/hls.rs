@@ -0,0 +1,132 @@
+use crate::video_preview::VideoPreview;
+use anyhow::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Height (px) and video bitrate (kbit/s) of the renditions. Renditions larger than the source are skipped.
+const RENDITIONS: [(u32, u32); 3] = [(480, 1000), (720, 2800), (1080, 5000)];
+const AUDIO_BITRATE: u32 = 128;
+/// Duration (s) of the segments
+const SEGMENT_DURATION: u32 = 6;
+
+/// Name of the master playlist in the output directory. Rendition playlists and segments are stored next to it.
+pub const HLS_MASTER_PLAYLIST: &str = "master.m3u8";
+
+/// Transcode videos to H.264/AAC HLS renditions for adaptive streaming
+pub struct HlsTranscoder {}
+
+impl HlsTranscoder {
+    /// Write the playlists and segments in `output_dir`, which is replaced once the transcode is complete
+    pub fn transcode(input_path: &Path, output_dir: &Path) -> Result<(), Error> {
+        if VideoPreview::duration(input_path)? <= 0f32 {
+            return Err(Error::msg("Unknown video duration"));
+        }
+        let source_height = Self::probe(input_path, "v:0", "stream=height")?.and_then(|height| u32::from_str(&height).ok())
+            .ok_or(Error::msg("Failed to find video stream"))?;
+        let has_audio = Self::probe(input_path, "a:0", "stream=index")?.is_some();
+
+        let mut renditions: Vec<(u32, u32)> = RENDITIONS.iter().filter(|(height, _)| *height <= source_height).cloned().collect();
+        if renditions.is_empty() {
+            renditions.push((source_height / 2 * 2, RENDITIONS[0].1));
+        }
+
+        let temp_dir = output_dir.with_extension("tmp");
+        if temp_dir.exists() {
+            fs::remove_dir_all(&temp_dir)?;
+        }
+        fs::create_dir_all(&temp_dir)?;
+
+        let mut cmd = Command::new("ffmpeg");
+        cmd.arg("-v").arg("error").arg("-y").arg("-i").arg(input_path);
+
+        let splits: String = (0..renditions.len()).map(|index| format!("[s{index}]")).collect();
+        let mut filter = format!("[0:v]split={}{splits}", renditions.len());
+        for (index, (height, _)) in renditions.iter().enumerate() {
+            filter += &format!(";[s{index}]scale=-2:{height}[v{index}]");
+        }
+        cmd.arg("-filter_complex").arg(filter);
+
+        let mut stream_map = vec![];
+        for (index, (_, bitrate)) in renditions.iter().enumerate() {
+            cmd.arg("-map").arg(format!("[v{index}]"))
+                .arg(format!("-b:v:{index}")).arg(format!("{bitrate}k"))
+                .arg(format!("-maxrate:v:{index}")).arg(format!("{}k", bitrate * 107 / 100))
+                .arg(format!("-bufsize:v:{index}")).arg(format!("{}k", bitrate * 3 / 2));
+            if has_audio {
+                cmd.arg("-map").arg("0:a:0");
+                stream_map.push(format!("v:{index},a:{index}"));
+            } else {
+                stream_map.push(format!("v:{index}"));
+            }
+        }
+
+        let mut segment_pattern = OsString::from(temp_dir.as_os_str());
+        segment_pattern.push("/stream_%v_%05d.ts");
+        let mut playlist_pattern = OsString::from(temp_dir.as_os_str());
+        playlist_pattern.push("/stream_%v.m3u8");
+
+        // Key frames are aligned on segments so that the player can switch between renditions
+        cmd.arg("-c:v").arg("libx264")
+            .arg("-preset").arg("veryfast")
+            .arg("-pix_fmt").arg("yuv420p")
+            .arg("-sc_threshold").arg("0")
+            .arg("-force_key_frames").arg(format!("expr:gte(t,n_forced*{SEGMENT_DURATION})"));
+        if has_audio {
+            cmd.arg("-c:a").arg("aac").arg("-b:a").arg(format!("{AUDIO_BITRATE}k")).arg("-ac").arg("2");
+        }
+        let result = cmd.arg("-f").arg("hls")
+            .arg("-hls_time").arg(SEGMENT_DURATION.to_string())
+            .arg("-hls_playlist_type").arg("vod")
+            .arg("-hls_flags").arg("independent_segments")
+            .arg("-hls_segment_filename").arg(segment_pattern)
+            .arg("-master_pl_name").arg(HLS_MASTER_PLAYLIST)
+            .arg("-var_stream_map").arg(stream_map.join(" "))
+            .arg(playlist_pattern)
+            .stderr(Stdio::inherit())
+            .output();
+
+        let result = match result {
+            Ok(result) => { result }
+            Err(err) => {
+                let _ = fs::remove_dir_all(&temp_dir);
+                return Err(Error::msg(format!("This server doesn't support video streaming because ffmpeg is not available : {}", err)))
+            }
+        };
+        if !result.status.success() || !temp_dir.join(HLS_MASTER_PLAYLIST).exists() {
+            let _ = fs::remove_dir_all(&temp_dir);
+            return Err(Error::msg(format!("ffmpeg failed to transcode video : {}", result.status)));
+        }
+
+        if output_dir.exists() {
+            fs::remove_dir_all(output_dir)?;
+        }
+        fs::rename(temp_dir, output_dir)?;
+        Ok(())
+    }
+
+    /// Read a property of a stream with ffprobe (None if the stream doesn't exist)
+    fn probe(input_path: &Path, stream: &str, entry: &str) -> Result<Option<String>, Error> {
+        let cmd = match Command::new("ffprobe")
+            .arg("-v")
+            .arg("error")
+            .arg("-select_streams")
+            .arg(stream)
+            .arg("-show_entries")
+            .arg(entry)
+            .arg("-of")
+            .arg("csv=p=0")
+            .arg(input_path)
+            .stderr(Stdio::inherit())
+            .output() {
+            Ok(cmd) => { cmd }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support video streaming because ffmpeg is not available : {}", err)))
+            }
+        };
+        let value = String::from_utf8(cmd.stdout)?.trim().to_string();
+        Ok(if value.is_empty() { None } else { Some(value) })
+    }
+}
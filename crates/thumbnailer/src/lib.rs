@@ -1,35 +1,25 @@
 use anyhow::Error;
 use std::ffi::OsString;
 use std::{env, fs};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::str::FromStr;
+use crate::raw_image::RawImage;
+use crate::video_preview::VideoPreview;
+
+pub mod converter;
+pub mod hls;
+pub mod metadata;
+pub mod raw_image;
+pub mod text_extractor;
+pub mod video_preview;
 
 pub struct Thumbnail {}
 
 impl Thumbnail {
     fn video_thumbnail(input_path: &PathBuf, output_path: &PathBuf, size: u32) -> Result<(), Error> {
-        let get_duration_cmd = match Command::new("ffprobe")
-            .arg("-v")
-            .arg("error")
-            .arg("-show_entries")
-            .arg("format=duration")
-            .arg("-of")
-            .arg("default=noprint_wrappers=1:nokey=1")
-            .arg(input_path)
-            .stderr(Stdio::inherit())
-            .output() {
-            Ok(cmd) => { cmd }
-            Err(err) => {
-                return Err(Error::msg(format!("This server doesn't support thumbnails because ffmpeg is not available : {}", err)))
-            }
-        };
-        let duration = match f32::from_str(String::from_utf8(get_duration_cmd.stdout)?.as_str()) {
-            Ok(duration) => { duration }
-            Err(_) => { 0f32 }
-        };
+        let duration = VideoPreview::duration(input_path)?;
 
-        let cmd = match Command::new("ffmpeg")
+        let output = match Command::new("ffmpeg")
             .arg("-v")
             .arg("error")
             .arg("-ss")
@@ -44,69 +34,103 @@ impl Thumbnail {
             .arg("webp")
             .arg(output_path)
             .stderr(Stdio::inherit())
-            .spawn() {
-            Ok(cmd) => { cmd }
+            .output() {
+            Ok(output) => { output }
             Err(err) => {
                 return Err(Error::msg(format!("This server doesn't support thumbnails because ffmpeg is not available : {}", err)))
             }
         };
-        cmd.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::msg(format!("ffmpeg failed to generate thumbnail : {}", output.status)));
+        }
+        // ffmpeg succeeds without writing anything when no frame could be decoded
+        if !output_path.exists() {
+            return Err(Error::msg("ffmpeg did not extract any frame"));
+        }
         Ok(())
     }
 
-    fn image_thumbnail(input_path: &PathBuf, output_path: &PathBuf, mimetype: &String, size: u32) -> Result<(), Error> {
-        fs::create_dir_all(output_path.parent().unwrap())?;
-        let mime_plain = match mimetype.as_str() {
-            "image/x-icon" => {
-                "image/ico"
-            }
-            "image/vnd.microsoft.icon" => {
-                "image/ico"
-            }
+    /// Image formats decoded in-process. Other formats (heic, svg...) require ImageMagick.
+    fn is_native_image(mimetype: &str) -> bool {
+        matches!(mimetype, "image/jpeg" | "image/pjpeg" | "image/png" | "image/apng" | "image/gif" | "image/webp" | "image/bmp" | "image/x-bmp" | "image/x-ms-bmp"
+            | "image/tiff" | "image/x-icon" | "image/vnd.microsoft.icon")
+    }
+
+    fn image_thumbnail(input_path: &Path, output_path: &Path, mimetype: &str, size: u32) -> Result<(), Error> {
+        if RawImage::is_supported(mimetype) {
+            Self::save_thumbnail(RawImage::decode(input_path)?, output_path, size)
+        } else if Self::is_native_image(mimetype) {
+            Self::native_image_thumbnail(input_path, output_path, size)
+        } else {
+            Self::external_image_thumbnail(input_path, output_path, mimetype, size)
+        }
+    }
+
+    fn native_image_thumbnail(input_path: &Path, output_path: &Path, size: u32) -> Result<(), Error> {
+        use image::{DynamicImage, ImageDecoder, ImageReader};
+
+        let mut decoder = ImageReader::open(input_path)?.with_guessed_format()?.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Self::save_thumbnail(image, output_path, size)
+    }
+
+    /// Downscale the image to fit in `size` and save it as webp
+    fn save_thumbnail(mut image: image::DynamicImage, output_path: &Path, size: u32) -> Result<(), Error> {
+        use image::DynamicImage;
+        use image::imageops::FilterType;
+
+        if image.width() > size || image.height() > size {
+            image = image.resize(size, size, FilterType::Lanczos3);
+        }
+        let image = if image.color().has_alpha() { DynamicImage::from(image.into_rgba8()) } else { DynamicImage::from(image.into_rgb8()) };
+        image.save_with_format(output_path, image::ImageFormat::WebP)?;
+        Ok(())
+    }
+
+    fn external_image_thumbnail(input_path: &Path, output_path: &Path, mimetype: &str, size: u32) -> Result<(), Error> {
+        let mime_plain = match mimetype {
             "image/svg+xml" => {
                 "image/svg"
             }
-            "image/x-canon-cr2" => {
-                "image/cr2"
-            }
             plain => { plain }
         };
         let mut mime = mime_plain.split("/");
         mime.next();
-        let mut path_str = OsString::from(mime.next().ok_or(Error::msg(format!("invalid mimetype : {}", mime_plain)))?);
-        path_str.push(":");
-        path_str.push(input_path.as_os_str());
-
-        let cmd = match Command::new("mogrify")
-            .arg("-format")
-            .arg("webp")
+        let mut input_str = OsString::from(mime.next().ok_or(Error::msg(format!("invalid mimetype : {}", mime_plain)))?);
+        input_str.push(":");
+        input_str.push(input_path.as_os_str());
+        let mut output_str = OsString::from("webp:");
+        output_str.push(output_path.as_os_str());
+
+        let cmd = match Command::new("convert")
+            .arg(&input_str)
+            .arg("-auto-orient")
+            .arg("-thumbnail")
+            .arg(format!("{size}x{size}"))
             .arg("-interlace")
             .arg("plane")
             .arg("-quality")
             .arg("70%")
-            .arg("-path")
-            .arg(output_path.parent().unwrap())
-            .arg("-thumbnail")
-            .arg(format!("{size}x{size}"))
-            .arg("-auto-orient")
-            .arg(&path_str)
+            .arg(&output_str)
             .stderr(Stdio::inherit())
             .stdout(Stdio::inherit())
             .spawn() {
             Ok(cmd) => { cmd }
             Err(err) => {
-                return Err(Error::msg(format!("This server doesn't support thumbnails because imagemagick is not available : {}", err)))
+                return Err(Error::msg(format!("This server doesn't support thumbnails for {mimetype} because imagemagick is not available : {}", err)))
             }
         };
-        cmd.wait_with_output()?;
-
-        let mut generated_file_name = OsString::from(output_path.file_name().unwrap());
-        generated_file_name.push(".webp");
-        fs::rename(output_path.parent().unwrap().join(generated_file_name), output_path)?;
+        let output = cmd.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::msg(format!("Imagemagick failed to generate thumbnail : {}", output.status)));
+        }
         Ok(())
     }
 
-    fn pdf_thumbnail(input_path: &PathBuf, output_path: &PathBuf, size: u32) -> Result<(), Error> {
+    /// Bind to the pdfium library distributed next to the executable
+    pub(crate) fn load_pdfium() -> Result<pdfium_render::prelude::Pdfium, Error> {
         use pdfium_render::prelude::*;
 
         // binaries available at https://github.com/bblanchon/pdfium-binaries/releases
@@ -139,7 +163,13 @@ impl Thumbnail {
             }
         };
 
-        let pdfium = Pdfium::new(bindings?);
+        Ok(Pdfium::new(bindings?))
+    }
+
+    fn pdf_thumbnail(input_path: &PathBuf, output_path: &PathBuf, size: u32) -> Result<(), Error> {
+        use pdfium_render::prelude::*;
+
+        let pdfium = Self::load_pdfium()?;
         let document = pdfium.load_pdf_from_file(input_path, None)?;
 
         let render_config = PdfRenderConfig::new()
@@ -158,10 +188,28 @@ impl Thumbnail {
         Ok(())
     }
 
-    pub fn create(input_path: &PathBuf, output_path: &PathBuf, mimetype: &String, size: u32) -> Result<PathBuf, Error> {
+    /// Thumbnails can be generated for pdf, image and video files
+    pub fn is_supported(mimetype: &str) -> bool {
+        mimetype.contains("pdf") || mimetype.starts_with("image/") || mimetype.starts_with("video/")
+    }
+
+    /// The thumbnail is generated in a temporary file first, so that an incomplete file is never served
+    pub fn create(input_path: &PathBuf, output_path: &PathBuf, mimetype: &str, size: u32) -> Result<PathBuf, Error> {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = output_path.with_extension("tmp");
+        if let Err(err) = Self::generate(input_path, &temp_path, mimetype, size) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        fs::rename(temp_path, output_path)?;
+        Ok(output_path.clone())
+    }
+
+    fn generate(input_path: &PathBuf, output_path: &PathBuf, mimetype: &str, size: u32) -> Result<(), Error> {
         if mimetype.contains("pdf") {
-            Self::pdf_thumbnail(input_path, output_path, size)?;
-            return Ok(output_path.clone());
+            return Self::pdf_thumbnail(input_path, output_path, size);
         }
 
         let mut mime_start = mimetype.split("/");
@@ -176,12 +224,12 @@ impl Thumbnail {
                 return Err(Error::msg(format!("Unsupported mimetype : {mimetype}")));
             }
         }
-        Ok(output_path.clone())
+        Ok(())
     }
     pub fn mimetype<'a>() -> &'a str {
         "image/jpeg"
     }
-    pub fn find_or_create(input_path: &PathBuf, output_path: &PathBuf, mimetype: &String, size: u32) -> Result<PathBuf, Error> {
+    pub fn find_or_create(input_path: &PathBuf, output_path: &PathBuf, mimetype: &str, size: u32) -> Result<PathBuf, Error> {
         if output_path.exists() {
             Ok(output_path.clone())
         } else {
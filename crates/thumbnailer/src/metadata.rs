@@ -0,0 +1,194 @@
+use anyhow::Error;
+use chrono::{DateTime, NaiveDate};
+use exif::{Exif, In, Tag, Value};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use types::item::MediaMetadata;
+
+/// Read the metadata of photos (exif), videos and audio files (ffprobe)
+pub struct MetadataExtractor {}
+
+#[derive(Deserialize, Default)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    #[serde(default)]
+    disposition: HashMap<String, i32>,
+}
+
+#[derive(Deserialize, Default)]
+struct ProbeFormat {
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ProbeResult {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    #[serde(default)]
+    format: ProbeFormat,
+}
+
+impl MetadataExtractor {
+    /// Metadata of the file, or None if this type of file has no supported metadata
+    pub fn extract(path: &Path, mimetype: &str) -> Result<Option<MediaMetadata>, Error> {
+        if mimetype.starts_with("image/") {
+            Ok(Some(Self::photo(path)?))
+        } else if mimetype.starts_with("video/") || mimetype.starts_with("audio/") {
+            Ok(Some(Self::media(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn photo(path: &Path) -> Result<MediaMetadata, Error> {
+        let mut metadata = MediaMetadata::default();
+        // Many images (screenshots, png...) have no exif : only their dimensions are known
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut BufReader::new(File::open(path)?)) {
+            metadata.capture_date = Self::exif_date(&exif);
+            metadata.camera_make = Self::exif_string(&exif, Tag::Make);
+            metadata.camera_model = Self::exif_string(&exif, Tag::Model);
+            metadata.lens = Self::exif_string(&exif, Tag::LensModel);
+            metadata.latitude = Self::exif_coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S');
+            metadata.longitude = Self::exif_coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W');
+            metadata.width = Self::exif_uint(&exif, Tag::PixelXDimension).or(Self::exif_uint(&exif, Tag::ImageWidth));
+            metadata.height = Self::exif_uint(&exif, Tag::PixelYDimension).or(Self::exif_uint(&exif, Tag::ImageLength));
+            metadata.orientation = Self::exif_uint(&exif, Tag::Orientation);
+        }
+        if metadata.width.is_none() || metadata.height.is_none() {
+            let dimensions = image::ImageReader::open(path).ok()
+                .and_then(|reader| reader.with_guessed_format().ok())
+                .and_then(|reader| reader.into_dimensions().ok());
+            if let Some((width, height)) = dimensions {
+                metadata.width = Some(width as i32);
+                metadata.height = Some(height as i32);
+            }
+        }
+        Ok(metadata)
+    }
+
+    fn exif_string(exif: &Exif, tag: Tag) -> Option<String> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => {
+                let value = String::from_utf8_lossy(values.first()?).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+                if value.is_empty() { None } else { Some(value) }
+            }
+            _ => { None }
+        }
+    }
+
+    fn exif_uint(exif: &Exif, tag: Tag) -> Option<i32> {
+        exif.get_field(tag, In::PRIMARY)?.value.get_uint(0).map(|value| value as i32)
+    }
+
+    /// Date the photo was taken. Without a recorded time zone, the date is considered to be UTC.
+    fn exif_date(exif: &Exif) -> Option<i64> {
+        let (field, offset_tag) = match exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
+            Some(field) => { (field, Tag::OffsetTimeOriginal) }
+            None => { (exif.get_field(Tag::DateTime, In::PRIMARY)?, Tag::OffsetTime) }
+        };
+        let mut date = match &field.value {
+            Value::Ascii(values) => { exif::DateTime::from_ascii(values.first()?).ok()? }
+            _ => { return None }
+        };
+        if let Some(Value::Ascii(values)) = exif.get_field(offset_tag, In::PRIMARY).map(|field| &field.value) {
+            if let Some(offset) = values.first() {
+                let _ = date.parse_offset(offset);
+            }
+        }
+        let timestamp = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+            .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)?
+            .and_utc().timestamp_millis();
+        Some(timestamp - date.offset.unwrap_or_default() as i64 * 60 * 1000)
+    }
+
+    /// Signed decimal degrees of a gps coordinate stored as degrees, minutes and seconds
+    fn exif_coordinate(exif: &Exif, tag: Tag, reference: Tag, negative_reference: u8) -> Option<f64> {
+        let value = match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(values) if values.len() >= 3 => { values[0].to_f64() + values[1].to_f64() / 60f64 + values[2].to_f64() / 3600f64 }
+            _ => { return None }
+        };
+        if !value.is_finite() {
+            return None;
+        }
+        let negative = match exif.get_field(reference, In::PRIMARY).map(|field| &field.value) {
+            Some(Value::Ascii(values)) => { values.first().and_then(|value| value.first()) == Some(&negative_reference) }
+            _ => { false }
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    fn media(path: &Path) -> Result<MediaMetadata, Error> {
+        let cmd = match Command::new("ffprobe")
+            .arg("-v")
+            .arg("error")
+            .arg("-show_entries")
+            .arg("format=duration:format_tags:stream=codec_type,codec_name,width,height:stream_disposition=attached_pic")
+            .arg("-of")
+            .arg("json")
+            .arg(path)
+            .stderr(Stdio::inherit())
+            .output() {
+            Ok(cmd) => { cmd }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support media metadata because ffmpeg is not available : {}", err)))
+            }
+        };
+        if !cmd.status.success() {
+            return Err(Error::msg(format!("ffprobe failed to read metadata : {}", cmd.status)));
+        }
+        let probe: ProbeResult = serde_json::from_slice(&cmd.stdout)?;
+
+        let mut metadata = MediaMetadata {
+            duration: probe.format.duration.and_then(|duration| f64::from_str(duration.trim()).ok()),
+            ..Default::default()
+        };
+        // Cover arts of audio files are reported as video streams
+        if let Some(video) = probe.streams.iter().find(|stream| stream.codec_type.as_deref() == Some("video") && stream.disposition.get("attached_pic") != Some(&1)) {
+            metadata.video_codec = video.codec_name.clone();
+            metadata.width = video.width;
+            metadata.height = video.height;
+        }
+        if let Some(audio) = probe.streams.iter().find(|stream| stream.codec_type.as_deref() == Some("audio")) {
+            metadata.audio_codec = audio.codec_name.clone();
+        }
+
+        // Tag names depend on the container (ARTIST in flac, artist in mp3...)
+        let tags: HashMap<String, String> = probe.format.tags.into_iter().map(|(key, value)| (key.to_lowercase(), value.trim().to_string())).filter(|(_, value)| !value.is_empty()).collect();
+        metadata.artist = tags.get("artist").or(tags.get("album_artist")).cloned();
+        metadata.album = tags.get("album").cloned();
+        metadata.title = tags.get("title").cloned();
+        // Track numbers may be written as "3/12"
+        metadata.track = tags.get("track").and_then(|track| i32::from_str(track.split('/').next().unwrap_or_default().trim()).ok());
+        metadata.capture_date = tags.get("creation_time").and_then(|date| DateTime::parse_from_rfc3339(date).ok()).map(|date| date.timestamp_millis());
+        metadata.camera_make = tags.get("com.apple.quicktime.make").cloned();
+        metadata.camera_model = tags.get("com.apple.quicktime.model").cloned();
+        if let Some((latitude, longitude)) = tags.get("location").or(tags.get("com.apple.quicktime.location.iso6709")).and_then(|location| Self::iso6709_location(location)) {
+            metadata.latitude = Some(latitude);
+            metadata.longitude = Some(longitude);
+        }
+        Ok(metadata)
+    }
+
+    /// Parse the latitude and longitude of an ISO 6709 location in decimal degrees (`+48.8584+002.2945+035.000/`)
+    fn iso6709_location(location: &str) -> Option<(f64, f64)> {
+        let location = location.trim_end_matches('/');
+        let mut bounds: Vec<usize> = location.char_indices().filter(|(_, c)| *c == '+' || *c == '-').map(|(index, _)| index).collect();
+        bounds.push(location.len());
+        if bounds.len() < 3 || bounds[0] != 0 {
+            return None;
+        }
+        let latitude = f64::from_str(&location[bounds[0]..bounds[1]]).ok()?;
+        let longitude = f64::from_str(&location[bounds[1]..bounds[2]]).ok()?;
+        Some((latitude, longitude))
+    }
+}
@@ -0,0 +1,599 @@
+use anyhow::Error;
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat};
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Quality of the jpeg renditions displayed by the viewer
+const RENDITION_QUALITY: u8 = 90;
+/// Larger renditions are downscaled (4K screens)
+const RENDITION_MAX_SIZE: u32 = 3840;
+/// Bytes read to check that an embedded image is a jpeg browsers and the image crate can decode
+const JPEG_HEADER_SIZE: u64 = 64 * 1024;
+/// Upper bound of the number of IFDs visited in a tiff structure, in case of corrupted offsets
+const MAX_IFDS: usize = 64;
+/// Uuid of the box holding the preview of CR3 files
+const CR3_PREVIEW_UUID: [u8; 16] = [0xea, 0xf4, 0x2b, 0x5e, 0x1c, 0x98, 0x4b, 0x88, 0xb9, 0xfb, 0xb7, 0xdc, 0x40, 0x6e, 0x4d, 0x16];
+/// Uuid of the box holding the metadata (CMT1 to CMT4) of CR3 files
+const CR3_METADATA_UUID: [u8; 16] = [0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48];
+
+/// Raw camera files. Cameras store a jpeg preview next to the sensor data : the largest one is used when present,
+/// otherwise the sensor data is demosaiced by dcraw with the white balance of the camera.
+pub struct RawImage {}
+
+/// Jpeg image stored in a raw file
+struct EmbeddedPreview {
+    data: Vec<u8>,
+    /// Orientation of the raw file, used when the preview doesn't specify its own
+    orientation: Orientation,
+}
+
+impl EmbeddedPreview {
+    fn decoder(&self) -> Result<JpegDecoder<Cursor<&[u8]>>, Error> {
+        Ok(JpegDecoder::new(Cursor::new(self.data.as_slice()))?)
+    }
+
+    /// Orientation of the preview, or of the raw file if the preview has no exif
+    fn orientation(&self, decoder: &mut JpegDecoder<Cursor<&[u8]>>) -> Result<Orientation, Error> {
+        Ok(match decoder.orientation()? {
+            Orientation::NoTransforms => { self.orientation }
+            orientation => { orientation }
+        })
+    }
+
+    fn decode(&self) -> Result<DynamicImage, Error> {
+        let mut decoder = self.decoder()?;
+        let orientation = self.orientation(&mut decoder)?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(image)
+    }
+}
+
+impl RawImage {
+    pub fn is_supported(mimetype: &str) -> bool {
+        matches!(mimetype, "image/x-canon-cr2" | "image/x-canon-cr3" | "image/x-canon-crw" | "image/x-nikon-nef" | "image/x-nikon-nrw" | "image/x-sony-arw"
+            | "image/x-fuji-raf" | "image/x-adobe-dng" | "image/x-olympus-orf" | "image/x-panasonic-rw2" | "image/x-pentax-pef")
+    }
+
+    /// Upright image of the raw file
+    pub fn decode(path: &Path) -> Result<DynamicImage, Error> {
+        match Self::embedded_preview(path)? {
+            Some(preview) => { preview.decode() }
+            None => { Self::demosaic(path) }
+        }
+    }
+
+    /// Jpeg that can be displayed by browsers. The embedded preview is copied as is when it doesn't need to be
+    /// rotated or resized.
+    pub fn jpeg_rendition(input_path: &Path, output_path: &Path) -> Result<(), Error> {
+        let max_size = RENDITION_MAX_SIZE;
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = output_path.with_extension("tmp");
+        let image = match Self::embedded_preview(input_path)? {
+            Some(preview) => {
+                let mut decoder = preview.decoder()?;
+                let orientation = preview.orientation(&mut decoder)?;
+                let (width, height) = decoder.dimensions();
+                if orientation == Orientation::NoTransforms && width <= max_size && height <= max_size {
+                    fs::write(&temp_path, &preview.data)?;
+                    fs::rename(temp_path, output_path)?;
+                    return Ok(());
+                }
+                preview.decode()?
+            }
+            None => { Self::demosaic(input_path)? }
+        };
+        let image = if image.width() > max_size || image.height() > max_size { image.resize(max_size, max_size, FilterType::Lanczos3) } else { image };
+        let mut output = File::create(&temp_path)?;
+        if let Err(err) = JpegEncoder::new_with_quality(&mut output, RENDITION_QUALITY).encode_image(&DynamicImage::from(image.into_rgb8())) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+        fs::rename(temp_path, output_path)?;
+        Ok(())
+    }
+
+    /// Develop the sensor data. dcraw applies the white balance of the camera (-w), converts to sRGB (-o 1) and
+    /// rotates the image itself.
+    fn demosaic(path: &Path) -> Result<DynamicImage, Error> {
+        let output = match Command::new("dcraw")
+            .arg("-c")
+            .arg("-w")
+            .arg("-o")
+            .arg("1")
+            .arg("-q")
+            .arg("3")
+            .arg("-T")
+            .arg(path)
+            .stderr(Stdio::inherit())
+            .output() {
+            Ok(output) => { output }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support raw files without embedded preview because dcraw is not available : {}", err)))
+            }
+        };
+        if !output.status.success() {
+            return Err(Error::msg(format!("dcraw failed to decode raw file : {}", output.status)));
+        }
+        Ok(image::load_from_memory_with_format(&output.stdout, ImageFormat::Tiff)?)
+    }
+
+    /// Largest jpeg preview of the file, if any
+    fn embedded_preview(path: &Path) -> Result<Option<EmbeddedPreview>, Error> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 16];
+        file.read_exact(&mut magic)?;
+
+        let (mut candidates, orientation) = if &magic[..15] == b"FUJIFILMCCD-RAW" {
+            (Self::raf_previews(&mut file)?, Orientation::NoTransforms)
+        } else if &magic[4..8] == b"ftyp" && &magic[8..12] == b"crx " {
+            Self::cr3_previews(&mut file)?
+        } else if let Some(tiff) = TiffReader::new(&mut file, 0)? {
+            tiff.previews(&mut file)?
+        } else {
+            return Ok(None);
+        };
+
+        candidates.sort_by_key(|(_, length)| std::cmp::Reverse(*length));
+        for (offset, length) in candidates {
+            // Offsets of corrupted or unknown files may point anywhere
+            match read_at(&mut file, offset, length.min(JPEG_HEADER_SIZE)) {
+                Ok(header) if Self::is_supported_jpeg(&header) => {}
+                _ => { continue }
+            }
+            let data = read_at(&mut file, offset, length)?;
+            let preview = EmbeddedPreview { data, orientation };
+            if preview.decoder().is_ok() {
+                return Ok(Some(preview));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Baseline or progressive jpeg. The sensor data of many raw formats is stored as lossless jpeg, which is not
+    /// an image that can be displayed.
+    fn is_supported_jpeg(header: &[u8]) -> bool {
+        if !header.starts_with(&[0xFF, 0xD8]) {
+            return false;
+        }
+        let mut index = 2;
+        while index + 4 <= header.len() {
+            if header[index] != 0xFF {
+                return false;
+            }
+            let marker = header[index + 1];
+            match marker {
+                0xC0..=0xC2 => { return true }
+                0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => { return false }
+                0xD8 | 0x01 | 0xD0..=0xD7 => { index += 2 }
+                _ => { index += 2 + u16::from_be_bytes([header[index + 2], header[index + 3]]) as usize }
+            }
+        }
+        false
+    }
+
+    /// Fujifilm files start with a header giving the position of a jpeg
+    fn raf_previews(file: &mut File) -> Result<Vec<(u64, u64)>, Error> {
+        let header = read_at(file, 84, 8)?;
+        let offset = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as u64;
+        Ok(vec![(offset, length)])
+    }
+
+    /// CR3 files are ISO base media files : the first track holds a full size jpeg, and a smaller one is stored in a
+    /// PRVW box. The orientation is stored in the tiff structure of the CMT1 box.
+    fn cr3_previews(file: &mut File) -> Result<(Vec<(u64, u64)>, Orientation), Error> {
+        let mut candidates = vec![];
+        let mut orientation = Orientation::NoTransforms;
+        let file_size = file.metadata()?.len();
+        for (kind, offset, size) in Self::iso_boxes(file, 0, file_size)? {
+            match &kind {
+                b"moov" => {
+                    let moov = Self::iso_boxes(file, offset, size)?;
+                    if let Some((_, trak_offset, trak_size)) = moov.iter().find(|(kind, _, _)| kind == b"trak") {
+                        if let Some(sample) = Self::iso_first_sample(file, *trak_offset, *trak_size)? {
+                            candidates.push(sample);
+                        }
+                    }
+                    for (kind, uuid_offset, uuid_size) in moov {
+                        if &kind == b"uuid" && uuid_size > 16 && read_at(file, uuid_offset, 16)? == CR3_METADATA_UUID {
+                            if let Some((_, cmt1_offset, _)) = Self::iso_boxes(file, uuid_offset + 16, uuid_size - 16)?.into_iter().find(|(kind, _, _)| kind == b"CMT1") {
+                                if let Some(tiff) = TiffReader::new(file, cmt1_offset)? {
+                                    orientation = tiff.orientation(file)?;
+                                }
+                            }
+                        }
+                    }
+                }
+                b"uuid" if size > 16 + 8 && read_at(file, offset, 16)? == CR3_PREVIEW_UUID => {
+                    // uuid, 8 unknown bytes, then the PRVW box : header, 12 bytes of dimensions and flags, jpeg size and jpeg
+                    if let Some((_, prvw_offset, prvw_size)) = Self::iso_boxes(file, offset + 24, size - 24)?.into_iter().find(|(kind, _, _)| kind == b"PRVW") {
+                        if prvw_size > 16 {
+                            let header = read_at(file, prvw_offset + 12, 4)?;
+                            let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+                            candidates.push((prvw_offset + 16, length.min(prvw_size - 16)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((candidates, orientation))
+    }
+
+    /// Offset and size of the first sample of a track (trak > mdia > minf > stbl > stsz / co64)
+    fn iso_first_sample(file: &mut File, offset: u64, size: u64) -> Result<Option<(u64, u64)>, Error> {
+        let mut parent = (offset, size);
+        for kind in [b"mdia", b"minf", b"stbl"] {
+            parent = match Self::iso_boxes(file, parent.0, parent.1)?.into_iter().find(|(child, _, _)| child == kind) {
+                Some((_, offset, size)) => { (offset, size) }
+                None => { return Ok(None) }
+            };
+        }
+        let boxes = Self::iso_boxes(file, parent.0, parent.1)?;
+        let sample_size = match boxes.iter().find(|(kind, _, _)| kind == b"stsz") {
+            Some((_, offset, size)) if *size >= 16 => {
+                // version and flags, default size, count, then the size of each sample if there is no default size
+                let data = read_at(file, *offset, 16)?;
+                match u32::from_be_bytes([data[4], data[5], data[6], data[7]]) {
+                    0 => { u32::from_be_bytes([data[12], data[13], data[14], data[15]]) as u64 }
+                    size => { size as u64 }
+                }
+            }
+            _ => { return Ok(None) }
+        };
+        let sample_offset = match boxes.iter().find(|(kind, _, _)| kind == b"co64" || kind == b"stco") {
+            Some((kind, offset, size)) if kind == b"co64" && *size >= 16 => {
+                let data = read_at(file, *offset + 8, 8)?;
+                u64::from_be_bytes([data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7]])
+            }
+            Some((_, offset, size)) if *size >= 12 => {
+                let data = read_at(file, *offset + 8, 4)?;
+                u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64
+            }
+            _ => { return Ok(None) }
+        };
+        Ok(Some((sample_offset, sample_size)))
+    }
+
+    /// Type, content offset and content size of the boxes stored in the given range
+    fn iso_boxes(file: &mut File, offset: u64, size: u64) -> Result<Vec<([u8; 4], u64, u64)>, Error> {
+        let mut boxes = vec![];
+        let end = match offset.checked_add(size) {
+            Some(end) => { end }
+            None => { return Ok(boxes) }
+        };
+        let mut position = offset;
+        while position.checked_add(8).map(|header_end| header_end <= end).unwrap_or(false) {
+            let header = read_at(file, position, 8)?;
+            let kind = [header[4], header[5], header[6], header[7]];
+            let (header_size, box_size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64 {
+                0 => { (8, end - position) }
+                1 => {
+                    let large = read_at(file, position + 8, 8)?;
+                    (16, u64::from_be_bytes([large[0], large[1], large[2], large[3], large[4], large[5], large[6], large[7]]))
+                }
+                size => { (8, size) }
+            };
+            // Sizes of corrupted files may wrap around or not move forward
+            let next = match position.checked_add(box_size) {
+                Some(next) if box_size >= header_size && next > position && next <= end => { next }
+                _ => { break }
+            };
+            boxes.push((kind, position + header_size, box_size - header_size));
+            position = next;
+        }
+        Ok(boxes)
+    }
+}
+
+/// Minimal reader of the tiff structure used by most raw formats (CR2, NEF, ARW, DNG, ORF, RW2, PEF...)
+struct TiffReader {
+    /// Offsets are relative to the start of the tiff header
+    base: u64,
+    little_endian: bool,
+    first_ifd: u64,
+}
+
+/// Entry of an IFD
+struct TiffEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Raw value field : the value itself if it fits in 4 bytes, its offset otherwise
+    value: [u8; 4],
+}
+
+impl TiffReader {
+    /// None if the data at `base` is not a tiff header
+    fn new(file: &mut File, base: u64) -> Result<Option<Self>, Error> {
+        let header = match read_at(file, base, 8) {
+            Ok(header) => { header }
+            Err(_) => { return Ok(None) }
+        };
+        let little_endian = match &header[..2] {
+            b"II" => { true }
+            b"MM" => { false }
+            _ => { return Ok(None) }
+        };
+        let reader = Self { base, little_endian, first_ifd: 0 };
+        // 42 for tiff, 'RO' and 'SR' for olympus, 0x55 for panasonic
+        if !matches!(reader.u16(&header[2..4]), 42 | 0x4F52 | 0x5352 | 0x55) {
+            return Ok(None);
+        }
+        let first_ifd = reader.u32(&header[4..8]) as u64;
+        Ok(Some(Self { first_ifd, ..reader }))
+    }
+
+    fn u16(&self, data: &[u8]) -> u16 {
+        if self.little_endian { u16::from_le_bytes([data[0], data[1]]) } else { u16::from_be_bytes([data[0], data[1]]) }
+    }
+
+    fn u32(&self, data: &[u8]) -> u32 {
+        if self.little_endian { u32::from_le_bytes([data[0], data[1], data[2], data[3]]) } else { u32::from_be_bytes([data[0], data[1], data[2], data[3]]) }
+    }
+
+    /// Value of a SHORT or LONG entry holding a single value
+    fn single_value(&self, entry: &TiffEntry) -> Option<u64> {
+        if entry.count != 1 {
+            return None;
+        }
+        match entry.kind {
+            3 => { Some(self.u16(&entry.value) as u64) }
+            4 | 13 => { Some(self.u32(&entry.value) as u64) }
+            _ => { None }
+        }
+    }
+
+    /// Entries of the IFD and offset of the next one (0 if none)
+    fn ifd(&self, file: &mut File, offset: u64) -> Result<(Vec<TiffEntry>, u64), Error> {
+        let count = self.u16(&read_at(file, self.base + offset, 2)?) as u64;
+        let data = read_at(file, self.base + offset + 2, count * 12 + 4)?;
+        let entries = data.chunks_exact(12).map(|entry| TiffEntry {
+            tag: self.u16(&entry[0..2]),
+            kind: self.u16(&entry[2..4]),
+            count: self.u32(&entry[4..8]),
+            value: [entry[8], entry[9], entry[10], entry[11]],
+        }).collect();
+        let next = self.u32(&data[count as usize * 12..]) as u64;
+        Ok((entries, next))
+    }
+
+    fn orientation(&self, file: &mut File) -> Result<Orientation, Error> {
+        let (entries, _) = self.ifd(file, self.first_ifd)?;
+        Ok(entries.iter().find(|entry| entry.tag == 0x0112)
+            .and_then(|entry| self.single_value(entry))
+            .and_then(|value| Orientation::from_exif(value as u8))
+            .unwrap_or(Orientation::NoTransforms))
+    }
+
+    /// Offset (in the file) and length of every image that may be a jpeg, in the IFD chain and the sub-IFDs, and the
+    /// orientation of the main image.
+    fn previews(&self, file: &mut File) -> Result<(Vec<(u64, u64)>, Orientation), Error> {
+        let mut candidates = vec![];
+        let mut visited = HashSet::new();
+        let mut pending = vec![self.first_ifd];
+        while let Some(offset) = pending.pop() {
+            if offset == 0 || visited.len() >= MAX_IFDS || !visited.insert(offset) {
+                continue;
+            }
+            let (entries, next) = match self.ifd(file, offset) {
+                Ok(ifd) => { ifd }
+                Err(_) => { continue }
+            };
+            pending.push(next);
+
+            let value = |tag: u16| entries.iter().find(|entry| entry.tag == tag).and_then(|entry| self.single_value(entry));
+            // JPEGInterchangeFormat / JPEGInterchangeFormatLength
+            if let (Some(offset), Some(length)) = (value(0x0201), value(0x0202)) {
+                candidates.push((self.base + offset, length));
+            }
+            // Single strip images (full size jpeg of CR2, previews of DNG)
+            if let (Some(offset), Some(length)) = (value(0x0111), value(0x0117)) {
+                candidates.push((self.base + offset, length));
+            }
+            for entry in &entries {
+                match entry.tag {
+                    // SubIFDs
+                    0x014A if matches!(entry.kind, 4 | 13) => {
+                        if entry.count == 1 {
+                            pending.push(self.u32(&entry.value) as u64);
+                        } else if entry.count <= MAX_IFDS as u32 {
+                            let offsets = read_at(file, self.base + self.u32(&entry.value) as u64, entry.count as u64 * 4)?;
+                            pending.extend(offsets.chunks_exact(4).map(|offset| self.u32(offset) as u64));
+                        }
+                    }
+                    // JpgFromRaw of panasonic files
+                    0x002E if entry.kind == 7 && entry.count > 4 => {
+                        candidates.push((self.base + self.u32(&entry.value) as u64, entry.count as u64));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok((candidates, self.orientation(file)?))
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+    let file_size = file.metadata()?.len();
+    if offset.checked_add(length).map(|end| end > file_size).unwrap_or(true) {
+        return Err(Error::msg("Invalid offset in raw file"));
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0u8; length as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawImage, TiffReader};
+    use image::metadata::Orientation;
+    use std::fs;
+    use std::fs::File;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    fn file_with(data: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("fileshare_raw_{}_{}", std::process::id(), FILE_COUNTER.fetch_add(1, Ordering::SeqCst)));
+        fs::write(&path, data).unwrap();
+        let file = File::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn iso_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = (8 + content.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(content);
+        data
+    }
+
+    #[test]
+    fn iso_boxes() {
+        let mut data = iso_box(b"ftyp", b"crx ");
+        data.extend(iso_box(b"moov", &iso_box(b"trak", &[])));
+        // Large size box
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&20u64.to_be_bytes());
+        data.extend_from_slice(&[0xAB; 4]);
+        // Box extending to the end of the range
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&[0; 3]);
+        let size = data.len() as u64;
+        let mut file = file_with(&data);
+
+        let boxes = RawImage::iso_boxes(&mut file, 0, size).unwrap();
+        assert_eq!(boxes, vec![(*b"ftyp", 8, 4), (*b"moov", 20, 8), (*b"mdat", 44, 4), (*b"free", 56, 3)]);
+        assert_eq!(RawImage::iso_boxes(&mut file, 20, 8).unwrap(), vec![(*b"trak", 28, 0)]);
+        // Truncated range
+        assert_eq!(RawImage::iso_boxes(&mut file, 0, 27).unwrap(), vec![(*b"ftyp", 8, 4)]);
+    }
+
+    #[test]
+    fn iso_boxes_with_corrupted_sizes() {
+        // Large size wrapping around
+        let mut data = iso_box(b"ftyp", b"crx ");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        let size = data.len() as u64;
+        let mut file = file_with(&data);
+        assert_eq!(RawImage::iso_boxes(&mut file, 0, size).unwrap(), vec![(*b"ftyp", 8, 4)]);
+        assert_eq!(RawImage::iso_boxes(&mut file, 12, u64::MAX).unwrap(), vec![]);
+
+        // Sizes smaller than their header would not move forward
+        for (size_field, large_size) in [(0u32, None), (4, None), (1, Some(0u64)), (1, Some(8))] {
+            let mut data = size_field.to_be_bytes().to_vec();
+            data.extend_from_slice(b"free");
+            if let Some(large_size) = large_size {
+                data.extend_from_slice(&large_size.to_be_bytes());
+            }
+            let mut file = file_with(&data);
+            // A zero size extends to the end of the range, which is exactly the header here
+            let expected = if size_field == 0 { vec![(*b"free", 8, 0)] } else { vec![] };
+            assert_eq!(RawImage::iso_boxes(&mut file, 0, data.len() as u64).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn supported_jpeg() {
+        let app0 = [0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00];
+        let jpeg = |sof: u8| [&[0xFF, 0xD8][..], &app0, &[0xFF, sof, 0x00, 0x02]].concat();
+        assert!(RawImage::is_supported_jpeg(&jpeg(0xC0)));
+        assert!(RawImage::is_supported_jpeg(&jpeg(0xC2)));
+        // Lossless and arithmetic coded jpeg
+        assert!(!RawImage::is_supported_jpeg(&jpeg(0xC3)));
+        assert!(!RawImage::is_supported_jpeg(&jpeg(0xC9)));
+        // Not a jpeg, truncated or invalid segments
+        assert!(!RawImage::is_supported_jpeg(b"II*\0"));
+        assert!(!RawImage::is_supported_jpeg(&[0xFF, 0xD8]));
+        assert!(!RawImage::is_supported_jpeg(&[0xFF, 0xD8, 0xFF, 0xE0, 0xFF, 0xFF]));
+        assert!(!RawImage::is_supported_jpeg(&[0xFF, 0xD8, 0x00, 0xC0, 0x00, 0x02]));
+    }
+
+    /// Little endian IFD with the given entries (tag, type, count, value) and offset of the next IFD
+    fn ifd(entries: &[(u16, u16, u32, u32)], next: u32) -> Vec<u8> {
+        let mut data = (entries.len() as u16).to_le_bytes().to_vec();
+        for (tag, kind, count, value) in entries {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&next.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn tiff_previews() {
+        // IFD0 (offset 8) : orientation, thumbnail and a sub-IFD, then IFD1 (offset 62) pointing back to IFD0
+        let mut data = b"II*\0".to_vec();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend(ifd(&[(0x0112, 3, 1, 6), (0x0201, 4, 1, 1000), (0x0202, 4, 1, 200), (0x014A, 4, 1, 100)], 62));
+        assert_eq!(data.len(), 62);
+        data.extend(ifd(&[(0x0111, 4, 1, 2000), (0x0117, 4, 1, 300)], 8));
+        data.resize(100, 0);
+        data.extend(ifd(&[(0x0201, 4, 1, 3000), (0x0202, 4, 1, 400), (0x0201, 3, 2, 0)], 0));
+        let mut file = file_with(&data);
+
+        let tiff = TiffReader::new(&mut file, 0).unwrap().unwrap();
+        let (mut candidates, orientation) = tiff.previews(&mut file).unwrap();
+        candidates.sort();
+        assert_eq!(candidates, vec![(1000, 200), (2000, 300), (3000, 400)]);
+        assert_eq!(orientation, Orientation::Rotate90);
+    }
+
+    #[test]
+    fn tiff_previews_with_corrupted_offsets() {
+        // Big endian header, the first IFD is out of the file
+        let mut data = b"MM\0*".to_vec();
+        data.extend_from_slice(&1000u32.to_be_bytes());
+        let mut file = file_with(&data);
+        let tiff = TiffReader::new(&mut file, 0).unwrap().unwrap();
+        assert!(tiff.previews(&mut file).is_err());
+
+        // Valid IFD whose next IFD and sub-IFDs are out of the file, with an offset relative to the tiff header
+        let mut data = vec![0; 4];
+        data.extend_from_slice(b"II*\0");
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend(ifd(&[(0x0201, 4, 1, 20), (0x0202, 4, 1, 10), (0x014A, 4, 1, u32::MAX)], 5000));
+        let mut file = file_with(&data);
+        let tiff = TiffReader::new(&mut file, 4).unwrap().unwrap();
+        let (candidates, orientation) = tiff.previews(&mut file).unwrap();
+        assert_eq!(candidates, vec![(24, 10)]);
+        assert_eq!(orientation, Orientation::NoTransforms);
+
+        // Not a tiff header
+        let mut file = file_with(b"II\x2B\0\x08\0\0\0");
+        assert!(TiffReader::new(&mut file, 0).unwrap().is_none());
+        assert!(TiffReader::new(&mut file, 4).unwrap().is_none());
+    }
+
+    #[test]
+    fn raf_previews() {
+        let mut data = b"FUJIFILMCCD-RAW 0201FF383501".to_vec();
+        data.resize(84, 0);
+        data.extend_from_slice(&148u32.to_be_bytes());
+        data.extend_from_slice(&5000u32.to_be_bytes());
+        let mut file = file_with(&data);
+        assert_eq!(RawImage::raf_previews(&mut file).unwrap(), vec![(148, 5000)]);
+
+        // Truncated header
+        let mut file = file_with(&data[..90]);
+        assert!(RawImage::raf_previews(&mut file).is_err());
+    }
+}
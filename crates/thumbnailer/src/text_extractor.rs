@@ -0,0 +1,114 @@
+use crate::Thumbnail;
+use anyhow::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Maximum amount of extracted text kept for indexing (PostgreSQL tsvector are limited to 1MB)
+const MAX_CONTENT_LENGTH: usize = 256 * 1024;
+/// Xml markup is much larger than the text it contains, but a small zip entry can inflate to any size
+const MAX_XML_LENGTH: u64 = 16 * MAX_CONTENT_LENGTH as u64;
+
+/// Extract the text content of documents for full-text search
+pub struct TextExtractor {}
+
+impl TextExtractor {
+    /// Returns None if the format is not supported
+    pub fn extract(path: &Path, mimetype: &str) -> Result<Option<String>, Error> {
+        let content = match mimetype {
+            "application/pdf" => { Some(Self::pdf_text(path)?) }
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                Some(Self::office_text(path, |name| name == "word/document.xml")?)
+            }
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+                Some(Self::office_text(path, |name| name == "xl/sharedStrings.xml")?)
+            }
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+                Some(Self::office_text(path, |name| name.starts_with("ppt/slides/slide") && name.ends_with(".xml"))?)
+            }
+            mimetype if mimetype.starts_with("application/vnd.oasis.opendocument.") => {
+                Some(Self::office_text(path, |name| name == "content.xml")?)
+            }
+            mimetype if mimetype.starts_with("text/") || mimetype == "application/json" || mimetype == "application/xml" => {
+                Some(Self::plain_text(path)?)
+            }
+            _ => { None }
+        };
+        Ok(content.map(Self::sanitize))
+    }
+
+    fn plain_text(path: &Path) -> Result<String, Error> {
+        let mut data = vec![];
+        File::open(path)?.take(MAX_CONTENT_LENGTH as u64).read_to_end(&mut data)?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    }
+
+    fn pdf_text(path: &Path) -> Result<String, Error> {
+        let pdfium = Thumbnail::load_pdfium()?;
+        let document = pdfium.load_pdf_from_file(path, None)?;
+        let mut text = String::new();
+        for page in document.pages().iter() {
+            text += page.text()?.all().as_str();
+            text += "\n";
+            if text.len() > MAX_CONTENT_LENGTH {
+                break;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Office documents are zip archives containing xml files
+    fn office_text(path: &Path, is_content: impl Fn(&str) -> bool) -> Result<String, Error> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let mut text = String::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if !is_content(entry.name()) {
+                continue;
+            }
+            let mut xml = vec![];
+            (&mut entry).take(MAX_XML_LENGTH).read_to_end(&mut xml)?;
+            text += Self::strip_xml(&String::from_utf8_lossy(&xml)).as_str();
+            text += "\n";
+            if text.len() > MAX_CONTENT_LENGTH {
+                break;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Keep the text nodes of an xml document
+    fn strip_xml(xml: &str) -> String {
+        let mut text = String::new();
+        let mut in_tag = false;
+        for c in xml.chars() {
+            match c {
+                '<' => {
+                    in_tag = true;
+                    if !text.ends_with(' ') { text.push(' ') }
+                }
+                '>' => { in_tag = false }
+                c if !in_tag => { text.push(c) }
+                _ => {}
+            }
+        }
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    /// Remove characters PostgreSQL cannot store and limit the length
+    fn sanitize(mut content: String) -> String {
+        content.retain(|c| c != '\0');
+        if content.len() > MAX_CONTENT_LENGTH {
+            let mut len = MAX_CONTENT_LENGTH;
+            while !content.is_char_boundary(len) {
+                len -= 1;
+            }
+            content.truncate(len);
+        }
+        content
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Maximum number of frames of a sprite sheet
+const SPRITE_FRAMES: u32 = 100;
+/// Minimum delay (s) between two frames of a sprite sheet
+const SPRITE_MIN_INTERVAL: f32 = 1.0;
+const SPRITE_COLUMNS: u32 = 10;
+const SPRITE_TILE_WIDTH: u32 = 160;
+const SPRITE_TILE_HEIGHT: u32 = 90;
+
+/// The animated preview is made of short clips evenly spread over the video
+const ANIMATED_SEGMENTS: u32 = 6;
+const ANIMATED_SEGMENT_DURATION: f32 = 1.0;
+const ANIMATED_WIDTH: u32 = 320;
+const ANIMATED_FPS: u32 = 10;
+
+/// Seek bar and hover previews of videos
+pub struct VideoPreview {}
+
+impl VideoPreview {
+    pub fn is_supported(mimetype: &str) -> bool {
+        mimetype.starts_with("video/")
+    }
+
+    /// Duration of the video in seconds
+    pub fn duration(input_path: &Path) -> Result<f32, Error> {
+        let cmd = match Command::new("ffprobe")
+            .arg("-v")
+            .arg("error")
+            .arg("-show_entries")
+            .arg("format=duration")
+            .arg("-of")
+            .arg("default=noprint_wrappers=1:nokey=1")
+            .arg(input_path)
+            .stderr(Stdio::inherit())
+            .output() {
+            Ok(cmd) => { cmd }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support video processing because ffmpeg is not available : {}", err)))
+            }
+        };
+        Ok(f32::from_str(String::from_utf8(cmd.stdout)?.trim()).unwrap_or(0f32))
+    }
+
+    /// Generate a sprite sheet (jpeg) of frames evenly spaced over the video, and the WebVTT track describing it.
+    /// The cues of the track point to `sprite_url`, which is relative to the url of the track.
+    pub fn sprite_sheet(input_path: &Path, sprite_path: &Path, vtt_path: &Path, sprite_url: &str) -> Result<(), Error> {
+        let duration = Self::duration(input_path)?;
+        if duration <= 0f32 {
+            return Err(Error::msg("Unknown video duration"));
+        }
+        let frames = ((duration / SPRITE_MIN_INTERVAL).ceil() as u32).clamp(1, SPRITE_FRAMES);
+        let interval = duration / frames as f32;
+        let columns = frames.min(SPRITE_COLUMNS);
+        let rows = frames.div_ceil(columns);
+
+        // Only key frames are decoded : the closest one is used for each tile
+        Self::run_ffmpeg(Command::new("ffmpeg")
+            .arg("-v")
+            .arg("error")
+            .arg("-y")
+            .arg("-skip_frame")
+            .arg("nokey")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-vf")
+            .arg(format!("fps={frames}/{duration},scale={SPRITE_TILE_WIDTH}:{SPRITE_TILE_HEIGHT}:force_original_aspect_ratio=decrease,\
+                          pad={SPRITE_TILE_WIDTH}:{SPRITE_TILE_HEIGHT}:(ow-iw)/2:(oh-ih)/2,tile={columns}x{rows}"))
+            .arg("-frames:v")
+            .arg("1")
+            .arg("-c:v")
+            .arg("mjpeg")
+            .arg("-q:v")
+            .arg("4")
+            .arg("-f")
+            .arg("image2"), sprite_path)?;
+
+        let mut vtt = String::from("WEBVTT\n");
+        for frame in 0..frames {
+            let start = frame as f32 * interval;
+            let end = (start + interval).min(duration);
+            let x = (frame % columns) * SPRITE_TILE_WIDTH;
+            let y = (frame / columns) * SPRITE_TILE_HEIGHT;
+            write!(vtt, "\n{} --> {}\n{sprite_url}#xywh={x},{y},{SPRITE_TILE_WIDTH},{SPRITE_TILE_HEIGHT}\n", Self::vtt_timestamp(start), Self::vtt_timestamp(end))?;
+        }
+        Self::write_file(vtt_path, vtt.as_bytes())
+    }
+
+    /// Generate a short animated webp looping over a few clips of the video
+    pub fn animated_preview(input_path: &Path, output_path: &Path) -> Result<(), Error> {
+        let duration = Self::duration(input_path)?;
+        if duration <= 0f32 {
+            return Err(Error::msg("Unknown video duration"));
+        }
+
+        let mut cmd = Command::new("ffmpeg");
+        cmd.arg("-v").arg("error").arg("-y");
+        let segments = if duration < (ANIMATED_SEGMENTS * 2) as f32 * ANIMATED_SEGMENT_DURATION {
+            // Short videos are previewed from the beginning
+            cmd.arg("-t").arg((ANIMATED_SEGMENTS as f32 * ANIMATED_SEGMENT_DURATION).min(duration).to_string()).arg("-i").arg(input_path);
+            1
+        } else {
+            for segment in 0..ANIMATED_SEGMENTS {
+                let start = duration * (segment as f32 + 0.5) / ANIMATED_SEGMENTS as f32 - ANIMATED_SEGMENT_DURATION / 2f32;
+                cmd.arg("-ss").arg(start.max(0f32).to_string()).arg("-t").arg(ANIMATED_SEGMENT_DURATION.to_string()).arg("-i").arg(input_path);
+            }
+            ANIMATED_SEGMENTS
+        };
+        let inputs: String = (0..segments).map(|segment| format!("[{segment}:v]")).collect();
+        Self::run_ffmpeg(cmd
+            .arg("-filter_complex")
+            .arg(format!("{inputs}concat=n={segments}:v=1:a=0,fps={ANIMATED_FPS},scale={ANIMATED_WIDTH}:-1"))
+            .arg("-an")
+            .arg("-loop")
+            .arg("0")
+            .arg("-c:v")
+            .arg("libwebp_anim")
+            .arg("-quality")
+            .arg("60")
+            .arg("-f")
+            .arg("webp"), output_path)
+    }
+
+    /// Run ffmpeg with a temporary output, so that an incomplete file is never served
+    fn run_ffmpeg(cmd: &mut Command, output_path: &Path) -> Result<(), Error> {
+        let temp_path = output_path.with_extension("tmp");
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let result = match cmd.arg(&temp_path).stderr(Stdio::inherit()).output() {
+            Ok(result) => { result }
+            Err(err) => {
+                return Err(Error::msg(format!("This server doesn't support video previews because ffmpeg is not available : {}", err)))
+            }
+        };
+        if !result.status.success() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::msg(format!("ffmpeg failed to generate video preview : {}", result.status)));
+        }
+        fs::rename(temp_path, output_path)?;
+        Ok(())
+    }
+
+    fn write_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+        let temp_path = path.with_extension("tmp");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&temp_path, data)?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    fn vtt_timestamp(seconds: f32) -> String {
+        let millis = (seconds * 1000f32).round() as u64;
+        format!("{:02}:{:02}:{:02}.{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use crate::database_ids::{AlbumId, DatabaseIdTrait, ItemId, RepositoryId, UserId};
+use crate::enc_string::EncString;
+
+#[cfg(feature = "tokio-postgres")]
+use postgres_from_row::FromRow;
+
+/// User-defined collection of files of a repository. Albums reference items, they don't contain them : the same
+/// file can be part of several albums.
+#[cfg_attr(feature = "tokio-postgres", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Album {
+    id: AlbumId,
+    pub repository: RepositoryId,
+    pub owner: UserId,
+    pub name: EncString,
+    pub description: Option<EncString>,
+    /// Cover image chosen by the user, or the first item of the album
+    pub cover: Option<ItemId>,
+    pub num_items: i64,
+    pub created_at: i64,
+}
+
+impl Album {
+    pub fn new(repository: RepositoryId, owner: UserId, name: EncString, description: Option<EncString>) -> Self {
+        Self {
+            repository,
+            owner,
+            name,
+            description,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_id(&mut self, id: AlbumId) -> Result<(), anyhow::Error> {
+        if self.id.is_valid() {
+            Err(anyhow::Error::msg("Cannot override a valid id"))
+        } else {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    pub fn id(&self) -> &AlbumId {
+        &self.id
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+use crate::database_ids::{ConversionId, DatabaseIdTrait, ItemId, ObjectId, UserId};
+
+#[cfg(feature = "tokio-postgres")]
+use postgres_from_row::FromRow;
+#[cfg(feature = "tokio-postgres")]
+use postgres_types::private::BytesMut;
+#[cfg(feature = "tokio-postgres")]
+use postgres_types::{to_sql_checked, IsNull, Type};
+
+/// Codec (videos) or format (images) a file is converted to
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ConversionTarget {
+    /// AV1 video and Opus audio in a mp4 container
+    #[default]
+    Av1,
+    Webp,
+    Jpeg,
+    Avif,
+}
+
+impl ConversionTarget {
+    /// Mimetype of the converted file
+    pub fn mimetype(&self) -> &'static str {
+        match self {
+            ConversionTarget::Av1 => { "video/mp4" }
+            ConversionTarget::Webp => { "image/webp" }
+            ConversionTarget::Jpeg => { "image/jpeg" }
+            ConversionTarget::Avif => { "image/avif" }
+        }
+    }
+
+    /// Extension of the converted file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConversionTarget::Av1 => { "mp4" }
+            ConversionTarget::Webp => { "webp" }
+            ConversionTarget::Jpeg => { "jpg" }
+            ConversionTarget::Avif => { "avif" }
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        *self == ConversionTarget::Av1
+    }
+
+    /// Can a file of this mimetype be converted to this target
+    pub fn accepts(&self, mimetype: &str) -> bool {
+        if self.is_video() {
+            mimetype.starts_with("video/")
+        } else {
+            mimetype.starts_with("image/")
+        }
+    }
+}
+
+impl From<String> for ConversionTarget {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "av1" => { ConversionTarget::Av1 }
+            "webp" => { ConversionTarget::Webp }
+            "jpeg" => { ConversionTarget::Jpeg }
+            "avif" => { ConversionTarget::Avif }
+            _ => { ConversionTarget::Av1 }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for ConversionTarget {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_target" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for ConversionTarget {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            ConversionTarget::Av1 => { "av1".to_sql(ty, out) }
+            ConversionTarget::Webp => { "webp".to_sql(ty, out) }
+            ConversionTarget::Jpeg => { "jpeg".to_sql(ty, out) }
+            ConversionTarget::Avif => { "avif".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_target" }
+    to_sql_checked!();
+}
+
+/// Quality preset : lower qualities produce smaller files
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ConversionQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl From<String> for ConversionQuality {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "low" => { ConversionQuality::Low }
+            "medium" => { ConversionQuality::Medium }
+            "high" => { ConversionQuality::High }
+            _ => { ConversionQuality::Medium }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for ConversionQuality {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_quality" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for ConversionQuality {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            ConversionQuality::Low => { "low".to_sql(ty, out) }
+            ConversionQuality::Medium => { "medium".to_sql(ty, out) }
+            ConversionQuality::High => { "high".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_quality" }
+    to_sql_checked!();
+}
+
+/// What is done with the converted file once the conversion is confirmed
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ConversionOutput {
+    /// A new file is created next to the original one
+    NewItem,
+    /// The converted file replaces the original one, which is kept in the version history
+    #[default]
+    NewVersion,
+}
+
+impl From<String> for ConversionOutput {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "new_item" => { ConversionOutput::NewItem }
+            "new_version" => { ConversionOutput::NewVersion }
+            _ => { ConversionOutput::NewVersion }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for ConversionOutput {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_output" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for ConversionOutput {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            ConversionOutput::NewItem => { "new_item".to_sql(ty, out) }
+            ConversionOutput::NewVersion => { "new_version".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_output" }
+    to_sql_checked!();
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ConversionStatus {
+    #[default]
+    Pending,
+    Running,
+    /// The converted file is available and waits for the confirmation of the user
+    Done,
+    Failed,
+}
+
+impl From<String> for ConversionStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "pending" => { ConversionStatus::Pending }
+            "running" => { ConversionStatus::Running }
+            "done" => { ConversionStatus::Done }
+            "failed" => { ConversionStatus::Failed }
+            _ => { ConversionStatus::Pending }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for ConversionStatus {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_status" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for ConversionStatus {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            ConversionStatus::Pending => { "pending".to_sql(ty, out) }
+            ConversionStatus::Running => { "running".to_sql(ty, out) }
+            ConversionStatus::Done => { "done".to_sql(ty, out) }
+            ConversionStatus::Failed => { "failed".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "conversion_status" }
+    to_sql_checked!();
+}
+
+/// Conversion of a file to another codec or format. The original file is kept until the user confirms the
+/// conversion, which then creates a new item or a new version of the file.
+#[cfg_attr(feature = "tokio-postgres", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Conversion {
+    id: ConversionId,
+    pub item: ItemId,
+    pub owner: UserId,
+    /// Object of the file when the conversion was requested
+    pub source: ObjectId,
+    pub target: ConversionTarget,
+    pub quality: ConversionQuality,
+    pub output: ConversionOutput,
+    pub status: ConversionStatus,
+    /// Completion of the conversion, from 0 to 1
+    pub progress: f32,
+    /// Converted file, once done
+    pub result: Option<ObjectId>,
+    pub result_size: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl Conversion {
+    pub fn new(item: ItemId, owner: UserId, source: ObjectId, target: ConversionTarget, quality: ConversionQuality, output: ConversionOutput) -> Self {
+        Self {
+            item,
+            owner,
+            source,
+            target,
+            quality,
+            output,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_id(&mut self, id: ConversionId) -> Result<(), anyhow::Error> {
+        if self.id.is_valid() {
+            Err(anyhow::Error::msg("Cannot override a valid id"))
+        } else {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    pub fn id(&self) -> &ConversionId {
+        &self.id
+    }
+}
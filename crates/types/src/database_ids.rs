@@ -22,7 +22,7 @@ macro_rules! make_wrapped_db_type {
 #[macro_export]
 macro_rules! make_database_id {
     ($T:ident) => {
-        crate::make_wrapped_db_type!($T, DatabaseId, Default, std::fmt::Debug, Clone);
+        $crate::make_wrapped_db_type!($T, DatabaseId, Default, std::fmt::Debug, Clone);
 
         impl std::ops::Deref for $T {
             type Target = DatabaseId;
@@ -109,6 +109,10 @@ make_database_id!(ItemId);
 make_database_id!(UserId);
 make_database_id!(ObjectId);
 make_database_id!(RepositoryId);
+make_database_id!(FileVersionId);
+make_database_id!(JobId);
+make_database_id!(ConversionId);
+make_database_id!(AlbumId);
 
 #[cfg(feature = "password")]
 make_wrapped_db_type!(PasswordHash, String, Clone, Default, Debug, serde::Serialize, serde::Deserialize);
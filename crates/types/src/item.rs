@@ -29,6 +29,32 @@ pub struct DirectoryData {
     pub content_size: i64,
 }
 
+/// Metadata read from the content of photos, videos and audio files. Fields that are not available are None.
+#[cfg_attr(feature = "tokio-postgres", derive(FromRow))]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MediaMetadata {
+    /// Date (ms) the photo or video was taken
+    pub capture_date: Option<i64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Dimensions (px) of photos and videos
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Exif orientation (1 to 8)
+    pub orientation: Option<i32>,
+    /// Duration (s) of videos and audio files
+    pub duration: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub track: Option<i32>,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Item {
     id: ItemId,
@@ -41,6 +67,7 @@ pub struct Item {
     pub in_trash: bool,
     pub directory: Option<DirectoryData>,
     pub file: Option<FileData>,
+    pub metadata: Option<MediaMetadata>,
 }
 
 impl Item {
@@ -66,12 +93,13 @@ impl FromRow for Item {
             repository: row.get::<&str, RepositoryId>("repository"),
             owner: row.get::<&str, UserId>("owner"),
             name: row.get::<&str, EncString>("name"),
-            description: if let Ok(description) = row.try_get::<&str, EncString>("description") { Some(description) } else { None },
-            parent_item: if let Ok(parent_item) = row.try_get::<&str, ItemId>("parent_item") { Some(parent_item) } else { None },
+            description: row.try_get::<&str, EncString>("description").ok(),
+            parent_item: row.try_get::<&str, ItemId>("parent_item").ok(),
             absolute_path: row.get::<&str, EncPath>("absolute_path"),
             in_trash: row.get::<&str, bool>("in_trash"),
             directory: None,
             file: None,
+            metadata: None,
         };
         if let Ok(size) = row.try_get::<&str, i64>("size") {
             item.file = Some(FileData {
@@ -89,6 +117,9 @@ impl FromRow for Item {
         } else {
             panic!("Parsed item is neither a file or a directory : missing data");
         }
+        if let Ok(Some(_)) = row.try_get::<&str, Option<i64>>("extracted_at") {
+            item.metadata = MediaMetadata::try_from_row(row).ok();
+        }
         item
     }
 
@@ -98,12 +129,13 @@ impl FromRow for Item {
             repository: row.try_get::<&str, RepositoryId>("repository")?,
             owner: row.try_get::<&str, UserId>("owner")?,
             name: row.try_get::<&str, EncString>("name")?,
-            description: if let Ok(description) = row.try_get::<&str, EncString>("description") { Some(description) } else { None },
-            parent_item: if let Ok(parent_item) = row.try_get::<&str, ItemId>("parent_item") { Some(parent_item) } else { None },
+            description: row.try_get::<&str, EncString>("description").ok(),
+            parent_item: row.try_get::<&str, ItemId>("parent_item").ok(),
             absolute_path: row.try_get::<&str, EncPath>("absolute_path")?,
             in_trash: row.try_get::<&str, bool>("in_trash")?,
             directory: None,
             file: None,
+            metadata: None,
         };
 
         if let Ok(size) = row.try_get::<&str, i64>("size") {
@@ -120,6 +152,9 @@ impl FromRow for Item {
                 content_size: row.try_get::<&str, i64>("content_size")?,
             });
         }
+        if let Ok(Some(_)) = row.try_get::<&str, Option<i64>>("extracted_at") {
+            item.metadata = Some(MediaMetadata::try_from_row(row)?);
+        }
         Ok(item)
     }
 }
@@ -161,6 +196,9 @@ impl Serialize for Item {
                 }
             };
         }
+        if let Some(metadata) = &self.metadata {
+            state.serialize_field("metadata", metadata)?;
+        }
         state.end()
     }
 }
@@ -183,9 +221,11 @@ impl<'de> Deserialize<'de> for Item {
             where
                 A: MapAccess<'de>,
             {
-                let mut item = Item::default();
-                item.file = Some(FileData::default());
-                item.directory = Some(DirectoryData::default());
+                let mut item = Item {
+                    file: Some(FileData::default()),
+                    directory: Some(DirectoryData::default()),
+                    ..Default::default()
+                };
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -210,13 +250,14 @@ impl<'de> Deserialize<'de> for Item {
                         "timestamp" => { if let Some(file) = &mut item.file { file.timestamp = map.next_value()? } }
                         "mimetype" => { if let Some(file) = &mut item.file { file.mimetype = map.next_value()? } }
                         "size" => { if let Some(file) = &mut item.file { file.size = map.next_value()? } }
+                        "metadata" => { item.metadata = map.next_value()? }
                         _ => {}
                     }
                 }
                 Ok(item)
             }
         }
-        const FIELDS: &[&str] = &["id", "repository", "owner", "name", "description", "parent_item", "absolute_path", "in_trash", "open_upload", "content_size", "num_items", "is_regular_file", "timestamp", "mimetype", "size"];
+        const FIELDS: &[&str] = &["id", "repository", "owner", "name", "description", "parent_item", "absolute_path", "in_trash", "open_upload", "content_size", "num_items", "is_regular_file", "timestamp", "mimetype", "size", "metadata"];
         deserializer.deserialize_struct("Item", FIELDS, ItemVisitor)
     }
 }
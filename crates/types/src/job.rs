@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use crate::database_ids::{ConversionId, DatabaseIdTrait, JobId, ObjectId};
+use crate::enc_string::EncString;
+
+#[cfg(feature = "tokio-postgres")]
+use postgres_from_row::FromRow;
+#[cfg(feature = "tokio-postgres")]
+use postgres_types::private::BytesMut;
+#[cfg(feature = "tokio-postgres")]
+use postgres_types::{to_sql_checked, IsNull, Type};
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum JobKind {
+    #[default]
+    Thumbnail,
+    /// Sprite sheet and WebVTT track for the seek bar of videos
+    VideoSprites,
+    /// Animated webp displayed when hovering videos
+    VideoPreview,
+    /// HLS renditions of videos for adaptive streaming
+    Hls,
+    /// Conversion of a file requested by a user
+    Conversion,
+    /// Jpeg rendition of raw photos displayed by the viewer
+    Rendition,
+}
+
+impl From<String> for JobKind {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "thumbnail" => { JobKind::Thumbnail }
+            "video_sprites" => { JobKind::VideoSprites }
+            "video_preview" => { JobKind::VideoPreview }
+            "hls" => { JobKind::Hls }
+            "conversion" => { JobKind::Conversion }
+            "rendition" => { JobKind::Rendition }
+            _ => { JobKind::Thumbnail }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for JobKind {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "job_kind" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for JobKind {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            JobKind::Thumbnail => { "thumbnail".to_sql(ty, out) }
+            JobKind::VideoSprites => { "video_sprites".to_sql(ty, out) }
+            JobKind::VideoPreview => { "video_preview".to_sql(ty, out) }
+            JobKind::Hls => { "hls".to_sql(ty, out) }
+            JobKind::Conversion => { "conversion".to_sql(ty, out) }
+            JobKind::Rendition => { "rendition".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "job_kind" }
+    to_sql_checked!();
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum JobStatus {
+    #[default]
+    Pending,
+    Running,
+    Failed,
+}
+
+impl From<String> for JobStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "pending" => { JobStatus::Pending }
+            "running" => { JobStatus::Running }
+            "failed" => { JobStatus::Failed }
+            _ => { JobStatus::Pending }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-postgres")]
+impl<'a> postgres_types::FromSql<'a> for JobStatus {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> { Ok(Self::from(String::from_sql(ty, raw)?)) }
+    fn accepts(ty: &Type) -> bool { ty.name() == "job_status" }
+}
+#[cfg(feature = "tokio-postgres")]
+impl postgres_types::ToSql for JobStatus {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self {
+            JobStatus::Pending => { "pending".to_sql(ty, out) }
+            JobStatus::Running => { "running".to_sql(ty, out) }
+            JobStatus::Failed => { "failed".to_sql(ty, out) }
+        }
+    }
+    fn accepts(ty: &Type) -> bool { ty.name() == "job_status" }
+    to_sql_checked!();
+}
+
+/// Background processing of an object (thumbnail generation...). Jobs are removed once done.
+#[cfg_attr(feature = "tokio-postgres", derive(FromRow))]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Job {
+    id: JobId,
+    pub kind: JobKind,
+    pub object: ObjectId,
+    pub mimetype: EncString,
+    /// Size (px) of the generated image for thumbnails
+    pub size: i32,
+    /// Conversion processed by the job, for conversion jobs
+    pub conversion: Option<ConversionId>,
+    pub status: JobStatus,
+    pub attempts: i32,
+    /// Timestamp (ms) before which the job should not be started, or requeued once it failed
+    pub next_attempt: i64,
+    pub last_error: Option<String>,
+}
+
+impl Job {
+    pub fn new(kind: JobKind, object: ObjectId, mimetype: EncString) -> Self {
+        Self {
+            kind,
+            object,
+            mimetype,
+            ..Default::default()
+        }
+    }
+
+    pub fn thumbnail(object: ObjectId, mimetype: EncString, size: usize) -> Self {
+        Self {
+            size: size as i32,
+            ..Self::new(JobKind::Thumbnail, object, mimetype)
+        }
+    }
+
+    pub fn conversion(object: ObjectId, mimetype: EncString, conversion: ConversionId) -> Self {
+        Self {
+            conversion: Some(conversion),
+            ..Self::new(JobKind::Conversion, object, mimetype)
+        }
+    }
+
+    pub fn set_id(&mut self, id: JobId) -> Result<(), anyhow::Error> {
+        if self.id.is_valid() {
+            Err(anyhow::Error::msg("Cannot override a valid id"))
+        } else {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    pub fn id(&self) -> &JobId {
+        &self.id
+    }
+}
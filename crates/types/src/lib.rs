@@ -1,6 +1,9 @@
+pub mod album;
+pub mod conversion;
 pub mod database_ids;
 pub mod enc_path;
 pub mod enc_string;
 pub mod item;
+pub mod job;
 pub mod repository;
 pub mod user;
\ No newline at end of file
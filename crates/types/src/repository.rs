@@ -59,6 +59,11 @@ pub struct Repository {
     pub max_file_size: Option<i64>,
     pub visitor_file_lifetime: Option<i64>,
     pub allow_visitor_upload: bool,
+    /// Number of versions kept for each file (unlimited if None)
+    pub max_file_versions: Option<i64>,
+    /// Videos are transcoded to HLS renditions for adaptive streaming
+    #[serde(default)]
+    pub hls_streaming: bool,
 }
 
 impl Repository {
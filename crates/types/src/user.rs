@@ -133,8 +133,7 @@ impl<'de> Deserialize<'de> for User {
             where
                 A: MapAccess<'de>,
             {
-                let mut user = User::default();
-                user.allow_contact = false;
+                let mut user = User { allow_contact: false, ..Default::default() };
                 while let Some(key) = map.next_key()? {
                     match key {
                         "id" => { user.id = map.next_value()? }
@@ -7,11 +7,22 @@ use std::path::PathBuf;
 pub struct PostgresConfig {
     pub username: String,
     pub secret: String,
+    /// Host name, IP address or directory containing the Unix socket (absolute path)
     pub url: String,
     pub port: u16,
     pub database: String,
     pub ssl_mode: bool,
-    pub scheme_name: String
+    pub scheme_name: String,
+    /// Maximum number of simultaneous connections to the database
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// PEM certificate of the authority used to verify the server when ssl_mode is set (system roots if None)
+    #[serde(default)]
+    pub ssl_root_certificate: Option<PathBuf>,
+}
+
+fn default_max_connections() -> usize {
+    16
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -39,11 +50,39 @@ pub struct TlsConfig {
 pub struct BackendConfig {
     pub file_storage_path: PathBuf,
     pub thumbnail_storage_path: PathBuf,
+    #[serde(default = "default_upload_storage_path")]
+    pub upload_storage_path: PathBuf,
+    /// Default thumbnail size (px), generated for each uploaded file
     pub thumbnail_size: usize,
+    /// Larger sizes (px) that can be requested for previews, generated on demand
+    #[serde(default = "default_thumbnail_sizes")]
+    pub thumbnail_sizes: Vec<usize>,
     pub max_parallel_task: usize,
     pub postgres: PostgresConfig,
 }
 
+fn default_upload_storage_path() -> PathBuf {
+    PathBuf::from("data").join("uploads")
+}
+
+fn default_thumbnail_sizes() -> Vec<usize> {
+    vec![256, 1024, 2048]
+}
+
+impl BackendConfig {
+    /// Smallest available thumbnail size containing the requested size (the largest one if none does)
+    pub fn thumbnail_preset(&self, requested: Option<usize>) -> usize {
+        let requested = match requested {
+            None => { return self.thumbnail_size }
+            Some(requested) => { requested }
+        };
+        let mut presets = self.thumbnail_sizes.clone();
+        presets.push(self.thumbnail_size);
+        presets.sort();
+        presets.iter().find(|size| **size >= requested).or(presets.last()).cloned().unwrap_or(self.thumbnail_size)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub addresses: Vec<String>,
@@ -62,7 +101,9 @@ impl Default for Config {
             backend_config: BackendConfig {
                 file_storage_path: PathBuf::from("data").join("files"),
                 thumbnail_storage_path: PathBuf::from("data").join("thumbnails"),
+                upload_storage_path: default_upload_storage_path(),
                 thumbnail_size: 100,
+                thumbnail_sizes: default_thumbnail_sizes(),
                 max_parallel_task: 0,
                 postgres: PostgresConfig {
                     username: "postgres".to_string(),
@@ -71,7 +112,9 @@ impl Default for Config {
                     port: 5432,
                     database: "postgres".to_string(),
                     ssl_mode: false,
-                    scheme_name: "fileshare_v3".to_string()
+                    scheme_name: "fileshare_v3".to_string(),
+                    max_connections: default_max_connections(),
+                    ssl_root_certificate: None,
                 },
             },
             server_mail_server: ServiceEmailConfig {
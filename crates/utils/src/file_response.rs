@@ -0,0 +1,366 @@
+use crate::server_error::ServerError;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+/// Above this number of requested ranges, the whole file is sent instead
+const MAX_RANGES: usize = 32;
+
+/// Serve a file from disk with support for range requests (RFC 9110 §14) and conditional requests
+/// (`If-None-Match`, `If-Modified-Since`, `If-Range`).
+pub struct FileResponse {
+    path: PathBuf,
+    mimetype: String,
+    etag: Option<String>,
+    last_modified: Option<SystemTime>,
+    disposition: Option<String>,
+}
+
+impl FileResponse {
+    pub fn new(path: PathBuf, mimetype: &str) -> Self {
+        Self {
+            path,
+            mimetype: mimetype.to_string(),
+            etag: None,
+            last_modified: None,
+            disposition: None,
+        }
+    }
+
+    /// Strong entity tag (without quotes)
+    pub fn etag(mut self, etag: &str) -> Self {
+        self.etag = Some(format!("\"{etag}\""));
+        self
+    }
+
+    pub fn last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    /// Last modification date expressed in milliseconds since UNIX epoch
+    pub fn last_modified_ms(self, timestamp: i64) -> Self {
+        self.last_modified(UNIX_EPOCH + Duration::from_millis(timestamp.max(0) as u64))
+    }
+
+    pub fn disposition(mut self, disposition: String) -> Self {
+        self.disposition = Some(disposition);
+        self
+    }
+
+    pub async fn respond(mut self, method: &Method, headers: &HeaderMap) -> Result<Response, ServerError> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        let metadata = file.metadata().await?;
+        let size = metadata.len();
+        if self.last_modified.is_none() {
+            self.last_modified = metadata.modified().ok();
+        }
+        if self.etag.is_none() {
+            // Fallback to a weak validator built from the file metadata
+            if let Some(modified) = self.last_modified {
+                self.etag = Some(format!("W/\"{:x}-{:x}\"", size, modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()));
+            }
+        }
+
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(etag) = &self.etag {
+            response_headers.insert(header::ETAG, HeaderValue::from_str(etag)?);
+        }
+        if let Some(last_modified) = self.last_modified {
+            response_headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))?);
+        }
+        if let Some(disposition) = &self.disposition {
+            response_headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_str(disposition)?);
+        }
+
+        if self.is_not_modified(headers) {
+            return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+        }
+
+        let ranges = match headers.get(header::RANGE) {
+            Some(range) if self.if_range_matches(headers) => {
+                match parse_ranges(range.to_str().unwrap_or_default(), size) {
+                    RangeRequest::Ignored => { None }
+                    RangeRequest::Unsatisfiable => {
+                        response_headers.insert(header::CONTENT_RANGE, HeaderValue::from_str(&format!("bytes */{size}"))?);
+                        return Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response());
+                    }
+                    RangeRequest::Ranges(ranges) => { Some(ranges) }
+                }
+            }
+            _ => { None }
+        };
+
+        let is_head = method == Method::HEAD;
+        match ranges {
+            None => {
+                response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(&self.mimetype)?);
+                response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+                let body = if is_head { Body::empty() } else { Body::from_stream(ReaderStream::new(file)) };
+                Ok((StatusCode::OK, response_headers, body).into_response())
+            }
+            Some(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(&self.mimetype)?);
+                response_headers.insert(header::CONTENT_RANGE, HeaderValue::from_str(&format!("bytes {start}-{end}/{size}"))?);
+                response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+                let body = if is_head {
+                    Body::empty()
+                } else {
+                    let mut file = file;
+                    file.seek(SeekFrom::Start(start)).await?;
+                    Body::from_stream(ReaderStream::new(file.take(end - start + 1)))
+                };
+                Ok((StatusCode::PARTIAL_CONTENT, response_headers, body).into_response())
+            }
+            Some(ranges) => {
+                let boundary = format!("{:016x}", rand::random::<u64>());
+                let part_headers: Vec<String> = ranges.iter().map(|(start, end)| {
+                    format!("\r\n--{boundary}\r\nContent-Type: {}\r\nContent-Range: bytes {start}-{end}/{size}\r\n\r\n", self.mimetype)
+                }).collect();
+                let closing = format!("\r\n--{boundary}--\r\n");
+                let length = part_headers.iter().map(|part| part.len() as u64).sum::<u64>()
+                    + ranges.iter().map(|(start, end)| end - start + 1).sum::<u64>()
+                    + closing.len() as u64;
+
+                response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}"))?);
+                response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+                if is_head {
+                    return Ok((StatusCode::PARTIAL_CONTENT, response_headers, Body::empty()).into_response());
+                }
+
+                let (mut w, r) = tokio::io::duplex(65536);
+                tokio::spawn(async move {
+                    let mut file = file;
+                    for (part_header, (start, end)) in part_headers.iter().zip(ranges) {
+                        w.write_all(part_header.as_bytes()).await?;
+                        file.seek(SeekFrom::Start(start)).await?;
+                        tokio::io::copy(&mut (&mut file).take(end - start + 1), &mut w).await?;
+                    }
+                    w.write_all(closing.as_bytes()).await?;
+                    w.flush().await
+                });
+                Ok((StatusCode::PARTIAL_CONTENT, response_headers, Body::from_stream(ReaderStream::new(r))).into_response())
+            }
+        }
+    }
+
+    /// Evaluate `If-None-Match`, or `If-Modified-Since` when no entity tag condition is given
+    fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+            return match &self.etag {
+                None => { false }
+                Some(etag) => {
+                    if_none_match.split(',').map(|tag| tag.trim()).any(|tag| tag == "*" || weak_compare(tag, etag))
+                }
+            };
+        }
+        if let (Some(if_modified_since), Some(last_modified)) = (headers.get(header::IF_MODIFIED_SINCE).and_then(|value| value.to_str().ok()), self.last_modified) {
+            if let Ok(if_modified_since) = httpdate::parse_http_date(if_modified_since) {
+                return truncate_to_secs(last_modified) <= if_modified_since;
+            }
+        }
+        false
+    }
+
+    /// A range request is only honored if the `If-Range` validator still matches the representation
+    fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let if_range = match headers.get(header::IF_RANGE).and_then(|value| value.to_str().ok()) {
+            None => { return true }
+            Some(if_range) => { if_range.trim() }
+        };
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            // If-Range requires a strong comparison
+            match &self.etag {
+                Some(etag) => { !etag.starts_with("W/") && etag == if_range }
+                None => { false }
+            }
+        } else {
+            match (httpdate::parse_http_date(if_range), self.last_modified) {
+                (Ok(date), Some(last_modified)) => { truncate_to_secs(last_modified) == date }
+                _ => { false }
+            }
+        }
+    }
+}
+
+fn weak_compare(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    /// Malformed or unsupported header : serve the full content
+    Ignored,
+    Unsatisfiable,
+    /// Inclusive byte ranges
+    Ranges(Vec<(u64, u64)>),
+}
+
+fn parse_ranges(header: &str, size: u64) -> RangeRequest {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        None => { return RangeRequest::Ignored }
+        Some(specs) => { specs }
+    };
+    let mut ranges = vec![];
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let (start, end) = match spec.split_once('-') {
+            None => { return RangeRequest::Ignored }
+            Some(range) => { range }
+        };
+        let range = if start.is_empty() {
+            // Suffix range : last N bytes
+            match end.parse::<u64>() {
+                Ok(0) => { None }
+                Ok(suffix) => { if size == 0 { None } else { Some((size.saturating_sub(suffix), size - 1)) } }
+                Err(_) => { return RangeRequest::Ignored }
+            }
+        } else {
+            let start = match start.parse::<u64>() {
+                Ok(start) => { start }
+                Err(_) => { return RangeRequest::Ignored }
+            };
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => { end }
+                    _ => { return RangeRequest::Ignored }
+                }
+            };
+            if start >= size { None } else { Some((start, end.min(size - 1))) }
+        };
+        if let Some(range) = range {
+            ranges.push(range);
+        }
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Ignored;
+    }
+    RangeRequest::Ranges(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ranges, FileResponse, RangeRequest, MAX_RANGES};
+    use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn headers(name: HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn weak_response() -> FileResponse {
+        let mut response = FileResponse::new(PathBuf::new(), "text/plain");
+        response.etag = Some("W/\"abc\"".to_string());
+        response
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_ranges("bytes=-500", 1000), RangeRequest::Ranges(vec![(500, 999)]));
+        // A suffix longer than the file selects the whole file
+        assert_eq!(parse_ranges("bytes=-500", 100), RangeRequest::Ranges(vec![(0, 99)]));
+        assert_eq!(parse_ranges("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-500", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(parse_ranges("bytes=500-", 1000), RangeRequest::Ranges(vec![(500, 999)]));
+        assert_eq!(parse_ranges("bytes=0-", 1), RangeRequest::Ranges(vec![(0, 0)]));
+        assert_eq!(parse_ranges("bytes=900-2000", 1000), RangeRequest::Ranges(vec![(900, 999)]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_ranges("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=1000-1200", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=0-10", 0), RangeRequest::Unsatisfiable);
+        // Satisfiable ranges are served even if others are not
+        assert_eq!(parse_ranges("bytes=0-9, 2000-3000", 1000), RangeRequest::Ranges(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn invalid_ranges_are_ignored() {
+        assert_eq!(parse_ranges("bytes=500-100", 1000), RangeRequest::Ignored);
+        assert_eq!(parse_ranges("items=0-10", 1000), RangeRequest::Ignored);
+        assert_eq!(parse_ranges("bytes=a-b", 1000), RangeRequest::Ignored);
+        assert_eq!(parse_ranges("bytes=10", 1000), RangeRequest::Ignored);
+    }
+
+    #[test]
+    fn too_many_ranges() {
+        let ranges = |count: usize| format!("bytes={}", (0..count).map(|i| format!("{}-{}", i * 10, i * 10 + 4)).collect::<Vec<_>>().join(","));
+        match parse_ranges(&ranges(MAX_RANGES), 1000) {
+            RangeRequest::Ranges(ranges) => { assert_eq!(ranges.len(), MAX_RANGES) }
+            other => { panic!("Unexpected result : {other:?}") }
+        }
+        assert_eq!(parse_ranges(&ranges(MAX_RANGES + 1), 1000), RangeRequest::Ignored);
+    }
+
+    #[test]
+    fn if_range_requires_a_strong_etag() {
+        let response = FileResponse::new(PathBuf::new(), "text/plain").etag("abc");
+        assert!(response.if_range_matches(&HeaderMap::new()));
+        assert!(response.if_range_matches(&headers(header::IF_RANGE, "\"abc\"")));
+        assert!(!response.if_range_matches(&headers(header::IF_RANGE, "\"def\"")));
+        assert!(!response.if_range_matches(&headers(header::IF_RANGE, "W/\"abc\"")));
+
+        // A weak validator never matches, even against itself
+        let response = weak_response();
+        assert!(!response.if_range_matches(&headers(header::IF_RANGE, "W/\"abc\"")));
+        assert!(!response.if_range_matches(&headers(header::IF_RANGE, "\"abc\"")));
+    }
+
+    #[test]
+    fn if_range_with_a_date() {
+        let response = FileResponse::new(PathBuf::new(), "text/plain").last_modified(UNIX_EPOCH + Duration::from_millis(1_000_000_500));
+        assert!(response.if_range_matches(&headers(header::IF_RANGE, &httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(1_000_000)))));
+        assert!(!response.if_range_matches(&headers(header::IF_RANGE, &httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(999_999)))));
+    }
+
+    #[test]
+    fn if_none_match() {
+        let response = FileResponse::new(PathBuf::new(), "text/plain").etag("abc");
+        assert!(response.is_not_modified(&headers(header::IF_NONE_MATCH, "*")));
+        assert!(response.is_not_modified(&headers(header::IF_NONE_MATCH, "\"abc\"")));
+        assert!(response.is_not_modified(&headers(header::IF_NONE_MATCH, "\"def\", \"abc\"")));
+        assert!(!response.is_not_modified(&headers(header::IF_NONE_MATCH, "\"def\", \"ghi\"")));
+        // If-None-Match uses the weak comparison
+        assert!(response.is_not_modified(&headers(header::IF_NONE_MATCH, "W/\"abc\"")));
+        assert!(weak_response().is_not_modified(&headers(header::IF_NONE_MATCH, "\"def\",\"abc\"")));
+        assert!(!FileResponse::new(PathBuf::new(), "text/plain").is_not_modified(&headers(header::IF_NONE_MATCH, "*")));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let response = FileResponse::new(PathBuf::new(), "text/plain").etag("abc").last_modified(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let mut request = headers(header::IF_NONE_MATCH, "\"def\"");
+        request.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_str(&httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(2_000_000))).unwrap());
+        assert!(!response.is_not_modified(&request));
+        request.remove(header::IF_NONE_MATCH);
+        assert!(response.is_not_modified(&request));
+        assert!(!response.is_not_modified(&headers(header::IF_MODIFIED_SINCE, &httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(999_999)))));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod server_error;
 pub mod config;
+pub mod file_response;
 
 #[macro_export]
 macro_rules! make_wrapped_db_type {
@@ -2,22 +2,26 @@ CREATE OR REPLACE FUNCTION SCHEMA_NAME.remove_item(item BIGINT) RETURNS BIGINT[]
 	DECLARE
 		removed_objects BIGINT[] := '{}';
 		entry RECORD;
-		removed_file BIGINT;
-		removed_object BIGINT;
+		used_objects BIGINT[];
+		used_object BIGINT;
 	BEGIN
 	    FOR entry IN SELECT id FROM SCHEMA_NAME.items WHERE parent_item = item LOOP
 			removed_objects := removed_objects || SCHEMA_NAME.remove_item(entry.id);
 		END LOOP;
 
-		SELECT object INTO removed_file FROM SCHEMA_NAME.files WHERE id = item;
+		-- Objects of the file and of its previous versions
+		used_objects := ARRAY(
+			SELECT object FROM SCHEMA_NAME.files WHERE id = item AND object IS NOT NULL
+			UNION SELECT object FROM SCHEMA_NAME.file_versions WHERE file_versions.item = remove_item.item);
 		DELETE FROM SCHEMA_NAME.items WHERE id = item;
 
-		IF removed_file IS NOT NULL THEN
-			SELECT id INTO entry FROM SCHEMA_NAME.files WHERE object = removed_file;
-			IF entry IS NULL THEN
-				removed_objects := ARRAY_APPEND(removed_objects, removed_file);
+		-- Objects can be shared between files : only remove the ones that are not referenced anymore
+		FOREACH used_object IN ARRAY used_objects LOOP
+			IF NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.files WHERE object = used_object) AND
+			   NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.file_versions WHERE object = used_object) THEN
+				removed_objects := ARRAY_APPEND(removed_objects, used_object);
 			END IF;
-		END IF;
+		END LOOP;
 	    RETURN removed_objects;
 	END;
 $$ LANGUAGE plpgsql;
\ No newline at end of file
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.uploads (
+        id VARCHAR(64) PRIMARY KEY,
+        owner BIGINT NOT NULL,
+        repository BIGINT NOT NULL,
+        parent_item BIGINT NULL,
+        name VARCHAR(200) NOT NULL,
+        description TEXT,
+        size BIGINT NOT NULL,
+        mimetype VARCHAR(200) NOT NULL,
+        timestamp BIGINT NOT NULL,
+        upload_offset BIGINT NOT NULL DEFAULT 0,
+        last_update BIGINT NOT NULL,
+        FOREIGN KEY(owner) REFERENCES SCHEMA_NAME.users(id),
+        FOREIGN KEY(repository) REFERENCES SCHEMA_NAME.repository(id)
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_uploads_owner_index ON SCHEMA_NAME.uploads USING hash(owner);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.item_search (
+        id BIGINT PRIMARY KEY,
+        plain_name TEXT NOT NULL DEFAULT '',
+        plain_description TEXT,
+        content TEXT,
+        search_vector tsvector GENERATED ALWAYS AS (
+            setweight(to_tsvector('simple', coalesce(plain_name, '')), 'A') ||
+            setweight(to_tsvector('simple', coalesce(plain_description, '')), 'B') ||
+            setweight(to_tsvector('simple', coalesce(content, '')), 'C')
+        ) STORED,
+        FOREIGN KEY(id) REFERENCES SCHEMA_NAME.items(id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_item_search_vector_index ON SCHEMA_NAME.item_search USING gin(search_vector);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.file_versions (
+        id BIGSERIAL PRIMARY KEY,
+        item BIGINT NOT NULL,
+        size BIGINT NOT NULL,
+        mimetype VARCHAR(200) NOT NULL,
+        timestamp BIGINT NOT NULL,
+        object BIGINT NOT NULL,
+        uploader BIGINT NULL,
+        FOREIGN KEY(item) REFERENCES SCHEMA_NAME.items(id) ON DELETE CASCADE,
+        FOREIGN KEY(object) REFERENCES SCHEMA_NAME.objects(id),
+        FOREIGN KEY(uploader) REFERENCES SCHEMA_NAME.users(id) ON DELETE SET NULL
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_file_versions_item_index ON SCHEMA_NAME.file_versions USING hash(item);
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_file_versions_object_index ON SCHEMA_NAME.file_versions USING hash(object);
+
+ALTER TABLE SCHEMA_NAME.repository ADD COLUMN IF NOT EXISTS max_file_versions BIGINT DEFAULT NULL;
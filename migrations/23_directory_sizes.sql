@@ -0,0 +1,147 @@
+-- The content of a directory is made of the files of its subtree sharing its trash state : the content of a trashed
+-- directory is still displayed in the trash, but it is not counted anymore in its parents.
+
+DROP TRIGGER IF EXISTS trig_update_directories_content_update ON SCHEMA_NAME.items;
+DROP FUNCTION IF EXISTS SCHEMA_NAME.update_directories_content_update();
+DROP PROCEDURE IF EXISTS SCHEMA_NAME.update_all_directory_sizes(BIGINT);
+
+-- RECOMPUTE : every directory of a subtree (or of the whole repository if root_item is null)
+CREATE OR REPLACE PROCEDURE SCHEMA_NAME.update_directory_sizes(repository_id BIGINT, root_item BIGINT) AS $$
+	BEGIN
+		WITH RECURSIVE subtree AS (
+			SELECT id, parent_item, in_trash FROM SCHEMA_NAME.items
+				WHERE (root_item IS NULL AND repository = repository_id AND parent_item IS NULL) OR id = root_item
+			UNION ALL
+			SELECT items.id, items.parent_item, items.in_trash FROM SCHEMA_NAME.items JOIN subtree ON items.parent_item = subtree.id
+		),
+		-- Each file with each of the ancestors it is counted in
+		file_ancestors AS (
+			SELECT files.id AS file, files.size, parent.id AS ancestor, item.in_trash FROM SCHEMA_NAME.files
+				JOIN subtree item ON item.id = files.id
+				JOIN subtree parent ON parent.id = item.parent_item AND parent.in_trash = item.in_trash
+			UNION ALL
+			SELECT file_ancestors.file, file_ancestors.size, parent.id, file_ancestors.in_trash FROM file_ancestors
+				JOIN subtree ancestor ON ancestor.id = file_ancestors.ancestor
+				JOIN subtree parent ON parent.id = ancestor.parent_item AND parent.in_trash = file_ancestors.in_trash
+		),
+		totals AS (
+			SELECT ancestor, COUNT(file) AS num_items, SUM(size) AS content_size FROM file_ancestors GROUP BY ancestor
+		)
+		UPDATE SCHEMA_NAME.directories SET num_items = COALESCE(totals.num_items, 0), content_size = COALESCE(totals.content_size, 0)
+			FROM subtree LEFT JOIN totals ON totals.ancestor = subtree.id
+			WHERE directories.id = subtree.id;
+	END;
+	$$ LANGUAGE plpgsql;
+
+-- Add a delta to a directory and to its parents sharing its trash state
+CREATE OR REPLACE PROCEDURE SCHEMA_NAME.add_delta_on_directory(item BIGINT, count_delta BIGINT, size_delta BIGINT) AS $$
+	BEGIN
+		WITH RECURSIVE ancestors AS (
+			SELECT id, parent_item, in_trash FROM SCHEMA_NAME.items WHERE id = item
+			UNION ALL
+			SELECT items.id, items.parent_item, items.in_trash FROM SCHEMA_NAME.items
+				JOIN ancestors ON items.id = ancestors.parent_item AND items.in_trash = ancestors.in_trash
+		)
+		UPDATE SCHEMA_NAME.directories SET num_items = num_items + count_delta, content_size = content_size + size_delta
+			WHERE id IN (SELECT id FROM ancestors);
+	END;
+	$$ LANGUAGE plpgsql;
+
+-- Add (factor = 1) or remove (factor = -1) the content of an item from a parent directory
+CREATE OR REPLACE PROCEDURE SCHEMA_NAME.add_item_to_directory(item BIGINT, item_in_trash BOOLEAN, parent BIGINT, factor BIGINT) AS $$
+	DECLARE
+		num_items BIGINT;
+		content_size BIGINT;
+	BEGIN
+		IF parent IS NULL OR NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.items WHERE id = parent AND in_trash = item_in_trash) THEN
+			RETURN;
+		END IF;
+		SELECT 1, size INTO num_items, content_size FROM SCHEMA_NAME.files WHERE id = item;
+		IF NOT FOUND THEN
+			SELECT directories.num_items, directories.content_size INTO num_items, content_size FROM SCHEMA_NAME.directories WHERE id = item;
+		END IF;
+		IF FOUND THEN
+			CALL SCHEMA_NAME.add_delta_on_directory(parent, factor * num_items, factor * content_size);
+		END IF;
+	END;
+	$$ LANGUAGE plpgsql;
+
+-- MOVE / TRASH / RESTORE ITEM
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_move() RETURNS TRIGGER AS $$
+	BEGIN
+		IF OLD.parent_item IS NOT DISTINCT FROM NEW.parent_item AND OLD.in_trash = NEW.in_trash THEN
+			RETURN NEW;
+		END IF;
+
+		-- The children of a trashed or restored item are updated by the trigger of their parent : they are handled with it
+		IF OLD.parent_item IS NOT DISTINCT FROM NEW.parent_item AND pg_trigger_depth() > 1 THEN
+			RETURN NEW;
+		END IF;
+
+		CALL SCHEMA_NAME.add_item_to_directory(NEW.id, OLD.in_trash, OLD.parent_item, -1);
+		IF OLD.in_trash != NEW.in_trash AND NOT NEW.is_regular_file THEN
+			CALL SCHEMA_NAME.update_directory_sizes(NEW.repository, NEW.id);
+		END IF;
+		CALL SCHEMA_NAME.add_item_to_directory(NEW.id, NEW.in_trash, NEW.parent_item, 1);
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+-- Must run after trig_ins_ensure_items_path_up_to_date, which forwards the trash state to the children
+CREATE OR REPLACE TRIGGER trig_update_directories_content_move
+	AFTER UPDATE ON SCHEMA_NAME.items
+	FOR EACH ROW EXECUTE FUNCTION SCHEMA_NAME.trig_update_directories_content_move();
+
+-- ADD FILE : after insert so that upserts of existing files are not counted twice
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_insert() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = NEW.id;
+		CALL SCHEMA_NAME.add_item_to_directory(NEW.id, data.in_trash, data.parent_item, 1);
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trig_update_directories_content_insert ON SCHEMA_NAME.files;
+CREATE TRIGGER trig_update_directories_content_insert
+	AFTER INSERT ON SCHEMA_NAME.files
+	FOR EACH ROW EXECUTE FUNCTION SCHEMA_NAME.trig_update_directories_content_insert();
+
+-- UPDATE FILE (new version)
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_resize() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = NEW.id;
+		IF EXISTS (SELECT 1 FROM SCHEMA_NAME.items WHERE id = data.parent_item AND in_trash = data.in_trash) THEN
+			CALL SCHEMA_NAME.add_delta_on_directory(data.parent_item, 0, NEW.size - OLD.size);
+		END IF;
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER trig_update_directories_content_resize
+	AFTER UPDATE OF size ON SCHEMA_NAME.files
+	FOR EACH ROW WHEN (OLD.size != NEW.size) EXECUTE FUNCTION SCHEMA_NAME.trig_update_directories_content_resize();
+
+-- REMOVE FILE : directories are always empty when removed, as their children are removed first
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_remove() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = OLD.id;
+		CALL SCHEMA_NAME.add_item_to_directory(OLD.id, data.in_trash, data.parent_item, -1);
+		RETURN OLD;
+	END;
+	$$ LANGUAGE plpgsql;
+
+DO $$
+	DECLARE
+		repository_row RECORD;
+	BEGIN
+		FOR repository_row IN SELECT id FROM SCHEMA_NAME.repository LOOP
+			CALL SCHEMA_NAME.update_directory_sizes(repository_row.id, NULL);
+		END LOOP;
+	END;
+$$;
@@ -0,0 +1,127 @@
+-- Paths of a whole subtree are regenerated in a single statement
+CREATE OR REPLACE PROCEDURE SCHEMA_NAME.regenerate_item_path_with_children(item_id BIGINT) AS $$
+	DECLARE
+		root_path VARCHAR;
+	BEGIN
+		CALL SCHEMA_NAME.regenerate_item_path(item_id);
+		SELECT absolute_path INTO root_path FROM SCHEMA_NAME.items WHERE id = item_id;
+
+		WITH RECURSIVE paths AS (
+			SELECT items.id, root_path || '/' || items.name AS path FROM SCHEMA_NAME.items WHERE parent_item = item_id
+			UNION ALL
+			SELECT items.id, paths.path || '/' || items.name FROM SCHEMA_NAME.items JOIN paths ON items.parent_item = paths.id
+		)
+		UPDATE SCHEMA_NAME.items SET absolute_path = paths.path FROM paths
+			WHERE items.id = paths.id AND items.absolute_path IS DISTINCT FROM paths.path;
+	END;
+	$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.make_item_path_up_to_date() RETURNS TRIGGER AS $$
+	DECLARE
+	BEGIN
+		IF OLD IS NULL OR NEW.parent_item != OLD.parent_item OR NEW.name != OLD.name OR
+		(NEW.parent_item IS NULL AND NOT OLD.parent_item IS NULL) OR
+		(OLD.parent_item IS NULL AND NOT NEW.parent_item IS NULL) THEN
+			CALL SCHEMA_NAME.regenerate_item_path_with_children(NEW.id);
+		END IF;
+
+		-- The whole subtree follows at once : the children updated here don't need to forward it again
+		IF NOT OLD.in_trash = NEW.in_trash AND pg_trigger_depth() = 1 THEN
+			WITH RECURSIVE subtree AS (
+				SELECT id FROM SCHEMA_NAME.items WHERE parent_item = NEW.id
+				UNION ALL
+				SELECT items.id FROM SCHEMA_NAME.items JOIN subtree ON items.parent_item = subtree.id
+			)
+			UPDATE SCHEMA_NAME.items SET in_trash = NEW.in_trash WHERE id IN (SELECT id FROM subtree) AND in_trash != NEW.in_trash;
+		END IF;
+
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+-- The whole subtree is removed in a single statement
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.remove_item(item BIGINT) RETURNS BIGINT[] AS $$
+	DECLARE
+		root RECORD;
+		removed_items BIGINT[];
+		used_objects BIGINT[];
+	BEGIN
+		SELECT * INTO root FROM SCHEMA_NAME.items WHERE id = item;
+		IF NOT FOUND THEN
+			RETURN '{}';
+		END IF;
+
+		WITH RECURSIVE subtree AS (
+			SELECT id FROM SCHEMA_NAME.items WHERE id = item
+			UNION ALL
+			SELECT items.id FROM SCHEMA_NAME.items JOIN subtree ON items.parent_item = subtree.id
+		)
+		SELECT ARRAY_AGG(id) INTO removed_items FROM subtree;
+
+		-- Objects of the files and of their previous versions
+		used_objects := ARRAY(
+			SELECT object FROM SCHEMA_NAME.files WHERE id = ANY(removed_items) AND object IS NOT NULL
+			UNION SELECT object FROM SCHEMA_NAME.file_versions WHERE file_versions.item = ANY(removed_items));
+
+		-- The parent directories are updated once for the whole subtree instead of once per removed file
+		CALL SCHEMA_NAME.add_item_to_directory(item, root.in_trash, root.parent_item, -1);
+		PERFORM set_config('SCHEMA_NAME.skip_directory_sizes', 'on', true);
+		DELETE FROM SCHEMA_NAME.items WHERE id = ANY(removed_items);
+		PERFORM set_config('SCHEMA_NAME.skip_directory_sizes', '', true);
+
+		-- Objects can be shared between files : only remove the ones that are not referenced anymore
+		RETURN ARRAY(
+			SELECT used.object FROM UNNEST(used_objects) AS used(object)
+			WHERE NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.files WHERE files.object = used.object)
+			  AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.file_versions WHERE file_versions.object = used.object));
+	END;
+$$ LANGUAGE plpgsql;
+
+-- Bulk operations can disable the update of directory sizes for each file (until the end of the transaction at most),
+-- then recompute the affected directories at once with update_directory_sizes
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.skip_directory_sizes() RETURNS BOOLEAN AS $$
+	BEGIN
+		RETURN COALESCE(current_setting('SCHEMA_NAME.skip_directory_sizes', true), '') = 'on';
+	END;
+	$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_insert() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		IF SCHEMA_NAME.skip_directory_sizes() THEN
+			RETURN NEW;
+		END IF;
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = NEW.id;
+		CALL SCHEMA_NAME.add_item_to_directory(NEW.id, data.in_trash, data.parent_item, 1);
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_resize() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		IF SCHEMA_NAME.skip_directory_sizes() THEN
+			RETURN NEW;
+		END IF;
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = NEW.id;
+		IF EXISTS (SELECT 1 FROM SCHEMA_NAME.items WHERE id = data.parent_item AND in_trash = data.in_trash) THEN
+			CALL SCHEMA_NAME.add_delta_on_directory(data.parent_item, 0, NEW.size - OLD.size);
+		END IF;
+		RETURN NEW;
+	END;
+	$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.trig_update_directories_content_remove() RETURNS TRIGGER AS $$
+	DECLARE
+		data RECORD;
+	BEGIN
+		IF SCHEMA_NAME.skip_directory_sizes() THEN
+			RETURN OLD;
+		END IF;
+		SELECT * INTO data FROM SCHEMA_NAME.items WHERE id = OLD.id;
+		CALL SCHEMA_NAME.add_item_to_directory(OLD.id, data.in_trash, data.parent_item, -1);
+		RETURN OLD;
+	END;
+	$$ LANGUAGE plpgsql;
@@ -0,0 +1,32 @@
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.job_kind AS ENUM ('thumbnail');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'job_kind already exists, skipping...';
+END
+$$;
+
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.job_status AS ENUM ('pending', 'running', 'failed');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'job_status already exists, skipping...';
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.jobs (
+        id BIGSERIAL PRIMARY KEY,
+        kind SCHEMA_NAME.job_kind NOT NULL,
+        object BIGINT NOT NULL,
+        mimetype VARCHAR(200) NOT NULL,
+        status SCHEMA_NAME.job_status NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt BIGINT NOT NULL DEFAULT 0,
+        last_error TEXT,
+        UNIQUE(kind, object),
+        FOREIGN KEY(object) REFERENCES SCHEMA_NAME.objects(id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_jobs_next_attempt_index ON SCHEMA_NAME.jobs(next_attempt) WHERE status = 'pending';
@@ -0,0 +1,7 @@
+-- Thumbnails are generated for several sizes : a job is identified by its size too
+ALTER TABLE SCHEMA_NAME.jobs ADD COLUMN IF NOT EXISTS size INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE SCHEMA_NAME.jobs DROP CONSTRAINT IF EXISTS jobs_kind_object_key;
+ALTER TABLE SCHEMA_NAME.jobs ADD CONSTRAINT jobs_kind_object_size_key UNIQUE(kind, object, size);
+
+-- The size of the queued thumbnails is unknown : they will be queued again when requested
+DELETE FROM SCHEMA_NAME.jobs WHERE kind = 'thumbnail';
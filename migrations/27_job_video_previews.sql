@@ -0,0 +1,2 @@
+ALTER TYPE SCHEMA_NAME.job_kind ADD VALUE IF NOT EXISTS 'video_sprites';
+ALTER TYPE SCHEMA_NAME.job_kind ADD VALUE IF NOT EXISTS 'video_preview';
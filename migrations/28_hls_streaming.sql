@@ -0,0 +1,3 @@
+-- Videos of repositories with hls_streaming are transcoded for adaptive streaming
+ALTER TABLE SCHEMA_NAME.repository ADD COLUMN IF NOT EXISTS hls_streaming BOOLEAN NOT NULL DEFAULT false;
+ALTER TYPE SCHEMA_NAME.job_kind ADD VALUE IF NOT EXISTS 'hls';
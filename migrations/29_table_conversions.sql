@@ -0,0 +1,104 @@
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.conversion_target AS ENUM ('av1', 'webp', 'jpeg', 'avif');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'conversion_target already exists, skipping...';
+END
+$$;
+
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.conversion_quality AS ENUM ('low', 'medium', 'high');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'conversion_quality already exists, skipping...';
+END
+$$;
+
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.conversion_output AS ENUM ('new_item', 'new_version');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'conversion_output already exists, skipping...';
+END
+$$;
+
+DO
+$$
+BEGIN
+CREATE TYPE SCHEMA_NAME.conversion_status AS ENUM ('pending', 'running', 'done', 'failed');
+EXCEPTION WHEN DUPLICATE_OBJECT THEN
+RAISE NOTICE 'conversion_status already exists, skipping...';
+END
+$$;
+
+-- The source object is referenced until the conversion is confirmed or cancelled, so it is never removed meanwhile
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.conversions (
+        id BIGSERIAL PRIMARY KEY,
+        item BIGINT NOT NULL,
+        owner BIGINT NOT NULL,
+        source BIGINT NOT NULL,
+        target SCHEMA_NAME.conversion_target NOT NULL,
+        quality SCHEMA_NAME.conversion_quality NOT NULL,
+        output SCHEMA_NAME.conversion_output NOT NULL,
+        status SCHEMA_NAME.conversion_status NOT NULL DEFAULT 'pending',
+        progress REAL NOT NULL DEFAULT 0,
+        result BIGINT NULL,
+        result_size BIGINT NULL,
+        last_error TEXT,
+        FOREIGN KEY(item) REFERENCES SCHEMA_NAME.items(id) ON DELETE CASCADE,
+        FOREIGN KEY(owner) REFERENCES SCHEMA_NAME.users(id) ON DELETE CASCADE,
+        FOREIGN KEY(source) REFERENCES SCHEMA_NAME.objects(id),
+        FOREIGN KEY(result) REFERENCES SCHEMA_NAME.objects(id)
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_conversions_item_index ON SCHEMA_NAME.conversions USING hash(item);
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_conversions_owner_index ON SCHEMA_NAME.conversions USING hash(owner);
+
+-- Conversion jobs are identified by their conversion : the same object can be converted several times
+ALTER TYPE SCHEMA_NAME.job_kind ADD VALUE IF NOT EXISTS 'conversion';
+ALTER TABLE SCHEMA_NAME.jobs ADD COLUMN IF NOT EXISTS conversion BIGINT NULL REFERENCES SCHEMA_NAME.conversions(id) ON DELETE CASCADE;
+ALTER TABLE SCHEMA_NAME.jobs DROP CONSTRAINT IF EXISTS jobs_kind_object_size_key;
+CREATE UNIQUE INDEX IF NOT EXISTS SCHEMA_NAME_jobs_unique_index ON SCHEMA_NAME.jobs(kind, object, size, COALESCE(conversion, 0));
+
+-- The results of the conversions of a removed item are removed with it, and objects used by conversions are kept
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.remove_item(item BIGINT) RETURNS BIGINT[] AS $$
+	DECLARE
+		root RECORD;
+		removed_items BIGINT[];
+		used_objects BIGINT[];
+	BEGIN
+		SELECT * INTO root FROM SCHEMA_NAME.items WHERE id = item;
+		IF NOT FOUND THEN
+			RETURN '{}';
+		END IF;
+
+		WITH RECURSIVE subtree AS (
+			SELECT id FROM SCHEMA_NAME.items WHERE id = item
+			UNION ALL
+			SELECT items.id FROM SCHEMA_NAME.items JOIN subtree ON items.parent_item = subtree.id
+		)
+		SELECT ARRAY_AGG(id) INTO removed_items FROM subtree;
+
+		-- Objects of the files, of their previous versions and of their pending conversions
+		used_objects := ARRAY(
+			SELECT object FROM SCHEMA_NAME.files WHERE id = ANY(removed_items) AND object IS NOT NULL
+			UNION SELECT object FROM SCHEMA_NAME.file_versions WHERE file_versions.item = ANY(removed_items)
+			UNION SELECT result FROM SCHEMA_NAME.conversions WHERE conversions.item = ANY(removed_items) AND result IS NOT NULL);
+
+		-- The parent directories are updated once for the whole subtree instead of once per removed file
+		CALL SCHEMA_NAME.add_item_to_directory(item, root.in_trash, root.parent_item, -1);
+		PERFORM set_config('SCHEMA_NAME.skip_directory_sizes', 'on', true);
+		DELETE FROM SCHEMA_NAME.items WHERE id = ANY(removed_items);
+		PERFORM set_config('SCHEMA_NAME.skip_directory_sizes', '', true);
+
+		-- Objects can be shared between files : only remove the ones that are not referenced anymore
+		RETURN ARRAY(
+			SELECT used.object FROM UNNEST(used_objects) AS used(object)
+			WHERE NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.files WHERE files.object = used.object)
+			  AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.file_versions WHERE file_versions.object = used.object)
+			  AND NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.conversions WHERE conversions.source = used.object OR conversions.result = used.object));
+	END;
+$$ LANGUAGE plpgsql;
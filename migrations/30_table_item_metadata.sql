@@ -0,0 +1,81 @@
+-- Metadata read from the content of files (exif of photos, duration of videos, tags of audio files...)
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.item_metadata (
+        id BIGINT PRIMARY KEY,
+        extracted_at BIGINT NOT NULL,
+        capture_date BIGINT NULL,
+        camera_make VARCHAR NULL,
+        camera_model VARCHAR NULL,
+        lens VARCHAR NULL,
+        latitude DOUBLE PRECISION NULL,
+        longitude DOUBLE PRECISION NULL,
+        width INTEGER NULL,
+        height INTEGER NULL,
+        orientation INTEGER NULL,
+        duration DOUBLE PRECISION NULL,
+        video_codec VARCHAR NULL,
+        audio_codec VARCHAR NULL,
+        artist VARCHAR NULL,
+        album VARCHAR NULL,
+        title VARCHAR NULL,
+        track INTEGER NULL,
+        FOREIGN KEY(id) REFERENCES SCHEMA_NAME.items(id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_item_metadata_capture_date_index ON SCHEMA_NAME.item_metadata(capture_date);
+
+-- The metadata columns are appended to the items : they are NULL for items without metadata
+CREATE OR REPLACE VIEW SCHEMA_NAME.item_full_view AS
+	SELECT * FROM SCHEMA_NAME.items
+	LEFT JOIN SCHEMA_NAME.directories USING(id)
+	LEFT JOIN SCHEMA_NAME.files USING(id)
+	LEFT JOIN SCHEMA_NAME.item_metadata USING(id);
+
+-- Copies also get the metadata of the source files, so that they are part of the timeline and of the metadata search
+CREATE OR REPLACE FUNCTION SCHEMA_NAME.copy_item(source_id BIGINT, new_parent BIGINT, new_repository BIGINT, new_name VARCHAR, new_owner BIGINT) RETURNS BIGINT AS $$
+	DECLARE
+		sources BIGINT[];
+		copies BIGINT[];
+	BEGIN
+		IF NOT EXISTS (SELECT 1 FROM SCHEMA_NAME.items WHERE id = source_id AND NOT in_trash) THEN
+			RAISE EXCEPTION 'Cannot copy item % : it does not exist or is in the trash', source_id;
+		END IF;
+
+		-- The id of each copy is reserved first, so that the copies of the children can reference the copy of their parent
+		WITH RECURSIVE subtree AS (
+			SELECT id, 0 AS depth FROM SCHEMA_NAME.items WHERE id = source_id
+			UNION ALL
+			SELECT items.id, subtree.depth + 1 FROM SCHEMA_NAME.items JOIN subtree ON items.parent_item = subtree.id WHERE NOT items.in_trash
+		), mapping AS (
+			SELECT id, depth, nextval(pg_get_serial_sequence('SCHEMA_NAME.items', 'id')) AS copy FROM subtree
+		)
+		SELECT ARRAY_AGG(id ORDER BY depth, id), ARRAY_AGG(copy ORDER BY depth, id) INTO sources, copies FROM mapping;
+
+		-- Parents are inserted before their children, which need their path
+		INSERT INTO SCHEMA_NAME.items (id, repository, owner, name, is_regular_file, description, parent_item, in_trash)
+			SELECT copy.id, new_repository, new_owner, CASE WHEN source.id = source_id THEN new_name ELSE source.name END,
+				source.is_regular_file, source.description, CASE WHEN source.id = source_id THEN new_parent ELSE parent.id END, FALSE
+			FROM UNNEST(sources, copies) WITH ORDINALITY AS copy(source, id, position)
+			JOIN SCHEMA_NAME.items AS source ON source.id = copy.source
+			LEFT JOIN UNNEST(sources, copies) AS parent(source, id) ON parent.source = source.parent_item
+			ORDER BY copy.position;
+
+		-- Copies share the objects of the source files. Directories are created before the files that are counted in them.
+		INSERT INTO SCHEMA_NAME.directories (id, open_upload)
+			SELECT copy.id, directories.open_upload FROM UNNEST(sources, copies) AS copy(source, id)
+			JOIN SCHEMA_NAME.directories ON directories.id = copy.source;
+		INSERT INTO SCHEMA_NAME.files (id, size, mimetype, timestamp, object)
+			SELECT copy.id, files.size, files.mimetype, files.timestamp, files.object FROM UNNEST(sources, copies) AS copy(source, id)
+			JOIN SCHEMA_NAME.files ON files.id = copy.source;
+		INSERT INTO SCHEMA_NAME.item_search (id, plain_name, plain_description, content)
+			SELECT copy.id, item_search.plain_name, item_search.plain_description, item_search.content FROM UNNEST(sources, copies) AS copy(source, id)
+			JOIN SCHEMA_NAME.item_search ON item_search.id = copy.source;
+		INSERT INTO SCHEMA_NAME.item_metadata (id, extracted_at, capture_date, camera_make, camera_model, lens, latitude, longitude,
+				width, height, orientation, duration, video_codec, audio_codec, artist, album, title, track)
+			SELECT copy.id, extracted_at, capture_date, camera_make, camera_model, lens, latitude, longitude,
+				width, height, orientation, duration, video_codec, audio_codec, artist, album, title, track
+			FROM UNNEST(sources, copies) AS copy(source, id)
+			JOIN SCHEMA_NAME.item_metadata ON item_metadata.id = copy.source;
+
+		RETURN copies[1];
+	END;
+$$ LANGUAGE plpgsql;
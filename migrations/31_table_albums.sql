@@ -0,0 +1,27 @@
+-- Albums are virtual collections of files of a repository, independent of the directory structure
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.albums (
+        id BIGSERIAL PRIMARY KEY,
+        repository BIGINT NOT NULL,
+        owner BIGINT NOT NULL,
+        name VARCHAR NOT NULL,
+        description TEXT NULL,
+        cover BIGINT NULL,
+        created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT,
+        FOREIGN KEY(repository) REFERENCES SCHEMA_NAME.repository(id) ON DELETE CASCADE,
+        FOREIGN KEY(owner) REFERENCES SCHEMA_NAME.users(id) ON DELETE CASCADE,
+        FOREIGN KEY(cover) REFERENCES SCHEMA_NAME.items(id) ON DELETE SET NULL
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_albums_repository_index ON SCHEMA_NAME.albums USING hash(repository);
+
+CREATE TABLE IF NOT EXISTS SCHEMA_NAME.album_items (
+        album BIGINT NOT NULL,
+        item BIGINT NOT NULL,
+        position INTEGER NOT NULL,
+        PRIMARY KEY(album, item),
+        FOREIGN KEY(album) REFERENCES SCHEMA_NAME.albums(id) ON DELETE CASCADE,
+        FOREIGN KEY(item) REFERENCES SCHEMA_NAME.items(id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS SCHEMA_NAME_album_items_item_index ON SCHEMA_NAME.album_items USING hash(item);
+
@@ -0,0 +1 @@
+ALTER TYPE SCHEMA_NAME.job_kind ADD VALUE IF NOT EXISTS 'rendition';
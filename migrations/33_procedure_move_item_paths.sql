@@ -0,0 +1,24 @@
+-- Changing the parent or the name already regenerates the paths of the subtree (make_item_path_up_to_date) : only
+-- regenerate them here when an item is moved to the root of another repository under the same name
+CREATE OR REPLACE PROCEDURE SCHEMA_NAME.move_item(item_id BIGINT, new_parent BIGINT, new_repository BIGINT, new_name VARCHAR) AS $$
+	DECLARE
+		old_path VARCHAR;
+		old_repository BIGINT;
+		old_parent BIGINT;
+		old_name VARCHAR;
+	BEGIN
+		SELECT absolute_path, repository, parent_item, name INTO old_path, old_repository, old_parent, old_name FROM SCHEMA_NAME.items WHERE id = item_id;
+
+		IF old_repository != new_repository THEN
+		    -- Paths are cleared first so that they don't collide with the content of any repository while moving
+			UPDATE SCHEMA_NAME.items SET absolute_path = NULL, repository = new_repository
+				WHERE repository = old_repository AND (id = item_id OR STARTS_WITH(absolute_path, old_path || '/'));
+		END IF;
+
+		UPDATE SCHEMA_NAME.items SET parent_item = new_parent, name = new_name WHERE id = item_id;
+
+		IF old_repository != new_repository AND old_parent IS NOT DISTINCT FROM new_parent AND old_name = new_name THEN
+			CALL SCHEMA_NAME.regenerate_item_path_with_children(item_id);
+		END IF;
+	END;
+	$$ LANGUAGE plpgsql;
@@ -1,3 +1,5 @@
+import {retry_generated_image} from "../../../../utilities/generated_image";
+
 class LazyImage extends HTMLElement {
     constructor() {
         super();
@@ -23,6 +25,9 @@ class LazyImage extends HTMLElement {
             this.innerHTML = '';
             this.append(image);
         }
+        // Images generated in background are not available on the first request
+        if (this.hasAttribute('retry'))
+            image.onerror = retry_generated_image(image, this.getAttribute('src'), () => this.isConnected);
     }
 }
 
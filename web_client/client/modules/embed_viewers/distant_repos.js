@@ -1,12 +1,20 @@
 import {get_mime_icon_path} from "../../utilities/mime_utils";
 import {APP_CONFIG} from "../../types/app_config";
 
+/**
+ * Raw camera files are displayed through a jpeg rendition generated by the server
+ */
+const RAW_MIMETYPES = new Set(['x-canon-cr2', 'x-canon-cr3', 'x-canon-crw', 'x-nikon-nef', 'x-nikon-nrw', 'x-sony-arw', 'x-fuji-raf',
+    'x-adobe-dng', 'x-olympus-orf', 'x-panasonic-rw2', 'x-pentax-pef']);
+
 function get(item) {
     const url = `${APP_CONFIG.origin()}/api/item/preview/${item.id}/`;
-    const thumbnail_url = `/api/item/thumbnail/${item.id}/`;
+    const thumbnail_url = `/api/item/thumbnail/${item.id}/?size=1024`;
     const mimetype = item.mimetype.split('/');
     switch (mimetype[0]) {
         case 'image':
+            if (RAW_MIMETYPES.has(mimetype[1]))
+                return `<lazy-img class="item-large" src="/api/item/rendition/${item.id}/" alternate-src="${thumbnail_url}" retry="true"/>`
             return `<lazy-img class="item-large" src="${url}" alternate-src="${thumbnail_url}""/>`
         case 'video':
             return `<video class="item-large video-js" preload="auto" data-setup="{}" autoplay="true" preload="auto" controls="true" height="100%" width="100%">
@@ -27,6 +27,8 @@ function edit_repository(repository) {
                 max_file_size: Number(document.getElementById('max_file_size').value),
                 visitor_file_lifetime: Number(document.getElementById('visitor_file_lifetime').value),
                 allow_visitor_upload: document.getElementById('allow_visitor_upload').checked,
+                max_file_versions: Number(document.getElementById('max_file_versions').value) || 0,
+                hls_streaming: document.getElementById('hls_streaming').checked,
                 status: document.getElementById('status').value,
                 description: EncString.from_client(description.length === 0 ? null : description)
             };
@@ -40,6 +42,8 @@ function edit_repository(repository) {
                 repository.max_file_size = new_data.max_file_size;
                 repository.visitor_file_lifetime = new_data.visitor_file_lifetime;
                 repository.allow_visitor_upload = new_data.allow_visitor_upload;
+                repository.max_file_versions = new_data.max_file_versions || null;
+                repository.hls_streaming = new_data.hls_streaming;
                 repository.status = new_data.status;
                 repository.refresh();
             }
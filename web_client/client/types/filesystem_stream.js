@@ -1,6 +1,6 @@
 const {EncString} = require("./encstring");
 const {User} = require("./user");
-const {fetch_api} = require("../utilities/request");
+const {fetch_api, fetch_all_pages} = require("../utilities/request");
 const {GLOBAL_EVENTS} = require("./event_manager");
 const {NOTIFICATION, Message} = require("../modules/index/tools/message_box/notification");
 
@@ -80,6 +80,12 @@ class FilesystemItem {
              * @type number
              */
             this.content_size = data.size;
+
+            /**
+             * Exif of photos, duration and tags of videos and audio files
+             * @type {Object|null}
+             */
+            this.metadata = data.metadata || null;
         } else {
             /**
              * @type boolean
@@ -257,7 +263,7 @@ class FilesystemStream {
             return existing.children;
         }
         existing.children = new Set();
-        for (const item of await fetch_api(`item/directory-content/`, 'POST', [item_id])
+        for (const item of await fetch_all_pages(`item/directory-content/`, 'POST', [item_id])
             .catch(error => {
                 NOTIFICATION.warn(new Message(error).title(`Impossible de lire le contenu de l'objet ${item_id}`))
                 return [];
@@ -273,7 +279,7 @@ class FilesystemStream {
     async root_content() {
         if (!this._roots) {
             this._roots = new Set();
-            for (const item of await fetch_api(`repository/root-content/`, 'POST', [this._repository.id])
+            for (const item of await fetch_all_pages(`repository/root-content/`, 'POST', [this._repository.id])
                 .catch(error => {
                     NOTIFICATION.warn(new Message(error).title(`Impossible de lire la racion du dépot ${this._repository.url_name.plain()}`));
                     return [];
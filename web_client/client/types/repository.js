@@ -71,6 +71,14 @@ class Repository {
          * @type {number}
          */
         this.allow_visitor_upload = data.allow_visitor_upload;
+        /**
+         * @type {number|null}
+         */
+        this.max_file_versions = data.max_file_versions;
+        /**
+         * @type {boolean}
+         */
+        this.hls_streaming = data.hls_streaming;
 
         /**
          * @type {FilesystemStream}
@@ -0,0 +1,29 @@
+/**
+ * Images generated in background by the server (thumbnails, renditions...) are not available on the first request : they
+ * are requested again a few times before giving up.
+ */
+const MAX_ATTEMPTS = 10;
+const RETRY_DELAY_MS = 2000;
+
+/**
+ * Build the error handler of an image generated by the server
+ * @param image {HTMLImageElement}
+ * @param src {string} url of the generated image
+ * @param is_needed {function(): boolean} retries stop once the image is not needed anymore
+ * @param on_give_up {function(): void} called when the image is still not available after the last attempt
+ * @return {function(): void}
+ */
+function retry_generated_image(image, src, is_needed, on_give_up = () => {}) {
+    let attempts = 0;
+    return () => {
+        if (!is_needed())
+            return;
+        if (++attempts > MAX_ATTEMPTS)
+            return on_give_up();
+        const url = new URL(src, window.location.href);
+        url.searchParams.set('attempt', attempts.toString());
+        setTimeout(() => image.src = url.toString(), RETRY_DELAY_MS);
+    }
+}
+
+export {retry_generated_image}
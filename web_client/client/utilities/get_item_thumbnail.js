@@ -1,4 +1,18 @@
 import {get_mime_icon_path} from "./mime_utils";
+import {retry_generated_image} from "./generated_image";
+
+// Thumbnails are generated in background : the first error of a thumbnail starts its retries, then its fallback is displayed
+document.addEventListener('error', event => {
+    const image = event.target;
+    if (!(image instanceof HTMLImageElement) || !image.dataset.thumbnail || image.dataset.retrying)
+        return;
+    image.dataset.retrying = 'true';
+    image.onerror = retry_generated_image(image, image.dataset.thumbnail, () => image.isConnected, () => {
+        image.onerror = null;
+        image.src = image.dataset.fallback;
+    });
+    image.onerror();
+}, true);
 
 function from_distant_repos(item) {
     const thumbnail_url = `/api/item/thumbnail/${item.id}/`;
@@ -6,16 +20,16 @@ function from_distant_repos(item) {
     switch (mime[0]) {
         case 'video':
             return `<div class="item-small">
-                            <img class="item-background" src="${thumbnail_url}" alt="fichier: '${item.name}" onError="this.onError = undefined; src='/public/images/icons/mime-icons/video.png'"/>
+                            <img class="item-background" src="${thumbnail_url}" alt="fichier: '${item.name}" data-thumbnail="${thumbnail_url}" data-fallback="/public/images/icons/mime-icons/video.png"/>
                             <img class="item-overlay" src="/public/images/icons/icons8-play-64.png" alt="play button">
                         </div>`
         case 'image':
-            return `<img class="item-small" src="${thumbnail_url}" alt="fichier: ${item.name}" onError="this.onError = null; this.src='/public/images/icons/mime-icons/image.png'"/>`
+            return `<img class="item-small" src="${thumbnail_url}" alt="fichier: ${item.name}" data-thumbnail="${thumbnail_url}" data-fallback="/public/images/icons/mime-icons/image.png"/>`
         case 'application':
             switch (mime[1]) {
                 case 'x-pdf':
                 case 'pdf':
-                    return `<img class="item-small" src="${thumbnail_url}" alt="fichier: ${item.name}" onError="this.onError = null; this.src='/public/images/icons/mime-icons/image.png'"/>`
+                    return `<img class="item-small" src="${thumbnail_url}" alt="fichier: ${item.name}" data-thumbnail="${thumbnail_url}" data-fallback="/public/images/icons/mime-icons/image.png"/>`
             }
             break;
     }
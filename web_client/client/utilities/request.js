@@ -42,4 +42,22 @@ async function fetch_api(path, method = 'GET', body = null, custom_token = null)
     throw {message: `${await result.text()}`, code: result.status}
 }
 
-export {fetch_api}
\ No newline at end of file
+/**
+ * Fetch every page of a paginated item listing
+ * @param path {string}
+ * @param method {string}
+ * @param body
+ * @returns {Promise<object[]>}
+ */
+async function fetch_all_pages(path, method = 'GET', body = null) {
+    let items = [];
+    let cursor = null;
+    do {
+        const page = await fetch_api(cursor ? `${path}?cursor=${encodeURIComponent(cursor)}` : path, method, body);
+        items = items.concat(page.items);
+        cursor = page.next_cursor;
+    } while (cursor);
+    return items;
+}
+
+export {fetch_api, fetch_all_pages}
\ No newline at end of file